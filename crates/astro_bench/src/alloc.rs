@@ -0,0 +1,57 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use std::{alloc::{GlobalAlloc, Layout, System}, sync::atomic::{AtomicU64, Ordering}};
+
+/// System allocator counting every allocation made through it.
+pub struct CountingAllocator {
+    count: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl CountingAllocator {
+
+    pub const fn new() -> Self {
+        Self{ count: AtomicU64::new(0), bytes: AtomicU64::new(0) }
+    }
+
+    /// Allocations and bytes allocated so far.
+    pub fn snapshot(&self) -> AllocationCount {
+        AllocationCount{ count: self.count.load(Ordering::Relaxed), bytes: self.bytes.load(Ordering::Relaxed) }
+    }
+
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    /// Growing counts as an allocation of the new size.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(new_size as u64, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocationCount {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+impl std::ops::Sub for AllocationCount {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self{ count: self.count - rhs.count, bytes: self.bytes - rhs.bytes }
+    }
+}
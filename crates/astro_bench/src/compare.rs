@@ -0,0 +1,67 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use serde_json::Value;
+
+/// Change in one metric from a baseline run, where lower is better.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delta {
+    pub name:     String,
+    pub baseline: f64,
+    pub current:  f64,
+    /// Whether growth past the threshold counts as a regression.
+    pub gated:    bool,
+}
+
+impl Delta {
+
+    pub fn percent(&self) -> f64 {
+        match (self.baseline, self.current) {
+            (b, c) if b == c   => 0.0,
+            (b, c) if b == 0.0 => f64::INFINITY.copysign(c),
+            (b, c)             => (c - b) / b * 100.0,
+        }
+    }
+
+    pub fn regressed(&self, threshold: f64) -> bool {
+        self.gated && self.percent() > threshold
+    }
+
+}
+
+impl std::fmt::Display for Delta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:<24} {:>14.1} -> {:>14.1} {:>+8.1}%", self.name, self.baseline, self.current, self.percent())
+    }
+}
+
+/// Metrics compared, by path, and whether they gate. Tick times other than
+/// the median and stage and system times are too noisy to fail a run on.
+const METRICS: [(&str, &[&str], bool); 5] = [
+    ("tick min ns",          &["tick_ns", "min"],          false),
+    ("tick median ns",       &["tick_ns", "median"],       true ),
+    ("tick p99 ns",          &["tick_ns", "p99"],          false),
+    ("ns per entity",        &["ns_per_entity"],           false),
+    ("allocations per tick", &["allocations", "per_tick"], true ),
+];
+
+/// Metrics found in both results, which must be from the same scenario.
+pub fn compare(baseline: &Value, current: &Value) -> Result<Vec<Delta>, String> {
+    if baseline.get("scenario") != current.get("scenario") {
+        return Err("Baseline was run with a different scenario".to_string());
+    }
+
+    let lookup = |json: &Value, path: &[&str]| path.iter().try_fold(json, |v, key| v.get(key)).and_then(Value::as_f64);
+    let delta  = |name: String, path: &[&str], gated: bool| Some(Delta{ name, baseline: lookup(baseline, path)?, current: lookup(current, path)?, gated });
+
+    let mut deltas = METRICS.iter().filter_map(|&(name, path, gated)| delta(name.to_string(), path, gated)).collect::<Vec<_>>();
+    for (group, prefix) in [("stages_ns", "stage"), ("systems_ns", "system")] {
+        if let Some(Value::Object(timings)) = current.get(group) {
+            deltas.extend(timings.iter().filter_map(|(name, _)| delta(format!("{} {} ns", prefix, name), &[group, name.as_str()], false)));
+        }
+    }
+    Ok(deltas)
+}
@@ -0,0 +1,33 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use serde_json::json;
+
+use super::*;
+
+#[test]
+fn baseline_deltas() {
+    let baseline = json!({"scenario":{"size":10},"tick_ns":{"median":100},"allocations":{"per_tick":0},"stages_ns":{"tick":5}});
+    let current  = json!({"scenario":{"size":10},"tick_ns":{"median":104,"p99":900},"allocations":{"per_tick":0},"stages_ns":{"tick":10,"machine":3}});
+
+    let deltas = compare(&baseline, &current).unwrap();
+    assert_eq!(deltas.iter().map(|v| (v.name.as_str(), v.percent().round() as i32)).collect::<Vec<_>>(), vec![("tick median ns", 4), ("allocations per tick", 0), ("stage tick ns", 100)]);
+    assert!(!deltas.iter().any(|v| v.regressed(5.0)));
+    assert!(deltas[0].regressed(3.0));
+
+    let other = json!({"scenario":{"size":20}});
+    assert!(compare(&baseline, &other).is_err());
+}
+
+#[test]
+fn reads_written_results() {
+    let text = r#"{"scenario":{"topology":"a\bb\fc \ud83d\ude00"},"stages_ns":{"tick":5,"machine":3}}"#;
+    let json = serde_json::from_str::<Value>(text).unwrap();
+    assert_eq!(json.pointer("/scenario/topology").and_then(Value::as_str), Some("a\u{8}b\u{c}c 😀"));
+    assert_eq!(serde_json::from_str::<Value>(&json.to_string()).unwrap(), json);
+
+    // Timings keep the order they were written in.
+    let stages = json.get("stages_ns").and_then(Value::as_object).unwrap();
+    assert_eq!(stages.keys().collect::<Vec<_>>(), vec!["tick", "machine"]);
+}
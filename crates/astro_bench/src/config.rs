@@ -0,0 +1,101 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use crate::scenario::{Load, PipeBackend, Scenario, Topology};
+
+pub const USAGE: &str = "\
+usage: astro_bench [--topology <chain|grid|fanout|belt>] [--size <n>] [--length <tiles>]
+                   [--load <saturated|sparse>] [--backend <simple|pooled|gap>]
+                   [--samples <ticks>] [--warmup <ticks>] [--print] [--compare-backends]
+                   [--json <file|->] [--baseline <file>] [--threshold <percent>]
+
+Compares against a baseline written with --json, exiting with 2 when the median
+tick time or allocations per tick grew by more than the threshold (default 5%).
+--compare-backends runs the scenario with every pipe backend and reports each
+one's ns per op against simple pipes. Stage and system times are only reported
+when built with the profile feature, which adds its own overhead to each tick.";
+
+pub struct BenchConfig {
+    pub scenario:  Scenario,
+    /// Ticks timed.
+    pub samples:   u32,
+    /// Ticks run before timing starts, so pipes have filled.
+    pub warmup:    u32,
+    /// Draws every machine and pipe each tick, only sensible for small scenarios.
+    pub print:     bool,
+    /// Runs the scenario once per pipe backend.
+    pub backends:  bool,
+    /// File to write results to as JSON, `-` for stdout.
+    pub json:      Option<String>,
+    /// Results to compare against.
+    pub baseline:  Option<String>,
+    /// Percentage growth treated as a regression.
+    pub threshold: f64,
+}
+
+impl BenchConfig {
+
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut topology  = Topology::Chain;
+        let mut size      = None;
+        let mut length    = None;
+        let mut load      = Load::Saturated;
+        let mut backend   = PipeBackend::Simple;
+        let mut samples   = 1000;
+        let mut warmup    = 100;
+        let mut print     = false;
+        let mut backends  = false;
+        let mut json      = None;
+        let mut baseline  = None;
+        let mut threshold = 5.0;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--topology"         => topology  = parse_name(&arg, args.next(), &Topology::ALL, Topology::name)?,
+                "--size"             => size      = Some(parse_value(&arg, args.next())?),
+                "--length"           => length    = Some(parse_value(&arg, args.next())?),
+                "--load"             => load      = parse_name(&arg, args.next(), &Load::ALL, Load::name)?,
+                "--backend"          => backend   = parse_name(&arg, args.next(), &PipeBackend::ALL, PipeBackend::name)?,
+                "--samples"          => samples   = parse_value(&arg, args.next())?,
+                "--warmup"           => warmup    = parse_value(&arg, args.next())?,
+                "--print"            => print     = true,
+                "--compare-backends" => backends  = true,
+                "--json"             => json      = Some(args.next().ok_or_else(|| format!("Missing value for {}", arg))?),
+                "--baseline"         => baseline  = Some(args.next().ok_or_else(|| format!("Missing value for {}", arg))?),
+                "--threshold"        => threshold = parse_value(&arg, args.next())?,
+                _ => return Err(format!("Unknown option {}", arg)),
+            }
+        }
+
+        let size   = size.unwrap_or_else(|| topology.default_size());
+        let length = length.unwrap_or_else(|| topology.default_length());
+        if size    == 0 { return Err("--size must be greater than 0".to_string()); }
+        if length  == 0 { return Err("--length must be greater than 0".to_string()); }
+        if samples == 0 { return Err("--samples must be greater than 0".to_string()); }
+
+        Ok(Self{
+            scenario: Scenario{ topology, size, length, load, backend },
+            samples,
+            warmup,
+            print,
+            backends,
+            json,
+            baseline,
+            threshold,
+        })
+    }
+
+}
+
+fn parse_value<T: std::str::FromStr>(arg: &str, value: Option<String>) -> Result<T, String> {
+    value
+        .ok_or_else(|| format!("Missing value for {}", arg))?
+        .parse()
+        .map_err(|_| format!("Invalid value for {}", arg))
+}
+
+fn parse_name<T: Copy>(arg: &str, value: Option<String>, all: &[T], name: fn(&T) -> &'static str) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("Missing value for {}", arg))?;
+    all.iter().copied().find(|v| name(v) == value).ok_or_else(|| format!("Invalid value for {}", arg))
+}
@@ -1,6 +1,64 @@
-
+mod alloc;
+mod compare;
+mod config;
 mod factory;
+mod report;
+#[cfg(feature = "profile")] mod profile;
+mod scenario;
+
+use alloc::CountingAllocator;
+use config::BenchConfig;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator::new();
 
 fn main() {
-    factory::factory_bench();
-}
\ No newline at end of file
+    let config = match BenchConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", config::USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    if config.backends {
+        println!("{}", report::BackendComparison(factory::backend_bench(&config)));
+        return;
+    }
+
+    let report = factory::factory_bench(&config);
+    let result = report.to_json();
+    match config.json.as_deref() {
+        Some("-") => println!("{}", result),
+        Some(path) => {
+            println!("{}", report);
+            if let Err(e) = std::fs::write(path, result.to_string()) {
+                eprintln!("Failed to write {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => println!("{}", report),
+    }
+
+    if let Some(path) = config.baseline.as_deref() {
+        let deltas = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path, e))
+            .and_then(|v| serde_json::from_str(&v).map_err(|e| format!("Invalid baseline {}: {}", path, e)))
+            .and_then(|baseline| compare::compare(&baseline, &result));
+        let deltas = match deltas {
+            Ok(deltas) => deltas,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+
+        // Keep stdout to the results when they're written there.
+        eprintln!("compared to {} (threshold {:.1}%)", path, config.threshold);
+        for delta in deltas.iter() {
+            eprintln!("  {}{}", delta, if delta.regressed(config.threshold) { "  REGRESSED" } else { "" });
+        }
+        if deltas.iter().any(|v| v.regressed(config.threshold)) { std::process::exit(2); }
+    }
+}
@@ -0,0 +1,50 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use std::time::Duration;
+
+use astro::factory::{FactoryProfile, PROFILED_STAGES, PROFILED_SYSTEMS};
+
+/// The `FactoryProfile` timings of every timed tick.
+pub struct ProfileSamples {
+    stages:  Vec<Vec<Duration>>,
+    systems: Vec<Vec<Duration>>,
+}
+
+impl ProfileSamples {
+
+    /// Reserves room for `ticks` samples, so recording doesn't allocate.
+    pub fn with_capacity(ticks: usize) -> Self {
+        Self{
+            stages:  (0..PROFILED_STAGES.len()).map(|_| Vec::with_capacity(ticks)).collect(),
+            systems: (0..PROFILED_SYSTEMS.len()).map(|_| Vec::with_capacity(ticks)).collect(),
+        }
+    }
+
+    pub fn record(&mut self, profile: &FactoryProfile) {
+        self.stages.iter_mut().zip(profile.stages).for_each(|(samples, v)| samples.push(v));
+        self.systems.iter_mut().zip(profile.systems).for_each(|(samples, v)| samples.push(v));
+    }
+
+    /// Median time of each stage.
+    pub fn stages(&mut self) -> Vec<(&'static str, Duration)> {
+        medians(PROFILED_STAGES.iter().copied(), &mut self.stages)
+    }
+
+    /// Median time of each labelled system.
+    pub fn systems(&mut self) -> Vec<(&'static str, Duration)> {
+        medians(PROFILED_SYSTEMS.iter().map(|&(name, _)| name), &mut self.systems)
+    }
+
+}
+
+fn medians(names: impl Iterator<Item = &'static str>, samples: &mut [Vec<Duration>]) -> Vec<(&'static str, Duration)> {
+    names.zip(samples.iter_mut())
+        .filter(|(_, samples)| !samples.is_empty())
+        .map(|(name, samples)| {
+            samples.sort_unstable();
+            (name, samples[(samples.len() - 1) / 2])
+        })
+        .collect()
+}
@@ -0,0 +1,115 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use std::time::Duration;
+
+use serde_json::{Map, Value, json};
+
+use crate::{alloc::AllocationCount, scenario::{PipeBackend, Scenario}};
+
+/// Tick times of a benchmark run.
+pub struct BenchReport {
+    pub scenario:    Scenario,
+    /// Machines and connections simulated each tick.
+    pub entities:    u32,
+    pub samples:     usize,
+    pub min:         Duration,
+    pub median:      Duration,
+    pub p99:         Duration,
+    /// Median time of each factory stage, empty unless profiled.
+    pub stages:      Vec<(&'static str, Duration)>,
+    /// Median time of each labelled factory system, empty unless profiled.
+    pub systems:     Vec<(&'static str, Duration)>,
+    /// Allocations over all timed ticks.
+    pub allocations: AllocationCount,
+}
+
+impl BenchReport {
+
+    pub fn new(scenario: Scenario, entities: u32, mut samples: Vec<Duration>) -> Self {
+        samples.sort_unstable();
+        let percentile = |p: usize| samples[((samples.len() * p + 99) / 100).clamp(1, samples.len()) - 1];
+        Self{
+            scenario,
+            entities,
+            samples:     samples.len(),
+            min:         samples[0],
+            median:      percentile(50),
+            p99:         percentile(99),
+            stages:      Vec::new(),
+            systems:     Vec::new(),
+            allocations: AllocationCount::default(),
+        }
+    }
+
+    /// Median nanoseconds spent per entity each tick.
+    pub fn ns_per_entity(&self) -> f64 {
+        self.median.as_nanos() as f64 / self.entities.max(1) as f64
+    }
+
+    /// Entities simulated per second at the median tick time.
+    pub fn throughput(&self) -> f64 {
+        self.entities as f64 / self.median.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    pub fn allocations_per_tick(&self) -> f64 {
+        self.allocations.count as f64 / self.samples as f64
+    }
+
+    pub fn to_json(&self) -> Value {
+        let timings = |v: &[(&str, Duration)]| v.iter().map(|&(name, v)| (name.to_string(), json!(v.as_nanos() as f64))).collect::<Map<_, _>>();
+        json!({
+            "scenario": {
+                "topology": self.scenario.topology.name(),
+                "size":     self.scenario.size,
+                "length":   self.scenario.length,
+                "load":     self.scenario.load.name(),
+                "backend":  self.scenario.backend.name(),
+            },
+            "entities":      self.entities,
+            "samples":       self.samples,
+            "tick_ns":       { "min": self.min.as_nanos() as f64, "median": self.median.as_nanos() as f64, "p99": self.p99.as_nanos() as f64 },
+            "ns_per_entity": self.ns_per_entity(),
+            "throughput":    self.throughput(),
+            "stages_ns":     timings(&self.stages),
+            "systems_ns":    timings(&self.systems),
+            "allocations":   {
+                "count":    self.allocations.count,
+                "bytes":    self.allocations.bytes,
+                "per_tick": self.allocations_per_tick(),
+            },
+        })
+    }
+
+}
+
+impl std::fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}: {} entities, {} ticks", self.scenario, self.entities, self.samples)?;
+        writeln!(f, "  tick   min {:>10.3?}  median {:>10.3?}  p99 {:>10.3?}", self.min, self.median, self.p99)?;
+        for (name, median) in self.stages.iter() {
+            writeln!(f, "  stage  {:<20} {:>10.3?}", name, median)?;
+        }
+        for (name, median) in self.systems.iter() {
+            writeln!(f, "  system {:<20} {:>10.3?}", name, median)?;
+        }
+        writeln!(f, "  alloc  {:.1} per tick, {} bytes total", self.allocations_per_tick(), self.allocations.bytes)?;
+        write!(f,   "  entity {:.2}ns per tick (median tick / entities), {:.1}M per second", self.ns_per_entity(), self.throughput() / 1e6)
+    }
+}
+
+/// The same scenario run with each pipe backend.
+pub struct BackendComparison(pub Vec<BenchReport>);
+
+impl std::fmt::Display for BackendComparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let simple = self.0.iter().find(|v| v.scenario.backend == PipeBackend::Simple).map(BenchReport::ns_per_entity);
+        write!(f, "ns per op, an op being one entity for one tick, taken from the median tick after warmup rather than the mean of every tick")?;
+        for report in self.0.iter() {
+            write!(f, "\n  {:<8} {:>8.2}ns per op", report.scenario.backend.name(), report.ns_per_entity())?;
+            if let Some(simple) = simple { write!(f, "  {:>6.2}x simple", report.ns_per_entity() / simple)?; }
+        }
+        Ok(())
+    }
+}
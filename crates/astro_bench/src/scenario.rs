@@ -0,0 +1,221 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::prelude::{Entity, World};
+
+use astro::factory::{ConnectionBuilder, PipeGap, PipeSimple, PortID};
+
+use crate::factory::{PassthroughMachineBundle, UnlimitedSourceBundle, RESOURCE_SPEED};
+
+/// Depth of the passthrough trees built by `Topology::FanOut`.
+pub const FANOUT_DEPTH: u32 = 4;
+
+/// Ticks between packets from each source under `Load::Sparse`.
+pub const SPARSE_PERIOD: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    /// Independent producer → passthrough → consumer chains.
+    Chain,
+    /// A square of passthroughs each feeding the one right of and below it.
+    Grid,
+    /// Binary trees of passthroughs splitting a single source.
+    FanOut,
+    /// Single long pipes from a producer to a consumer.
+    Belt,
+}
+
+/// How often sources produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Load {
+    Saturated,
+    Sparse,
+}
+
+/// Storage the benchmarked connections are built with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipeBackend {
+    Simple,
+    Pooled,
+    Gap,
+}
+
+impl Topology {
+    pub const ALL: [Self; 4] = [Self::Chain, Self::Grid, Self::FanOut, Self::Belt];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Chain  => "chain",
+            Self::Grid   => "grid",
+            Self::FanOut => "fanout",
+            Self::Belt   => "belt",
+        }
+    }
+
+    /// Chains, grid width, trees or belts built when no size is given. Chains
+    /// keep the million the bench has always run, so results stay comparable.
+    pub fn default_size(&self) -> u32 {
+        match self {
+            Self::Chain  => 1_000_000,
+            Self::Grid   => 300,
+            Self::FanOut => 10_000,
+            Self::Belt   => 100_000,
+        }
+    }
+
+    pub fn default_length(&self) -> u32 {
+        match self {
+            Self::Belt => 1000,
+            _          => 16,
+        }
+    }
+}
+
+impl Load {
+    pub const ALL: [Self; 2] = [Self::Saturated, Self::Sparse];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Saturated => "saturated",
+            Self::Sparse    => "sparse",
+        }
+    }
+}
+
+impl PipeBackend {
+    pub const ALL: [Self; 3] = [Self::Simple, Self::Pooled, Self::Gap];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Simple => "simple",
+            Self::Pooled => "pooled",
+            Self::Gap    => "gap",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scenario {
+    pub topology: Topology,
+    pub size:     u32,
+    /// Length of every pipe.
+    pub length:   u32,
+    pub load:     Load,
+    pub backend:  PipeBackend,
+}
+
+impl std::fmt::Display for Scenario {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} x{} len {} {} {}", self.topology.name(), self.size, self.length, self.load.name(), self.backend.name())
+    }
+}
+
+impl Scenario {
+
+    /// Spawns the scenario, returning the number of machines and connections.
+    pub fn build(&self, world: &mut World) -> u32 {
+        let mut builder = ScenarioBuilder{ world, scenario: *self, entities: 0 };
+        match self.topology {
+            Topology::Chain  => (0..self.size).for_each(|_| builder.chain()),
+            Topology::Grid   => builder.grid(),
+            Topology::FanOut => (0..self.size).for_each(|_| builder.tree()),
+            Topology::Belt   => (0..self.size).for_each(|_| builder.belt()),
+        }
+        builder.entities
+    }
+
+}
+
+struct ScenarioBuilder<'w> {
+    world:    &'w mut World,
+    scenario: Scenario,
+    entities: u32,
+}
+
+impl ScenarioBuilder<'_> {
+
+    fn chain(&mut self) {
+        let producer    = self.source();
+        let consumer    = self.source();
+        let passthrough = self.passthrough();
+        self.connect(producer, passthrough);
+        self.connect(passthrough, consumer);
+    }
+
+    fn grid(&mut self) {
+        let size  = self.scenario.size as usize;
+        let cells = (0..size*size).map(|_| self.passthrough()).collect::<Vec<_>>();
+        for i in 0..size {
+            let (row, column) = (cells[i*size], cells[i]);
+            let source = self.source();
+            self.connect(source, row);
+            let source = self.source();
+            self.connect(source, column);
+        }
+
+        for y in 0..size {
+            for x in 0..size {
+                let cell  = cells[y*size + x];
+                let right = if x + 1 < size { cells[y*size + x + 1]   } else { self.source() };
+                let below = if y + 1 < size { cells[(y + 1)*size + x] } else { self.source() };
+                self.connect(cell, right);
+                self.connect(cell, below);
+            }
+        }
+    }
+
+    fn tree(&mut self) {
+        let source = self.source();
+        let root   = self.passthrough();
+        self.connect(source, root);
+
+        let mut level = vec![root];
+        for _ in 0..FANOUT_DEPTH {
+            level = level.into_iter().flat_map(|parent| [parent, parent]).collect();
+            for parent in level.iter_mut() {
+                let child = self.passthrough();
+                self.connect(*parent, child);
+                *parent = child;
+            }
+        }
+
+        for leaf in level {
+            let consumer = self.source();
+            self.connect(leaf, consumer);
+        }
+    }
+
+    fn belt(&mut self) {
+        let producer = self.source();
+        let consumer = self.source();
+        self.connect(producer, consumer);
+    }
+
+    /// Sources also discard what they're sent, so they double as consumers.
+    fn source(&mut self) -> Entity {
+        let period = match self.scenario.load { Load::Saturated => 1, Load::Sparse => SPARSE_PERIOD };
+        self.entities += 1;
+        self.world.spawn().insert_bundle(UnlimitedSourceBundle::new(RESOURCE_SPEED.id(), period)).id()
+    }
+
+    fn passthrough(&mut self) -> Entity {
+        self.entities += 1;
+        self.world.spawn().insert_bundle(PassthroughMachineBundle::default()).id()
+    }
+
+    /// Grid cells are fed from two sides, so sends aren't exclusive.
+    fn connect(&mut self, from: Entity, to: Entity) -> Entity {
+        let builder = ConnectionBuilder::new(self.scenario.length)
+            .send_to(to, PortID::A)
+            .recv_from(from, PortID::B)
+            .exclusive(false);
+        self.entities += 1;
+        match self.scenario.backend {
+            PipeBackend::Simple => builder.build::<PipeSimple>(self.world),
+            PipeBackend::Pooled => builder.build_pooled(self.world),
+            PipeBackend::Gap    => builder.build::<PipeGap>(self.world),
+        }.expect("Invalid connection")
+    }
+
+}
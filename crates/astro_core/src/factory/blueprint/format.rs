@@ -0,0 +1,130 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+//! Single line text format for sharing, machines then connections:
+//!
+//! ```text
+//! bp1|SOURCE,SPEED@0,0,East;PASSTHROUGH;SINK,-,SPEED|0B>1A:16;1B>2A:16@1,0/2,0
+//! ```
+//!
+//! A machine is its kind, recipe and the filters of ports A to D, trailing
+//! `-` fields are left out, then its grid position and rotation if placed.
+//! A connection takes from machine 0 port B and delivers into machine 1 port
+//! A with a length of 16, then the tiles of its path if placed.
+
+use std::str::FromStr;
+
+use bevy::prelude::IVec2;
+
+use super::{Blueprint, BlueprintMachine, BlueprintConnection, BlueprintError, MachineUUID, ResourceUUID, PortID, Rotation};
+use crate::factory::parse_tile;
+
+pub const BLUEPRINT_HEADER: &str = "bp1";
+
+impl std::fmt::Display for Blueprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}|", BLUEPRINT_HEADER)?;
+
+        for (i, machine) in self.machines.iter().enumerate() {
+            if i > 0 { f.write_str(";")?; }
+            write!(f, "{}", machine.kind)?;
+
+            let fields = std::iter::once(machine.recipe).chain(machine.filters.iter().copied()).collect::<Vec<_>>();
+            let used   = fields.iter().rposition(Option::is_some).map_or(0, |v| v + 1);
+            for field in fields[..used].iter() {
+                match field {
+                    Some(resource) => write!(f, ",{}", resource)?,
+                    None           => f.write_str(",-")?,
+                }
+            }
+            if let Some((position, rotation)) = machine.placement {
+                write!(f, "@{},{},{:?}", position.x, position.y, rotation)?;
+            }
+        }
+
+        f.write_str("|")?;
+        for (i, c) in self.connections.iter().enumerate() {
+            if i > 0 { f.write_str(";")?; }
+            write!(f, "{}{:?}>{}{:?}:{}", c.from, c.from_port, c.to, c.to_port, c.length)?;
+            for (i, tile) in c.path.iter().flatten().enumerate() {
+                write!(f, "{}{},{}", if i == 0 { "@" } else { "/" }, tile.x, tile.y)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Blueprint {
+    type Err = BlueprintError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut sections = text.trim().split('|');
+        if sections.next() != Some(BLUEPRINT_HEADER) { return Err(BlueprintError::Parse("Missing header")); }
+        let machines    = sections.next().ok_or(BlueprintError::Parse("Missing machines"))?;
+        let connections = sections.next().ok_or(BlueprintError::Parse("Missing connections"))?;
+        if sections.next().is_some() { return Err(BlueprintError::Parse("Unexpected section")); }
+
+        Ok(Self{
+            machines:    entries(machines).map(parse_machine).collect::<Result<_, _>>()?,
+            connections: entries(connections).map(parse_connection).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+fn entries(section: &str) -> impl Iterator<Item = &str> {
+    section.split(';').filter(|v| !v.is_empty())
+}
+
+fn parse_machine(entry: &str) -> Result<BlueprintMachine, BlueprintError> {
+    let (entry, placement) = match entry.split_once('@') {
+        Some((entry, placement)) => (entry, Some(parse_placement(placement)?)),
+        None                     => (entry, None),
+    };
+
+    let mut fields = entry.split(',');
+    let kind = fields.next().filter(|v| !v.is_empty()).ok_or(BlueprintError::Parse("Missing machine kind"))?;
+    let kind = MachineUUID::try_new(kind).map_err(BlueprintError::Parse)?;
+
+    let mut resources = [None; 5];
+    for slot in resources.iter_mut() {
+        *slot = match fields.next() {
+            None | Some("-") => None,
+            Some(v)          => Some(ResourceUUID::try_new(v).map_err(BlueprintError::Parse)?),
+        };
+    }
+    if fields.next().is_some() { return Err(BlueprintError::Parse("Too many machine fields")); }
+
+    let [recipe, a, b, c, d] = resources;
+    Ok(BlueprintMachine{ kind, recipe, filters: [a, b, c, d], placement })
+}
+
+/// Position and rotation written as `x,y,Rotation`.
+fn parse_placement(value: &str) -> Result<(IVec2, Rotation), BlueprintError> {
+    let (position, rotation) = value.rsplit_once(',').ok_or(BlueprintError::Parse("Invalid placement"))?;
+    let position = parse_tile(position).ok_or(BlueprintError::Parse("Invalid placement"))?;
+    Ok((position, rotation.parse().map_err(BlueprintError::Parse)?))
+}
+
+fn parse_connection(entry: &str) -> Result<BlueprintConnection, BlueprintError> {
+    let (entry, path) = match entry.split_once('@') {
+        Some((entry, path)) => (entry, Some(path.split('/').map(|v| parse_tile(v).ok_or(BlueprintError::Parse("Invalid path"))).collect::<Result<_, _>>()?)),
+        None                => (entry, None),
+    };
+
+    let (from, rest)   = entry.split_once('>').ok_or(BlueprintError::Parse("Invalid connection"))?;
+    let (to, length)   = rest.split_once(':').ok_or(BlueprintError::Parse("Invalid connection"))?;
+    let (from, from_port) = parse_endpoint(from)?;
+    let (to, to_port)     = parse_endpoint(to)?;
+    let length = length.parse().map_err(|_| BlueprintError::Parse("Invalid number"))?;
+    Ok(BlueprintConnection{ from, from_port, to, to_port, length, path })
+}
+
+/// Machine index immediately followed by the port letter, e.g. `12B`.
+fn parse_endpoint(value: &str) -> Result<(u32, PortID), BlueprintError> {
+    if value.len() < 2 || !value.is_char_boundary(value.len() - 1) { return Err(BlueprintError::Parse("Invalid endpoint")); }
+    let (idx, port) = value.split_at(value.len() - 1);
+    let idx = idx.parse().map_err(|_| BlueprintError::Parse("Invalid number"))?;
+    Ok((idx, port.parse().map_err(BlueprintError::Parse)?))
+}
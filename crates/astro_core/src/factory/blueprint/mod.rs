@@ -0,0 +1,163 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use bevy::{prelude::{Entity, IVec2, World}, utils::HashMap};
+
+use super::{
+    CommandError, FactoryCommand, FactoryHistory, GridPosition, Machine, MachineError, MachineRegistry, MachineUUID, PipePath, PortFilter,
+    PortID, PortSend, PortRecv, ResourceID, ResourceUUID, Rotation, SnapshotEntities, capture_connection, execute_command
+};
+
+mod format;
+pub use format::*;
+
+/// A reusable layout of machines and the connections between them. Port
+/// contents and packets in transit aren't part of it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Blueprint {
+    pub machines:    Vec<BlueprintMachine>,
+    pub connections: Vec<BlueprintConnection>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlueprintMachine {
+    pub kind:      MachineUUID,
+    pub recipe:    Option<ResourceUUID>,
+    pub filters:   [Option<ResourceUUID>; 4],
+    /// Where the machine sat on the grid, if it was placed on it.
+    pub placement: Option<(IVec2, Rotation)>,
+}
+
+/// A simple pipe taking from port `from_port` of machine `from` and
+/// delivering into port `to_port` of machine `to`, by index into `machines`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlueprintConnection {
+    pub from:      u32,
+    pub from_port: PortID,
+    pub to:        u32,
+    pub to_port:   PortID,
+    pub length:    u32,
+    /// Tiles the pipe ran along, if it was placed on the grid.
+    pub path:      Option<Vec<IVec2>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlueprintError {
+    Parse(&'static str),
+    NotAMachine(Entity),
+    UnnamedResource(ResourceID),
+}
+
+impl std::fmt::Display for BlueprintError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse(message)     => write!(f, "invalid blueprint: {}", message),
+            Self::NotAMachine(e)     => write!(f, "entity {} is not a machine", e.id()),
+            Self::UnnamedResource(v) => write!(f, "resource {:?} has no UUID", v),
+        }
+    }
+}
+
+impl std::error::Error for BlueprintError {}
+
+impl Blueprint {
+
+    /// Captures the machines and every connection running between two of
+    /// them, connections of unregistered pipe types are skipped.
+    pub fn capture(world: &mut World, machines: &[Entity]) -> Result<Self, BlueprintError> {
+        let indices = machines.iter().enumerate().map(|(i, &e)| (e, i as u32)).collect::<HashMap<_, _>>();
+        let uuid    = |r: Option<ResourceID>| r.map(|r| r.uuid().ok_or(BlueprintError::UnnamedResource(r))).transpose();
+
+        let mut result = Self::default();
+        for &entity in machines {
+            let machine = world.get::<Machine>(entity).ok_or(BlueprintError::NotAMachine(entity))?;
+            let filter  = world.get::<PortFilter>(entity).copied().unwrap_or_default();
+            let mut filters = [None; 4];
+            for (port, slot) in PortID::ALL.into_iter().zip(filters.iter_mut()) {
+                *slot = uuid(filter.get(port))?;
+            }
+            let placement = world.get::<GridPosition>(entity).zip(world.get::<Rotation>(entity)).map(|(position, &rotation)| (position.0, rotation));
+            result.machines.push(BlueprintMachine{ kind: machine.kind, recipe: uuid(machine.recipe)?, filters, placement });
+        }
+
+        let mut connections = world.query::<(Entity, &PortRecv, &PortSend)>()
+            .iter(world)
+            .filter_map(|(e, &PortRecv(from, from_port), &PortSend(to, to_port))| {
+                Some((e, *indices.get(&from)?, from_port, *indices.get(&to)?, to_port))
+            })
+            .collect::<Vec<_>>();
+        connections.sort_unstable_by_key(|v| v.0);
+
+        for (entity, from, from_port, to, to_port) in connections {
+            if let Some((_, contents)) = capture_connection(world, entity) {
+                let path = world.get::<PipePath>(entity).map(|v| v.0.clone());
+                result.connections.push(BlueprintConnection{ from, from_port, to, to_port, length: contents.length, path });
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Pastes the blueprint as new entities through `execute_command`, so it's
+    /// recorded, and undone in one step if there's a `FactoryHistory`. Placed
+    /// machines and pipes are moved by `offset`. If a command fails the
+    /// entities pasted so far are left in place. Prefer queueing
+    /// `FactoryCommand::Paste` outside of setup and tests.
+    pub fn paste(&self, world: &mut World, offset: IVec2) -> Result<SnapshotEntities, CommandError> {
+        self.validate(world)?;
+
+        if let Some(mut history) = world.get_resource_mut::<FactoryHistory>() { history.begin_group(); }
+        let mut result = SnapshotEntities::default();
+        let outcome    = self.paste_commands(world, offset, &mut result);
+        if let Some(mut history) = world.get_resource_mut::<FactoryHistory>() { history.end_group(); }
+        outcome.map(|_| result)
+    }
+
+    fn validate(&self, world: &World) -> Result<(), CommandError> {
+        let registry = world.get_resource::<MachineRegistry>();
+        for machine in self.machines.iter() {
+            if registry.and_then(|v| v.get(machine.kind)).is_none() { return Err(MachineError::UnknownKind(machine.kind).into()); }
+        }
+
+        let count = self.machines.len() as u32;
+        for connection in self.connections.iter() {
+            for idx in [connection.from, connection.to] {
+                if idx >= count { return Err(CommandError::InvalidBlueprint(idx)); }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn paste_commands(&self, world: &mut World, offset: IVec2, result: &mut SnapshotEntities) -> Result<(), CommandError> {
+        for machine in self.machines.iter() {
+            let (kind, recipe) = (machine.kind, machine.recipe.map(ResourceID::intern));
+            let entity = match machine.placement {
+                Some((position, rotation)) => execute_command(world, FactoryCommand::PlaceMachineAt{ kind, recipe, position: position + offset, rotation })?,
+                None                       => execute_command(world, FactoryCommand::PlaceMachine{ kind, recipe })?,
+            }.unwrap();
+            for (port, filter) in PortID::ALL.into_iter().zip(machine.filters.iter()) {
+                if let Some(filter) = filter {
+                    execute_command(world, FactoryCommand::ConfigurePortFilter{ machine: entity, port, filter: Some(ResourceID::intern(*filter)) })?;
+                }
+            }
+            result.machines.push(entity);
+        }
+
+        for connection in self.connections.iter() {
+            let (from, to) = (result.machines[connection.from as usize], result.machines[connection.to as usize]);
+            let (from_port, to_port) = (connection.from_port, connection.to_port);
+            let command = match connection.path.as_ref() {
+                Some(path) => FactoryCommand::PlacePipe{ from, from_port, to, to_port, path: path.iter().map(|&v| v + offset).collect() },
+                None       => FactoryCommand::Connect{ from, from_port, to, to_port, length: connection.length },
+            };
+            result.connections.push(execute_command(world, command)?.unwrap());
+        }
+
+        Ok(())
+    }
+
+}
@@ -0,0 +1,118 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::prelude::App;
+
+use super::*;
+use crate::factory::{FactoryCommandQueue, FactoryPlugins, PipeSimple, MACHINE_SOURCE, MACHINE_SINK, MACHINE_PASSTHROUGH};
+
+fn example() -> Blueprint {
+    let speed = ResourceUUID::new("SPEED");
+    Blueprint{
+        machines: vec![
+            BlueprintMachine{ kind: MACHINE_SOURCE,      recipe: Some(speed), filters: [None; 4], placement: None },
+            BlueprintMachine{ kind: MACHINE_PASSTHROUGH, recipe: None,        filters: [None; 4], placement: None },
+            BlueprintMachine{ kind: MACHINE_SINK,        recipe: None,        filters: [None, Some(speed), None, None], placement: None },
+        ],
+        connections: vec![
+            BlueprintConnection{ from: 0, from_port: PortID::B, to: 1, to_port: PortID::A, length: 16, path: None },
+            BlueprintConnection{ from: 1, from_port: PortID::B, to: 2, to_port: PortID::A, length: 4,  path: None },
+        ],
+    }
+}
+
+fn placed() -> Blueprint {
+    Blueprint{
+        machines: vec![
+            BlueprintMachine{ kind: MACHINE_SOURCE, recipe: None, filters: [None; 4], placement: Some((IVec2::new(0, 0), Rotation::North)) },
+            BlueprintMachine{ kind: MACHINE_SINK,   recipe: None, filters: [None; 4], placement: Some((IVec2::new(3, 0), Rotation::North)) },
+        ],
+        connections: vec![
+            BlueprintConnection{ from: 0, from_port: PortID::B, to: 1, to_port: PortID::A, length: 2, path: Some(vec![IVec2::new(1, 0), IVec2::new(2, 0)]) },
+        ],
+    }
+}
+
+#[test]
+fn format() {
+    assert_eq!(example().to_string(), "bp1|SOURCE,SPEED;PASSTHROUGH;SINK,-,-,SPEED|0B>1A:16;1B>2A:4");
+    assert_eq!(example().to_string().parse::<Blueprint>(), Ok(example()));
+    assert_eq!("bp1||".parse::<Blueprint>(), Ok(Blueprint::default()));
+    assert_eq!(placed().to_string(), "bp1|SOURCE@0,0,North;SINK@3,0,North|0B>1A:2@1,0/2,0");
+    assert_eq!(placed().to_string().parse::<Blueprint>(), Ok(placed()));
+}
+
+#[test]
+fn parse_errors() {
+    assert_eq!("bp2||".parse::<Blueprint>(),                Err(BlueprintError::Parse("Missing header")));
+    assert_eq!("bp1|SINK".parse::<Blueprint>(),             Err(BlueprintError::Parse("Missing connections")));
+    assert_eq!("bp1|,SPEED|".parse::<Blueprint>(),          Err(BlueprintError::Parse("Missing machine kind")));
+    assert_eq!("bp1|SINK,-,-,-,-,-,-|".parse::<Blueprint>(), Err(BlueprintError::Parse("Too many machine fields")));
+    assert_eq!("bp1|SINK|0B1A:4".parse::<Blueprint>(),      Err(BlueprintError::Parse("Invalid connection")));
+    assert_eq!("bp1|SINK|0E>1A:4".parse::<Blueprint>(),     Err(BlueprintError::Parse("Invalid port")));
+    assert_eq!("bp1|SINK@1,0|".parse::<Blueprint>(),        Err(BlueprintError::Parse("Invalid placement")));
+    assert_eq!("bp1|SINK@1,0,Up|".parse::<Blueprint>(),     Err(BlueprintError::Parse("Invalid rotation")));
+    assert_eq!("bp1|SINK|0B>1A:4@1,0/2".parse::<Blueprint>(), Err(BlueprintError::Parse("Invalid path")));
+}
+
+#[test]
+fn capture_and_paste() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins).insert_resource(FactoryHistory::default());
+
+    let pasted = example().paste(&mut app.world, IVec2::ZERO).unwrap();
+    assert_eq!((pasted.machines.len(), pasted.connections.len()), (3, 2));
+    assert_eq!(Blueprint::capture(&mut app.world, &pasted.machines), Ok(example()));
+
+    let copy = example().paste(&mut app.world, IVec2::ZERO).unwrap();
+    assert!(copy.machines.iter().all(|e| !pasted.machines.contains(e)));
+    assert_eq!(app.world.query::<&PipeSimple>().iter(&app.world).count(), 4);
+
+    // Only connections between captured machines are included.
+    let partial = Blueprint::capture(&mut app.world, &copy.machines[..2]).unwrap();
+    assert_eq!(partial.connections, example().connections[..1]);
+
+    assert_eq!(app.world.get_resource::<FactoryHistory>().unwrap().undo_len(), 2);
+    crate::factory::undo(&mut app.world).unwrap();
+    assert_eq!(app.world.query::<&Machine>().iter(&app.world).count(), 3);
+    assert_eq!(app.world.query::<&PipeSimple>().iter(&app.world).count(), 2);
+
+    let unknown = "bp1|NOPE|".parse::<Blueprint>().unwrap();
+    assert_eq!(unknown.paste(&mut app.world, IVec2::ZERO).map(|_| ()), Err(CommandError::Machine(MachineError::UnknownKind(MachineUUID::new("NOPE")))));
+    let mut invalid = example();
+    invalid.connections[0].to = 3;
+    assert_eq!(invalid.paste(&mut app.world, IVec2::ZERO).map(|_| ()), Err(CommandError::InvalidBlueprint(3)));
+}
+
+#[test]
+fn paste_offset() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+
+    let pasted = placed().paste(&mut app.world, IVec2::ZERO).unwrap();
+    assert_eq!(Blueprint::capture(&mut app.world, &pasted.machines), Ok(placed()));
+    assert!(placed().paste(&mut app.world, IVec2::ZERO).is_err());
+
+    let moved = placed().paste(&mut app.world, IVec2::new(0, 2)).unwrap();
+    assert_eq!(app.world.get::<GridPosition>(moved.machines[1]), Some(&GridPosition(IVec2::new(3, 2))));
+    assert_eq!(app.world.get::<PipePath>(moved.connections[0]).map(|v| v.0.clone()), Some(vec![IVec2::new(1, 2), IVec2::new(2, 2)]));
+}
+
+#[test]
+fn queued_paste() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins).insert_resource(FactoryHistory::new(2));
+
+    app.world.get_resource_mut::<FactoryCommandQueue>().unwrap().push(FactoryCommand::Paste{ blueprint: example(), offset: IVec2::ZERO });
+    assert_eq!(app.world.query::<&Machine>().iter(&app.world).count(), 0);
+    app.update();
+    assert_eq!(app.world.query::<&Machine>().iter(&app.world).count(), 3);
+
+    // Six edits, more than the history holds, are still undone in one step.
+    assert_eq!(app.world.get_resource::<FactoryHistory>().unwrap().undo_len(), 1);
+    app.world.get_resource_mut::<FactoryCommandQueue>().unwrap().push(FactoryCommand::Undo);
+    app.update();
+    assert_eq!(app.world.query::<&Machine>().iter(&app.world).count(), 0);
+    assert_eq!(app.world.query::<&PipeSimple>().iter(&app.world).count(), 0);
+}
@@ -0,0 +1,246 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, ecs::event::Events};
+
+use super::{
+    Blueprint, ConnectionBuilder, ConnectionError, ConnectionIndex, FactoryStageInternal, FactorySystem, FactoryTick, GridError, Machine, MachineError, MachineUUID,
+    FactoryEdit, FactoryHistory, PipeSimple, Ports, PortID, PortFilter, PortSend, PortRecv, ReplayRecorder, ResourceID, Rotation,
+    is_stranded, place_machine, place_pipe, redo, spawn_machine, spill_connection, spill_dropped, undo
+};
+
+/// A player build action.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FactoryCommand {
+    PlaceMachine{ kind: MachineUUID, recipe: Option<ResourceID> },
+    /// Places a machine on the grid, see `place_machine`.
+    PlaceMachineAt{ kind: MachineUUID, recipe: Option<ResourceID>, position: IVec2, rotation: Rotation },
+    /// Connects port `from_port` of `from` to port `to_port` of `to` with a simple pipe.
+    Connect{ from: Entity, from_port: PortID, to: Entity, to_port: PortID, length: u32 },
+    /// Connects two placed machines along a path on the grid, see `place_pipe`.
+    PlacePipe{ from: Entity, from_port: PortID, to: Entity, to_port: PortID, path: Vec<IVec2> },
+    /// Removes every connection attached to the port, spilling what they held.
+    Disconnect{ machine: Entity, port: PortID },
+    SetRecipe{ machine: Entity, recipe: Option<ResourceID> },
+    /// Restricts the port to the given resource, or lifts the restriction if `None`.
+    ConfigurePortFilter{ machine: Entity, port: PortID, filter: Option<ResourceID> },
+    /// Removes a machine or connection. Removed connections, and those left
+    /// with nowhere to deliver, spill straight away rather than once they're
+    /// found dangling.
+    Remove{ entity: Entity },
+    /// Pastes a blueprint with placed machines and pipes moved by `offset`,
+    /// see `Blueprint::paste`. Spawns the first machine pasted.
+    Paste{ blueprint: Blueprint, offset: IVec2 },
+    /// Reverts the most recent edit in the `FactoryHistory`.
+    Undo,
+    Redo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError {
+    Machine(MachineError),
+    Connection(ConnectionError),
+    Grid(GridError),
+    NotAMachine(Entity),
+    NotConnected(Entity, PortID),
+    NotFound(Entity),
+    InvalidBlueprint(u32),
+    EmptyHistory,
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Machine(e)            => e.fmt(f),
+            Self::Connection(e)         => e.fmt(f),
+            Self::Grid(e)               => e.fmt(f),
+            Self::NotAMachine(e)        => write!(f, "entity {} is not a machine", e.id()),
+            Self::NotConnected(e, port) => write!(f, "port {:?} of entity {} has no connections", port, e.id()),
+            Self::NotFound(e)           => write!(f, "no machine or connection {}", e.id()),
+            Self::InvalidBlueprint(v)   => write!(f, "blueprint machine index {} is out of range", v),
+            Self::EmptyHistory          => write!(f, "nothing to undo or redo"),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl From<MachineError> for CommandError {
+    fn from(e: MachineError) -> Self {
+        Self::Machine(e)
+    }
+}
+
+impl From<ConnectionError> for CommandError {
+    fn from(e: ConnectionError) -> Self {
+        Self::Connection(e)
+    }
+}
+
+impl From<GridError> for CommandError {
+    fn from(e: GridError) -> Self {
+        Self::Grid(e)
+    }
+}
+
+impl FactoryCommand {
+
+    /// Applies the command, returning the entity it spawned, if any.
+    pub fn apply(&self, world: &mut World) -> Result<Option<Entity>, CommandError> {
+        match *self {
+            Self::PlaceMachine{ kind, recipe } => Ok(Some(spawn_machine(world, kind, recipe)?)),
+            Self::PlaceMachineAt{ kind, recipe, position, rotation } => Ok(Some(place_machine(world, kind, recipe, position, rotation)?)),
+            Self::Connect{ from, from_port, to, to_port, length } => {
+                let entity = ConnectionBuilder::new(length)
+                    .recv_from(from, from_port)
+                    .send_to(to, to_port)
+                    .build::<PipeSimple>(world)?;
+                Ok(Some(entity))
+            },
+            Self::PlacePipe{ from, from_port, to, to_port, ref path } => Ok(Some(place_pipe(world, from, from_port, to, to_port, path.clone())?)),
+            Self::Disconnect{ machine, port } => {
+                let connections = port_connections(world, machine, port);
+                if connections.is_empty() { return Err(CommandError::NotConnected(machine, port)); }
+                for connection in connections {
+                    spill_connection(world, connection);
+                    world.despawn(connection);
+                }
+                Ok(None)
+            },
+            Self::SetRecipe{ machine, recipe } => {
+                world.get_mut::<Machine>(machine).ok_or(CommandError::NotAMachine(machine))?.recipe = recipe;
+                Ok(None)
+            },
+            Self::ConfigurePortFilter{ machine, port, filter } => {
+                let mut entity = world.get_entity_mut(machine).filter(|v| v.contains::<Ports>()).ok_or(CommandError::NotAMachine(machine))?;
+                let mut filters = entity.get::<PortFilter>().copied().unwrap_or_default();
+                filters.set(port, filter);
+                if filters.is_empty() { entity.remove::<PortFilter>(); } else { entity.insert(filters); }
+                Ok(None)
+            },
+            Self::Remove{ entity } => {
+                let found = world.get_entity(entity).map_or(false, |v| v.contains::<Ports>() || v.contains::<PortSend>() || v.contains::<PortRecv>());
+                if !found { return Err(CommandError::NotFound(entity)); }
+                if world.get::<Ports>(entity).is_some() {
+                    let connections = PortID::ALL.into_iter().flat_map(|port| port_connections(world, entity, port)).collect::<Vec<_>>();
+                    for connection in connections {
+                        if is_stranded(world, connection, &[entity]) { spill_connection(world, connection); }
+                    }
+                } else {
+                    spill_connection(world, entity);
+                }
+                world.despawn(entity);
+                Ok(None)
+            },
+            Self::Paste{ ref blueprint, offset } => Ok(blueprint.paste(world, offset)?.machines.first().copied()),
+            Self::Undo => { undo(world)?; Ok(None) },
+            Self::Redo => { redo(world)?; Ok(None) },
+        }
+    }
+
+}
+
+/// Connections attached to the port in either direction, looked up in the
+/// `ConnectionIndex` and checked against their current ends.
+pub fn port_connections(world: &World, machine: Entity, port: PortID) -> Vec<Entity> {
+    let index = match world.get_resource::<ConnectionIndex>() {
+        Some(v) => v,
+        None    => return Vec::new(),
+    };
+
+    let senders   = index.senders(machine, port).iter().copied().filter(|&c| world.get::<PortSend>(c) == Some(&PortSend(machine, port)));
+    let receivers = index.receivers(machine, port).iter().copied().filter(|&c| world.get::<PortRecv>(c) == Some(&PortRecv(machine, port)));
+    let mut result = senders.chain(receivers).collect::<Vec<_>>();
+    result.sort_unstable();
+    result.dedup();
+    result
+}
+
+/// Applies a command immediately, recording it if a `ReplayRecorder` or
+/// `FactoryHistory` is active. Undo, redo and paste record the commands they
+/// expand to rather than themselves. Prefer `FactoryCommandQueue` outside of
+/// setup and tests.
+pub fn execute_command(world: &mut World, command: FactoryCommand) -> Result<Option<Entity>, CommandError> {
+    let inverse = if world.contains_resource::<FactoryHistory>() { FactoryEdit::inverse_of(world, &command)? } else { None };
+
+    let result   = command.apply(world)?;
+    let tick     = world.get_resource::<FactoryTick>().map_or(0, |v| v.0);
+    let expanded = matches!(command, FactoryCommand::Undo | FactoryCommand::Redo | FactoryCommand::Paste{ .. });
+    if let Some(mut recorder) = world.get_resource_mut::<ReplayRecorder>().filter(|_| !expanded) {
+        recorder.record(tick, &command, result);
+    }
+    if let Some(mut history) = world.get_resource_mut::<FactoryHistory>().filter(|_| !expanded) {
+        if let Some(inverse) = inverse.or_else(|| result.map(|e| FactoryEdit::Despawn(vec![e]))) { history.push(inverse); }
+    }
+    spill_dropped(world);
+    Ok(result)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CommandId(pub u32);
+
+/// Commands waiting for the start of the next tick. Each is answered by a
+/// `FactoryCommandResult` event with the ID `push` returned.
+#[derive(Default)]
+pub struct FactoryCommandQueue {
+    next:    u32,
+    pending: VecDeque<(CommandId, FactoryCommand)>,
+}
+
+impl FactoryCommandQueue {
+
+    pub fn push(&mut self, command: FactoryCommand) -> CommandId {
+        let id = CommandId(self.next);
+        self.next = self.next.wrapping_add(1);
+        self.pending.push_back((id, command));
+        id
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FactoryCommandResult {
+    pub id:      CommandId,
+    pub tick:    u32,
+    pub command: FactoryCommand,
+    pub result:  Result<Option<Entity>, CommandError>,
+}
+
+pub struct FactoryCommandPlugin;
+
+impl Plugin for FactoryCommandPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<FactoryCommandQueue>()
+            .add_event::<FactoryCommandResult>()
+            .add_system_to_stage(FactoryStageInternal::Tick, apply_command_queue.exclusive_system().at_start().label(FactorySystem::ApplyCommands));
+    }
+}
+
+/// Applies queued commands in order, before the tick advances, so every
+/// command sees the factory as the previous tick left it.
+pub fn apply_command_queue(world: &mut World) {
+    let pending = match world.get_resource_mut::<FactoryCommandQueue>() {
+        Some(mut queue) => std::mem::take(&mut queue.pending),
+        None            => return,
+    };
+
+    let tick = world.get_resource::<FactoryTick>().map_or(0, |v| v.0);
+    for (id, command) in pending {
+        let result = execute_command(world, command.clone());
+        world.get_resource_mut::<Events<FactoryCommandResult>>().unwrap().send(FactoryCommandResult{ id, tick, command, result });
+    }
+}
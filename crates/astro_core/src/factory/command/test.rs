@@ -0,0 +1,109 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+use crate::factory::{FactoryPlugins, FlowMonitor, FlowStatus, ResourceUUID, SpilledResources, MACHINE_SOURCE, MACHINE_SINK, capture_connection};
+
+/// Queues a command and runs a frame, returning its result.
+fn run(app: &mut App, command: FactoryCommand) -> FactoryCommandResult {
+    let id = app.world.get_resource_mut::<FactoryCommandQueue>().unwrap().push(command);
+    app.update();
+    let events = app.world.get_resource::<Events<FactoryCommandResult>>().unwrap();
+    events.iter_current_update_events().find(|v| v.id == id).cloned().unwrap()
+}
+
+fn build_app() -> (App, Entity, Entity, Entity) {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+
+    let speed  = ResourceID::intern(ResourceUUID::new("SPEED"));
+    let source = run(&mut app, FactoryCommand::PlaceMachine{ kind: MACHINE_SOURCE, recipe: Some(speed) }).result.unwrap().unwrap();
+    let sink   = run(&mut app, FactoryCommand::PlaceMachine{ kind: MACHINE_SINK, recipe: None }).result.unwrap().unwrap();
+    let pipe   = run(&mut app, FactoryCommand::Connect{ from: source, from_port: PortID::B, to: sink, to_port: PortID::A, length: 2 }).result.unwrap().unwrap();
+    (app, source, sink, pipe)
+}
+
+#[test]
+fn applied_at_tick_start() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+    app.update();
+
+    let mut queue = app.world.get_resource_mut::<FactoryCommandQueue>().unwrap();
+    let first  = queue.push(FactoryCommand::PlaceMachine{ kind: MACHINE_SINK, recipe: None });
+    let second = queue.push(FactoryCommand::Remove{ entity: Entity::from_raw(99) });
+    assert_eq!(queue.len(), 2);
+    assert_eq!(app.world.query::<&Ports>().iter(&app.world).count(), 0);
+
+    app.update();
+    let events  = app.world.get_resource::<Events<FactoryCommandResult>>().unwrap();
+    let results = events.iter_current_update_events().map(|v| (v.id, v.tick, v.result.is_ok())).collect::<Vec<_>>();
+    assert_eq!(results, vec![(first, 1, true), (second, 1, false)]);
+    assert!(app.world.get_resource::<FactoryCommandQueue>().unwrap().is_empty());
+    assert_eq!(app.world.query::<&Ports>().iter(&app.world).count(), 1);
+}
+
+#[test]
+fn port_filter() {
+    let (mut app, _, sink, pipe) = build_app();
+    let iron = ResourceID::intern(ResourceUUID::new("IRON"));
+
+    run(&mut app, FactoryCommand::ConfigurePortFilter{ machine: sink, port: PortID::A, filter: Some(iron) }).result.unwrap();
+    for _ in 0..8 { app.update(); }
+    assert_eq!(app.world.get::<FlowMonitor>(pipe).unwrap().status(), FlowStatus::ResourceMismatch);
+
+    run(&mut app, FactoryCommand::ConfigurePortFilter{ machine: sink, port: PortID::A, filter: None }).result.unwrap();
+    assert_eq!(app.world.get::<FlowMonitor>(pipe).unwrap().status(), FlowStatus::Running);
+
+    let result = run(&mut app, FactoryCommand::ConfigurePortFilter{ machine: pipe, port: PortID::A, filter: None }).result;
+    assert_eq!(result, Err(CommandError::NotAMachine(pipe)));
+}
+
+#[test]
+fn disconnect() {
+    let (mut app, source, _, pipe) = build_app();
+
+    assert_eq!(run(&mut app, FactoryCommand::Disconnect{ machine: source, port: PortID::B }).result, Ok(None));
+    assert!(app.world.get_entity(pipe).is_none());
+    assert_eq!(
+        run(&mut app, FactoryCommand::Disconnect{ machine: source, port: PortID::B }).result,
+        Err(CommandError::NotConnected(source, PortID::B))
+    );
+}
+
+/// Connections removed directly spill what they held, like those removed
+/// along with a machine.
+#[test]
+fn removed_connections_spill() {
+    let speed = ResourceID::intern(ResourceUUID::new("SPEED"));
+    let commands: [fn(Entity, Entity) -> FactoryCommand; 2] = [
+        |source, _| FactoryCommand::Disconnect{ machine: source, port: PortID::B },
+        |_, pipe|   FactoryCommand::Remove{ entity: pipe },
+    ];
+    for command in commands {
+        let (mut app, source, _, pipe) = build_app();
+        for _ in 0..4 { app.update(); }
+        let held = capture_connection(&app.world, pipe).unwrap().1.packets.len() as u64;
+        assert!(held > 0);
+
+        run(&mut app, command(source, pipe)).result.unwrap();
+        assert!(app.world.get_entity(pipe).is_none());
+        assert_eq!(app.world.get_resource::<SpilledResources>().unwrap().get(speed), held);
+    }
+}
+
+#[test]
+fn port_connections_follow_ends() {
+    let (mut app, source, sink, pipe) = build_app();
+    assert_eq!(port_connections(&app.world, source, PortID::B), vec![pipe]);
+    assert_eq!(port_connections(&app.world, sink, PortID::A),   vec![pipe]);
+    assert!(port_connections(&app.world, source, PortID::A).is_empty());
+
+    // Stale index entries are ignored until the index catches up.
+    app.world.entity_mut(pipe).remove::<PortRecv>();
+    assert!(port_connections(&app.world, source, PortID::B).is_empty());
+    app.update();
+    assert!(port_connections(&app.world, source, PortID::B).is_empty());
+    assert_eq!(port_connections(&app.world, sink, PortID::A), vec![pipe]);
+}
@@ -0,0 +1,255 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+//! Lockstep support. Factory stages run single threaded with every built-in
+//! system explicitly ordered by `FactorySystem` labels, and simulation state is
+//! only ever walked in archetype or sorted entity order. Hash maps are only
+//! used for lookups, never iterated. Two runs with the same inputs therefore
+//! produce the same checksum.
+
+#[cfg(test)] mod test;
+
+use std::{collections::VecDeque, hash::Hasher};
+
+use bevy::{prelude::*, ecs::event::Events, utils::HashMap};
+
+use super::{FactoryStageInternal, FactoryTick, Machine, Ports, PortID, PortFilter, PortSend, PortRecv, ResourceID, visit_connection};
+
+/// FNV-1a, stable across platforms and releases unlike `DefaultHasher`.
+pub struct Fnv64(u64);
+
+impl Default for Fnv64 {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv64 {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0  = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// Checksum of the factory at the end of a tick, with a hash per entity so a
+/// mismatch can be narrowed down.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FactoryChecksum {
+    pub tick:     u32,
+    pub value:    u64,
+    /// Position in the order checksummed, local entity and hash, sorted by
+    /// position. Checksums are compared by position, since entity IDs
+    /// differ between worlds.
+    pub entities: Vec<(u32, Entity, u64)>,
+}
+
+/// First difference between a local and an expected checksum. `index` is the
+/// position of the entity in the order checksummed, `None` along with
+/// `entity` if only the tick differs. `entity` is also `None` if the local
+/// world has nothing at that position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FactoryDesync {
+    pub tick:     u32,
+    pub index:    Option<u32>,
+    pub entity:   Option<Entity>,
+    pub local:    Option<u64>,
+    pub expected: Option<u64>,
+}
+
+/// Scratch space for checksums, kept between ticks so they don't allocate.
+#[derive(Default)]
+pub struct ChecksumBuffers {
+    order:     Vec<Entity>,
+    positions: HashMap<Entity, u32>,
+    /// Raw UUIDs by resource ID, looked up once per resource.
+    uuids:     Vec<Option<u128>>,
+}
+
+impl ChecksumBuffers {
+
+    /// Hashes the UUID where there is one, IDs depend on interning order.
+    fn hash_resource(&mut self, hasher: &mut Fnv64, resource: ResourceID) {
+        let i = resource.into_inner() as usize;
+        if i >= self.uuids.len() { self.uuids.resize(i + 1, None); }
+        if self.uuids[i].is_none() { self.uuids[i] = resource.uuid().map(|v| v.to_raw()); }
+        match self.uuids[i] {
+            Some(uuid) => hasher.write_u128(uuid),
+            None       => hasher.write_u16(resource.into_inner()),
+        }
+    }
+
+}
+
+/// Runs `f` with the world's `ChecksumBuffers`, inserting them if needed.
+pub fn with_checksum_buffers<R>(world: &mut World, f: impl FnOnce(&mut World, &mut ChecksumBuffers) -> R) -> R {
+    if !world.contains_resource::<ChecksumBuffers>() { world.insert_resource(ChecksumBuffers::default()); }
+    world.resource_scope(|world, mut buffers: Mut<ChecksumBuffers>| f(world, &mut buffers))
+}
+
+impl FactoryChecksum {
+
+    /// Checksums every machine and connection in entity order.
+    pub fn compute(world: &mut World) -> Self {
+        with_checksum_buffers(world, |world, buffers| {
+            let mut order = std::mem::take(&mut buffers.order);
+            order.clear();
+            order.extend(world.query_filtered::<Entity, Or<(With<Ports>, With<PortSend>, With<PortRecv>)>>().iter(world));
+            order.sort_unstable();
+            let checksum = Self::compute_ordered(world, &order, buffers);
+            buffers.order = order;
+            checksum
+        })
+    }
+
+    /// Checksums the given entities, skipping despawned ones. Entities and
+    /// connection targets are hashed by their position in `order` rather
+    /// than their ID, so worlds with different entity allocation match.
+    pub fn compute_ordered(world: &World, order: &[Entity], buffers: &mut ChecksumBuffers) -> Self {
+        let tick = world.get_resource::<FactoryTick>().map_or(0, |v| v.0);
+        buffers.positions.clear();
+        buffers.positions.extend(order.iter().enumerate().map(|(i, &e)| (e, i as u32)));
+        let positions = std::mem::take(&mut buffers.positions);
+        let position  = |e: Entity| positions.get(&e).copied().unwrap_or(u32::MAX);
+
+        let mut global = Fnv64::default();
+        global.write_u32(tick);
+
+        let entities = order.iter().filter(|&&e| world.get_entity(e).is_some()).map(|&entity| {
+            let mut hasher = Fnv64::default();
+
+            if let Some(ports) = world.get::<Ports>(entity) {
+                for port in PortID::ALL {
+                    match ports.get(port).get() {
+                        Some((resource, count)) => { buffers.hash_resource(&mut hasher, resource); hasher.write_u16(count); },
+                        None                    => hasher.write_u16(0),
+                    }
+                }
+            }
+
+            if let Some(machine) = world.get::<Machine>(entity) {
+                hasher.write_u128(machine.kind.to_raw());
+                if let Some(recipe) = machine.recipe { buffers.hash_resource(&mut hasher, recipe); }
+            }
+
+            if let Some(filter) = world.get::<PortFilter>(entity) {
+                for port in PortID::ALL {
+                    match filter.get(port) {
+                        Some(resource) => buffers.hash_resource(&mut hasher, resource),
+                        None           => hasher.write_u16(0),
+                    }
+                }
+            }
+
+            if let Some(&PortSend(target, port)) = world.get::<PortSend>(entity) {
+                hasher.write_u32(position(target));
+                hasher.write_u8(port as u8);
+            }
+
+            if let Some(&PortRecv(target, port)) = world.get::<PortRecv>(entity) {
+                hasher.write_u32(position(target));
+                hasher.write_u8(port as u8);
+            }
+
+            // Packets are hashed before the length, which is only known once visited.
+            let length = visit_connection(world, entity, &mut |tick, resource| {
+                hasher.write_u32(tick);
+                buffers.hash_resource(&mut hasher, resource);
+            });
+            if let Some(length) = length { hasher.write_u32(length); }
+
+            let hash = hasher.finish();
+            global.write_u32(position(entity));
+            global.write_u64(hash);
+            (position(entity), entity, hash)
+        }).collect::<Vec<_>>();
+
+        buffers.positions = positions;
+        Self{ tick, value: global.finish(), entities }
+    }
+
+    /// Finds the first entity, by position, whose hash differs from `expected`.
+    pub fn first_divergence(&self, expected: &FactoryChecksum) -> Option<FactoryDesync> {
+        if self.tick == expected.tick && self.value == expected.value { return None; }
+
+        let mut lhs = self.entities.iter().peekable();
+        let mut rhs = expected.entities.iter().peekable();
+        loop {
+            let (index, entity, local, remote) = match (lhs.peek().copied(), rhs.peek().copied()) {
+                (None, None) => break,
+                (Some(&(a, e, va)), Some(&(b, _, vb))) if a == b => {
+                    lhs.next();
+                    rhs.next();
+                    if va == vb { continue; }
+                    (a, Some(e), Some(va), Some(vb))
+                },
+                (Some(&(a, e, va)), Some(&(b, _, _))) if a < b => (a, Some(e), Some(va), None),
+                (Some(&(a, e, va)), None)                      => (a, Some(e), Some(va), None),
+                (_, Some(&(b, _, vb)))                         => (b, None, None, Some(vb)),
+            };
+            return Some(FactoryDesync{ tick: self.tick, index: Some(index), entity, local, expected: remote });
+        }
+
+        Some(FactoryDesync{ tick: self.tick, index: None, entity: None, local: Some(self.value), expected: Some(expected.value) })
+    }
+
+}
+
+/// Checksums to compare against as their ticks complete, e.g. from a peer or a replay.
+#[derive(Default)]
+pub struct DesyncDetector {
+    expected: VecDeque<FactoryChecksum>,
+}
+
+impl DesyncDetector {
+
+    /// Checksums must be pushed in tick order, ones for ticks that have
+    /// already passed are dropped.
+    pub fn expect(&mut self, checksum: FactoryChecksum) {
+        self.expected.push_back(checksum);
+    }
+
+    pub fn pending(&self) -> usize {
+        self.expected.len()
+    }
+
+    fn verify(&mut self, checksum: &FactoryChecksum) -> Option<FactoryDesync> {
+        while self.expected.front().map_or(false, |v| v.tick < checksum.tick) {
+            self.expected.pop_front();
+        }
+
+        if self.expected.front()?.tick != checksum.tick { return None; }
+        let expected = self.expected.pop_front().unwrap();
+        checksum.first_divergence(&expected)
+    }
+
+}
+
+/// Checksums the factory at the end of every tick. Add after `FactoryPlugins`,
+/// whose stages are already single threaded.
+pub struct FactoryDeterminismPlugin;
+
+impl Plugin for FactoryDeterminismPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<FactoryChecksum>()
+            .init_resource::<ChecksumBuffers>()
+            .init_resource::<DesyncDetector>()
+            .add_event::<FactoryDesync>()
+            .add_system_to_stage(FactoryStageInternal::Machine, update_checksum.exclusive_system().at_end());
+    }
+}
+
+pub fn update_checksum(world: &mut World) {
+    let checksum = FactoryChecksum::compute(world);
+    if let Some(desync) = world.get_resource_mut::<DesyncDetector>().and_then(|mut v| v.verify(&checksum)) {
+        world.get_resource_mut::<Events<FactoryDesync>>().unwrap().send(desync);
+    }
+    world.insert_resource(checksum);
+}
@@ -0,0 +1,122 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+use crate::factory::{ConnectionBuilder, FactoryPlugins, PipeSimple, ResourceUUID, MACHINE_SOURCE, MACHINE_SINK, MACHINE_PASSTHROUGH, spawn_machine};
+
+/// Machines holding `counts` of SPEED in port A, after `skip` other entities.
+fn build_world(tick: u32, skip: u32, counts: &[u16]) -> (World, Vec<Entity>) {
+    let mut world = World::new();
+    world.insert_resource(FactoryTick(tick));
+    for _ in 0..skip { world.spawn(); }
+
+    let speed    = ResourceID::intern(ResourceUUID::new("SPEED"));
+    let entities = counts.iter().map(|&count| {
+        let mut ports = Ports::default();
+        ports.get_mut(PortID::A).set(speed, count);
+        world.spawn().insert(ports).id()
+    }).collect();
+    (world, entities)
+}
+
+fn checksum(tick: u32, counts: &[u16]) -> FactoryChecksum {
+    FactoryChecksum::compute(&mut build_world(tick, 0, counts).0)
+}
+
+#[test]
+fn fnv_reference() {
+    let mut hasher = Fnv64::default();
+    hasher.write(b"a");
+    assert_eq!(hasher.finish(), 0xaf63dc4c8601ec8c);
+}
+
+#[test]
+fn divergence() {
+    let base = checksum(5, &[1, 2, 3]);
+    let hash = |checksum: &FactoryChecksum, i: usize| Some(checksum.entities[i].2);
+    assert_eq!(base.first_divergence(&base.clone()), None);
+
+    // Matched by position, whatever the entities are in the other world.
+    let (mut world, _) = build_world(5, 100, &[1, 2, 3]);
+    assert_eq!(base.first_divergence(&FactoryChecksum::compute(&mut world)), None);
+    let (mut world, _) = build_world(5, 100, &[1, 2, 4]);
+    let moved = FactoryChecksum::compute(&mut world);
+    assert_eq!(base.first_divergence(&moved), Some(FactoryDesync{ tick: 5, index: Some(2), entity: Some(base.entities[2].1), local: hash(&base, 2), expected: hash(&moved, 2) }));
+
+    let changed = checksum(5, &[1, 4, 4]);
+    assert_eq!(base.first_divergence(&changed), Some(FactoryDesync{ tick: 5, index: Some(1), entity: Some(base.entities[1].1), local: hash(&base, 1), expected: hash(&changed, 1) }));
+
+    let (mut world, order) = build_world(5, 0, &[1, 2, 3]);
+    world.despawn(order[1]);
+    let missing = FactoryChecksum::compute_ordered(&world, &order, &mut ChecksumBuffers::default());
+    assert_eq!(missing.entities.iter().map(|v| v.0).collect::<Vec<_>>(), vec![0, 2]);
+    assert_eq!(base.first_divergence(&missing), Some(FactoryDesync{ tick: 5, index: Some(1), entity: Some(base.entities[1].1), local: hash(&base, 1), expected: None }));
+    assert_eq!(missing.first_divergence(&base), Some(FactoryDesync{ tick: 5, index: Some(1), entity: None, local: None, expected: hash(&base, 1) }));
+
+    let tick = checksum(6, &[1, 2, 3]);
+    assert_eq!(base.first_divergence(&tick).map(|v| v.entity), Some(None));
+}
+
+#[test]
+fn detector_skips_past_ticks() {
+    let mut detector = DesyncDetector::default();
+    detector.expect(checksum(3, &[1]));
+    detector.expect(checksum(4, &[2]));
+    detector.expect(checksum(6, &[3]));
+
+    assert_eq!(detector.verify(&checksum(4, &[2])), None);
+    assert_eq!(detector.pending(), 1);
+    assert_eq!(detector.verify(&checksum(5, &[9])), None);
+    assert_eq!(detector.verify(&checksum(6, &[9])).and_then(|v| v.index), Some(0));
+    assert_eq!(detector.pending(), 0);
+}
+
+fn build_app() -> App {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins).add_plugin(FactoryDeterminismPlugin);
+
+    let speed = ResourceID::intern(ResourceUUID::new("SPEED"));
+    let world = &mut app.world;
+    let source      = spawn_machine(world, MACHINE_SOURCE, Some(speed)).unwrap();
+    let passthrough = spawn_machine(world, MACHINE_PASSTHROUGH, None).unwrap();
+    let sink        = spawn_machine(world, MACHINE_SINK, None).unwrap();
+    ConnectionBuilder::new(3).recv_from(source, PortID::B).send_to(passthrough, PortID::A).build::<PipeSimple>(world).unwrap();
+    ConnectionBuilder::new(5).recv_from(passthrough, PortID::B).send_to(sink, PortID::A).build::<PipeSimple>(world).unwrap();
+    app
+}
+
+#[test]
+fn identical_runs() {
+    let mut a = build_app();
+    let mut b = build_app();
+    for _ in 0..50 {
+        b.update();
+        let expected = b.world.get_resource::<FactoryChecksum>().unwrap().clone();
+        a.world.get_resource_mut::<DesyncDetector>().unwrap().expect(expected);
+        a.update();
+
+        assert_eq!(a.world.get_resource::<DesyncDetector>().unwrap().pending(), 0);
+        assert_eq!(a.world.get_resource::<Events<FactoryDesync>>().unwrap().iter_current_update_events().count(), 0);
+    }
+    assert_eq!(a.world.get_resource::<FactoryChecksum>().unwrap().tick, 50);
+}
+
+#[test]
+fn reports_divergent_entity() {
+    let mut a = build_app();
+    let mut b = build_app();
+    for _ in 0..10 {
+        a.update();
+        b.update();
+    }
+
+    let sink = b.world.query_filtered::<Entity, With<crate::factory::MachineSink>>().iter(&b.world).next().unwrap();
+    b.world.get_mut::<Ports>(sink).unwrap().get_mut(PortID::C).set(ResourceID::intern(ResourceUUID::new("SPEED")), 7);
+    b.update();
+    a.update();
+
+    let expected = b.world.get_resource::<FactoryChecksum>().unwrap().clone();
+    let desync   = a.world.get_resource::<FactoryChecksum>().unwrap().first_divergence(&expected).unwrap();
+    assert_eq!(desync.entity, Some(sink));
+}
@@ -0,0 +1,87 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use bevy::{prelude::*, utils::HashSet};
+
+use super::{ConnectionIndex, FactoryStage, FactoryStageInternal, FactorySystem, FactoryTick, FlowMonitor, FlowStatus, LateRemovals, Machine, PortFilter, Ports};
+
+mod schedule;
+pub use schedule::*;
+
+/// Marks a machine or connection that can't make progress until the ports it
+/// touches change, so its systems skip it. Removed by `wake_dormant` when a
+/// machine's `Ports`, `PortFilter` or `Machine` change, waking the machine
+/// and every connection attached to it, or when a connection's head packet
+/// arrives according to the `ArrivalSchedule`.
+///
+/// The `FlowMonitor` isn't updated while asleep, read it through
+/// `Dormant::caught_up` to include the ticks slept through.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dormant {
+    /// Tick the entity last ran on.
+    pub since:  u32,
+    /// Status recorded each tick it sleeps through, once woken.
+    pub status: FlowStatus,
+}
+
+impl Dormant {
+
+    /// Ticks slept through, up to and including `tick`.
+    pub fn slept(&self, tick: u32) -> u32 {
+        tick.saturating_sub(self.since)
+    }
+
+    /// The monitor as it would read if the sleep were recorded up to `tick`.
+    pub fn caught_up(&self, monitor: &FlowMonitor, tick: u32) -> FlowMonitor {
+        let mut monitor = monitor.clone();
+        monitor.record_for(self.status, self.slept(tick));
+        monitor
+    }
+
+}
+
+/// Puts an entity to sleep at the end of the current stage.
+pub fn sleep(commands: &mut Commands, entity: Entity, tick: u32, status: FlowStatus) {
+    commands.entity(entity).insert(Dormant{ since: tick, status });
+}
+
+pub fn wake_dormant(
+    mut commands:   Commands,
+    tick:           Res<FactoryTick>,
+    index:          Res<ConnectionIndex>,
+    mut schedule:   ResMut<ArrivalSchedule>,
+    mut dormant:    Query<(&Dormant, Option<&mut FlowMonitor>)>,
+    changed:        Query<Entity, (With<Ports>, Or<(Changed<Ports>, Changed<PortFilter>, Changed<Machine>)>)>,
+    (removed_ports, removed_filter, late): (RemovedComponents<Ports>, RemovedComponents<PortFilter>, Res<LateRemovals>),
+) {
+    let machines = changed.iter().chain(removed_ports.iter()).chain(removed_filter.iter()).chain(late.iter());
+    let attached = machines.flat_map(|machine| std::iter::once(machine).chain(index.attached(machine)));
+
+    // It's about to run this tick, so the sleep ended on the previous one.
+    let mut woken = HashSet::default();
+    for entity in schedule.take_due(tick.0).into_iter().chain(attached) {
+        if !woken.insert(entity) { continue; }
+        if let Ok((dormant, monitor)) = dormant.get_mut(entity) {
+            if let Some(mut monitor) = monitor { monitor.record_for(dormant.status, dormant.slept(tick.0.saturating_sub(1))); }
+            commands.entity(entity).remove::<Dormant>();
+        }
+    }
+}
+
+
+pub struct FactoryDormancyPlugin;
+
+impl Plugin for FactoryDormancyPlugin {
+    fn build(&self, app: &mut App) {
+        // Wakes after commands for machines, then again after machines have
+        // run for connections, so nothing sleeps through a tick it would have
+        // acted on. Removals after that are picked up from `LateRemovals` on
+        // the next tick, waking anything woken before harmlessly again.
+        app.schedule.add_stage_after(FactoryStage::Machine, FactoryStageInternal::Wake, SystemStage::single_threaded());
+        app.add_system_to_stage(FactoryStageInternal::Tick, wake_dormant.after(FactorySystem::UpdateTick));
+        app.add_system_to_stage(FactoryStageInternal::Wake, wake_dormant);
+    }
+}
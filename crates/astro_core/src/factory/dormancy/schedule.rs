@@ -0,0 +1,58 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::prelude::Entity;
+
+/// Number of ticks covered by one turn of the wheel.
+pub const ARRIVAL_WHEEL_SLOTS: u32 = 256;
+
+/// Timing wheel of dormant connections to wake when their head packet
+/// arrives. Entries further out than a turn stay in their slot until the
+/// wheel comes round to their tick.
+pub struct ArrivalSchedule {
+    slots:   Vec<Vec<(u32, Entity)>>,
+    current: u32,
+    len:     usize,
+}
+
+impl Default for ArrivalSchedule {
+    fn default() -> Self {
+        Self{ slots: vec![Vec::new(); ARRIVAL_WHEEL_SLOTS as usize], current: 0, len: 0 }
+    }
+}
+
+impl ArrivalSchedule {
+
+    /// Schedules the entity for `tick`, or the next tick taken if it's passed.
+    pub fn schedule(&mut self, tick: u32, entity: Entity) {
+        let tick = tick.max(self.current.wrapping_add(1));
+        self.slots[(tick % ARRIVAL_WHEEL_SLOTS) as usize].push((tick, entity));
+        self.len += 1;
+    }
+
+    /// Removes and returns everything scheduled up to and including `tick`.
+    pub fn take_due(&mut self, tick: u32) -> Vec<Entity> {
+        let mut due = Vec::new();
+        if tick <= self.current { return due; }
+
+        let turns = (tick - self.current).min(ARRIVAL_WHEEL_SLOTS);
+        for offset in 1..=turns {
+            let slot = &mut self.slots[(self.current.wrapping_add(offset) % ARRIVAL_WHEEL_SLOTS) as usize];
+            slot.retain(|&(at, entity)| if at <= tick { due.push(entity); false } else { true });
+        }
+
+        self.len    -= due.len();
+        self.current = tick;
+        due
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+}
@@ -0,0 +1,141 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+use crate::factory::{FactoryCommand, FactoryCommandQueue, FactoryPlugins, PortID, ResourceID, ResourceUUID, MACHINE_SOURCE, MACHINE_SINK, execute_command, worst_bottlenecks};
+
+fn build_app() -> (App, Entity, Entity, Entity) {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+
+    let source = execute_command(&mut app.world, FactoryCommand::PlaceMachine{ kind: MACHINE_SOURCE, recipe: None }).unwrap().unwrap();
+    let sink   = execute_command(&mut app.world, FactoryCommand::PlaceMachine{ kind: MACHINE_SINK,   recipe: None }).unwrap().unwrap();
+    let pipe   = execute_command(&mut app.world, FactoryCommand::Connect{ from: source, from_port: PortID::B, to: sink, to_port: PortID::A, length: 3 }).unwrap().unwrap();
+    (app, source, sink, pipe)
+}
+
+fn queue(app: &mut App, command: FactoryCommand) {
+    app.world.get_resource_mut::<FactoryCommandQueue>().unwrap().push(command);
+}
+
+#[test]
+fn catch_up() {
+    let mut monitor = FlowMonitor::default();
+    monitor.record(FlowStatus::InputStarved);
+    monitor.record_for(FlowStatus::InputStarved, 4);
+    monitor.record_for(FlowStatus::Running, 0);
+    assert_eq!((monitor.status(), monitor.consecutive_ticks(), monitor.ticks_in(FlowStatus::InputStarved)), (FlowStatus::InputStarved, 5, 5));
+
+    monitor.record_for(FlowStatus::OutputBlocked, 2);
+    assert_eq!((monitor.status(), monitor.consecutive_ticks(), monitor.stalled_ticks()), (FlowStatus::OutputBlocked, 2, 7));
+}
+
+#[test]
+fn sleeps_until_ports_change() {
+    let (mut app, source, sink, pipe) = build_app();
+    for _ in 0..3 { app.update(); }
+    assert!([source, sink, pipe].iter().all(|&e| app.world.get::<Dormant>(e).is_some()));
+
+    let speed = ResourceID::intern(ResourceUUID::new("SPEED"));
+    queue(&mut app, FactoryCommand::SetRecipe{ machine: source, recipe: Some(speed) });
+    app.update();
+    assert!(app.world.get::<Dormant>(source).is_none());
+    assert!(app.world.get::<Dormant>(pipe).is_none());
+    assert!(app.world.get::<Dormant>(sink).is_some());
+    assert_eq!(app.world.get::<FlowMonitor>(pipe).unwrap().ticks_in(FlowStatus::InputStarved), 3);
+    assert_eq!(app.world.get::<FlowMonitor>(pipe).unwrap().status(), FlowStatus::Running);
+
+    for _ in 0..10 { app.update(); }
+    let monitor = app.world.get::<FlowMonitor>(sink).unwrap();
+    assert!(app.world.get::<Dormant>(sink).is_none());
+    assert_eq!(monitor.status(), FlowStatus::Running);
+    assert_eq!(monitor.ticks_in(FlowStatus::Running) + monitor.ticks_in(FlowStatus::InputStarved), 14);
+}
+
+#[test]
+fn wakes_when_target_removed() {
+    let (mut app, source, _, pipe) = build_app();
+    for _ in 0..2 { app.update(); }
+    assert!(app.world.get::<Dormant>(pipe).is_some());
+
+    queue(&mut app, FactoryCommand::Remove{ entity: source });
+    app.update();
+    assert_eq!(app.world.get::<FlowMonitor>(pipe).map(|v| v.status()), Some(FlowStatus::DanglingTarget));
+}
+
+#[derive(Component)]
+struct DespawnLate;
+
+fn despawn_late(mut commands: Commands, query: Query<Entity, With<DespawnLate>>) {
+    query.iter().for_each(|e| commands.entity(e).despawn());
+}
+
+#[test]
+fn wakes_after_late_removal() {
+    let (mut app, source, _, pipe) = build_app();
+    app.add_system_to_stage(CoreStage::PostUpdate, despawn_late);
+    for _ in 0..2 { app.update(); }
+    assert!(app.world.get::<Dormant>(pipe).is_some());
+
+    app.world.entity_mut(source).insert(DespawnLate);
+    app.update();
+    assert!(app.world.get_entity(source).is_none());
+    app.update();
+    assert_eq!(app.world.get::<FlowMonitor>(pipe).map(|v| v.status()), Some(FlowStatus::DanglingTarget));
+}
+
+#[test]
+fn bottlenecks_while_asleep() {
+    let (mut app, _, sink, pipe) = build_app();
+    for _ in 0..5 { app.update(); }
+    assert_eq!(app.world.get::<FlowMonitor>(pipe).unwrap().stalled_ticks(), 1);
+
+    let tick      = app.world.get_resource::<FactoryTick>().unwrap().0;
+    let mut query = app.world.query::<(Entity, &FlowMonitor, &Dormant)>();
+    let worst     = worst_bottlenecks(query.iter(&app.world).map(|(e, monitor, dormant)| (e, dormant.caught_up(monitor, tick))), 3);
+    let stalled   = |entity: Entity| worst.iter().find(|v| v.entity == entity).map(|v| (v.status, v.stalled_ticks, v.consecutive_ticks));
+    assert_eq!(stalled(pipe), Some((FlowStatus::InputStarved, 5, 5)));
+    assert_eq!(stalled(sink), Some((FlowStatus::InputStarved, 5, 5)));
+}
+
+#[test]
+fn timing_wheel() {
+    let (a, b, c, d) = (Entity::from_raw(1), Entity::from_raw(2), Entity::from_raw(3), Entity::from_raw(4));
+    let mut schedule = ArrivalSchedule::default();
+    schedule.schedule(3, a);
+    schedule.schedule(3 + ARRIVAL_WHEEL_SLOTS, b);
+    schedule.schedule(5, c);
+    assert_eq!(schedule.len(), 3);
+
+    assert_eq!(schedule.take_due(2), vec![]);
+    assert_eq!(schedule.take_due(4), vec![a]);
+    schedule.schedule(1, d);
+    assert_eq!(schedule.take_due(5), vec![c, d]);
+    assert_eq!(schedule.take_due(5), vec![]);
+    assert_eq!(schedule.take_due(2 + ARRIVAL_WHEEL_SLOTS), vec![]);
+    assert_eq!(schedule.take_due(10_000), vec![b]);
+    assert!(schedule.is_empty());
+}
+
+#[test]
+fn wakes_on_arrival() {
+    let (mut app, source, sink, pipe) = build_app();
+    let speed = ResourceID::intern(ResourceUUID::new("SPEED"));
+    app.update();
+
+    queue(&mut app, FactoryCommand::SetRecipe{ machine: source, recipe: Some(speed) });
+    app.update();
+    queue(&mut app, FactoryCommand::SetRecipe{ machine: source, recipe: None });
+    app.update();
+    app.update();
+
+    // Enqueued on tick 2, so it arrives on tick 5 with nothing moving between.
+    assert_eq!(app.world.get::<Dormant>(pipe), Some(&Dormant{ since: 3, status: FlowStatus::Running }));
+    assert_eq!(app.world.get::<Ports>(sink).unwrap().get(PortID::A).count(), 0);
+
+    app.update();
+    assert!(app.world.get::<Dormant>(pipe).is_none());
+    assert_eq!(app.world.get::<Ports>(sink).unwrap().get(PortID::A).count(), 1);
+    assert!(app.world.get_resource::<ArrivalSchedule>().unwrap().is_empty());
+}
@@ -0,0 +1,204 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::{prelude::{Changed, Entity, IVec2, Or, Query, RemovedComponents, ResMut, World}, utils::HashMap};
+
+use super::{Footprint, GridError, GridPosition, PipePath, Rotation};
+
+/// Width and height of a chunk in tiles.
+pub const GRID_CHUNK_SIZE: i32 = 32;
+
+/// Placed machines and pipes by tile, bucketed into chunks.
+///
+/// Like `ConnectionIndex`, entries may briefly outlive their entity until
+/// they're resynced at the end of the frame.
+#[derive(Default)]
+pub struct FactorySpatialIndex {
+    chunks:   HashMap<IVec2, GridChunk>,
+    entities: HashMap<Entity, Vec<IVec2>>,
+}
+
+#[derive(Debug, Default)]
+pub struct GridChunk {
+    tiles:    HashMap<IVec2, Entity>,
+    entities: Vec<Entity>,
+}
+
+impl GridChunk {
+
+    pub fn get(&self, tile: IVec2) -> Option<Entity> {
+        self.tiles.get(&tile).copied()
+    }
+
+    /// Entities with at least one tile in the chunk, in the order added.
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = (IVec2, Entity)> + '_ {
+        self.tiles.iter().map(|(&tile, &e)| (tile, e))
+    }
+
+}
+
+/// The chunk containing a tile.
+pub fn chunk_of(tile: IVec2) -> IVec2 {
+    IVec2::new(tile.x.div_euclid(GRID_CHUNK_SIZE), tile.y.div_euclid(GRID_CHUNK_SIZE))
+}
+
+impl FactorySpatialIndex {
+
+    pub fn get(&self, tile: IVec2) -> Option<Entity> {
+        self.chunks.get(&chunk_of(tile)).and_then(|v| v.get(tile))
+    }
+
+    /// Tiles indexed for an entity.
+    pub fn tiles_of(&self, entity: Entity) -> &[IVec2] {
+        self.entities.get(&entity).map_or(&[], |v| v.as_slice())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn chunk(&self, chunk: IVec2) -> Option<&GridChunk> {
+        self.chunks.get(&chunk)
+    }
+
+    /// Every non-empty chunk, in no particular order.
+    pub fn chunks(&self) -> impl Iterator<Item = (IVec2, &GridChunk)> {
+        self.chunks.iter().map(|(&coord, chunk)| (coord, chunk))
+    }
+
+    /// Entities with a tile inside the inclusive rectangle, sorted.
+    pub fn in_rect(&self, min: IVec2, max: IVec2) -> Vec<Entity> {
+        self.collect(min, max, |_| true)
+    }
+
+    /// Entities with a tile within `radius` tiles of `center`, sorted.
+    pub fn in_radius(&self, center: IVec2, radius: u32) -> Vec<Entity> {
+        let r = radius.min(i32::MAX as u32) as i32;
+        let radius_sq = (radius as i64) * (radius as i64);
+        let min = IVec2::new(center.x.saturating_sub(r), center.y.saturating_sub(r));
+        let max = IVec2::new(center.x.saturating_add(r), center.y.saturating_add(r));
+        self.collect(min, max, |tile| {
+            let (dx, dy) = (tile.x as i64 - center.x as i64, tile.y as i64 - center.y as i64);
+            dx * dx + dy * dy <= radius_sq
+        })
+    }
+
+    fn collect(&self, min: IVec2, max: IVec2, filter: impl Fn(IVec2) -> bool) -> Vec<Entity> {
+        let (min_chunk, max_chunk) = (chunk_of(min), chunk_of(max));
+        let inside = |tile: IVec2| tile.x >= min.x && tile.y >= min.y && tile.x <= max.x && tile.y <= max.y && filter(tile);
+
+        let mut result = Vec::new();
+        let mut visit  = |chunk: &GridChunk| result.extend(chunk.tiles().filter(|&(tile, _)| inside(tile)).map(|(_, e)| e));
+
+        // Large areas are mostly empty chunks, so walk the chunks that exist.
+        let span = (max_chunk - min_chunk + IVec2::ONE).max(IVec2::ZERO);
+        if (span.x as u64) * (span.y as u64) > self.chunks.len() as u64 {
+            let overlaps = |c: IVec2| c.x >= min_chunk.x && c.y >= min_chunk.y && c.x <= max_chunk.x && c.y <= max_chunk.y;
+            self.chunks.iter().filter(|(&c, _)| overlaps(c)).for_each(|(_, chunk)| visit(chunk));
+        } else {
+            for y in min_chunk.y..=max_chunk.y {
+                for x in min_chunk.x..=max_chunk.x {
+                    if let Some(chunk) = self.chunks.get(&IVec2::new(x, y)) { visit(chunk); }
+                }
+            }
+        }
+
+        result.sort_unstable();
+        result.dedup();
+        result
+    }
+
+    /// Replaces the tiles indexed for an entity, leaving the index untouched
+    /// if another entity is indexed on any of them.
+    pub fn sync(&mut self, entity: Entity, tiles: impl IntoIterator<Item = IVec2>) -> Result<(), GridError> {
+        let tiles = tiles.into_iter().collect::<Vec<_>>();
+        for &tile in tiles.iter() {
+            if let Some(by) = self.get(tile).filter(|&v| v != entity) { return Err(GridError::Occupied{ tile, by }); }
+        }
+
+        self.remove(entity);
+        if tiles.is_empty() { return Ok(()); }
+
+        for &tile in tiles.iter() {
+            let chunk = self.chunks.entry(chunk_of(tile)).or_default();
+            chunk.tiles.insert(tile, entity);
+            if !chunk.entities.contains(&entity) { chunk.entities.push(entity); }
+        }
+        self.entities.insert(entity, tiles);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        for tile in self.entities.remove(&entity).unwrap_or_default() {
+            let coord = chunk_of(tile);
+            if let Some(chunk) = self.chunks.get_mut(&coord) {
+                if chunk.tiles.get(&tile) == Some(&entity) { chunk.tiles.remove(&tile); }
+                chunk.entities.retain(|&v| v != entity);
+                if chunk.tiles.is_empty() { self.chunks.remove(&coord); }
+            }
+        }
+    }
+
+}
+
+/// Tiles an entity occupies according to its components.
+pub fn placement_tiles(world: &World, entity: Entity) -> Vec<IVec2> {
+    let entity = match world.get_entity(entity) {
+        Some(v) => v,
+        None    => return Vec::new(),
+    };
+    match (entity.get::<GridPosition>(), entity.get::<Rotation>(), entity.get::<Footprint>(), entity.get::<PipePath>()) {
+        (Some(position), Some(&rotation), Some(footprint), _) => footprint.tiles(position.0, rotation).collect(),
+        (_, _, _, Some(path))                                 => path.0.clone(),
+        _                                                     => Vec::new(),
+    }
+}
+
+/// Indexes an entity immediately rather than at the end of the frame,
+/// evicting entries of entities that have since moved off or despawned.
+pub fn index_placement(world: &mut World, entity: Entity) -> Result<(), GridError> {
+    let tiles = placement_tiles(world, entity);
+    let stale = match world.get_resource::<FactorySpatialIndex>() {
+        Some(index) => tiles.iter()
+            .filter_map(|&tile| index.get(tile).filter(|&v| v != entity && !placement_tiles(world, v).contains(&tile)))
+            .collect::<Vec<_>>(),
+        None => return Ok(()),
+    };
+
+    let mut index = world.get_resource_mut::<FactorySpatialIndex>().unwrap();
+    stale.into_iter().for_each(|v| index.remove(v));
+    index.sync(entity, tiles)
+}
+
+pub fn update_spatial_index(
+    mut index:        ResMut<FactorySpatialIndex>,
+    machines:         Query<(Entity, &GridPosition, &Rotation, &Footprint), Or<(Changed<GridPosition>, Changed<Rotation>, Changed<Footprint>)>>,
+    pipes:            Query<(Entity, &PipePath), Changed<PipePath>>,
+    removed_position: RemovedComponents<GridPosition>,
+    removed_path:     RemovedComponents<PipePath>,
+) {
+    // Clear everything that moved first, so swapping tiles isn't an overlap.
+    let moved = machines.iter().map(|(e, ..)| e).chain(pipes.iter().map(|(e, _)| e));
+    for entity in removed_position.iter().chain(removed_path.iter()).chain(moved) {
+        index.remove(entity);
+    }
+
+    for (entity, position, &rotation, footprint) in machines.iter() {
+        let result = index.sync(entity, footprint.tiles(position.0, rotation));
+        debug_assert!(result.is_ok(), "overlapping placement: {:?}", result);
+    }
+
+    for (entity, path) in pipes.iter() {
+        let result = index.sync(entity, path.0.iter().copied());
+        debug_assert!(result.is_ok(), "overlapping placement: {:?}", result);
+    }
+}
@@ -0,0 +1,260 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use bevy::{prelude::{Changed, Component, CoreStage, Entity, IVec2, Or, Plugin, World}, utils::{HashMap, HashSet}};
+
+use super::{ConnectionBuilder, ConnectionError, ConnectionEnd, MachineError, MachineUUID, PipeSimple, PortID, ResourceID, MAX_PIPE_LENGTH, spawn_machine};
+
+mod index;
+pub use index::*;
+
+/// Tile a machine is anchored at, footprint tiles extend from it.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GridPosition(pub IVec2);
+
+/// Clockwise quarter turns of a footprint around its anchor tile.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rotation {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Self::North
+    }
+}
+
+impl std::str::FromStr for Rotation {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "North" | "north" | "N" => Ok(Rotation::North),
+            "East"  | "east"  | "E" => Ok(Rotation::East),
+            "South" | "south" | "S" => Ok(Rotation::South),
+            "West"  | "west"  | "W" => Ok(Rotation::West),
+            _ => Err("Invalid rotation"),
+        }
+    }
+}
+
+impl Rotation {
+
+    pub const ALL: [Rotation; 4] = [Rotation::North, Rotation::East, Rotation::South, Rotation::West];
+
+    /// Rotates an offset from the anchor tile, +Y is north.
+    pub fn apply(self, offset: IVec2) -> IVec2 {
+        match self {
+            Self::North => offset,
+            Self::East  => IVec2::new( offset.y, -offset.x),
+            Self::South => IVec2::new(-offset.x, -offset.y),
+            Self::West  => IVec2::new(-offset.y,  offset.x),
+        }
+    }
+
+    pub fn clockwise(self) -> Self {
+        Self::ALL[(self as usize + 1) % 4]
+    }
+
+}
+
+/// Tiles a machine occupies facing north, and the tile outside it each port
+/// connects through.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Footprint {
+    pub size:  IVec2,
+    pub ports: [IVec2; 4],
+}
+
+impl Default for Footprint {
+    fn default() -> Self {
+        Self::UNIT
+    }
+}
+
+impl Footprint {
+
+    /// A single tile with ports A to D to the west, east, north and south.
+    pub const UNIT: Footprint = Footprint{
+        size:  IVec2::ONE,
+        ports: [IVec2::new(-1, 0), IVec2::new(1, 0), IVec2::new(0, 1), IVec2::new(0, -1)],
+    };
+
+    pub fn tiles(&self, position: IVec2, rotation: Rotation) -> impl Iterator<Item = IVec2> {
+        let size = self.size;
+        (0..size.y).flat_map(move |y| (0..size.x).map(move |x| position + rotation.apply(IVec2::new(x, y))))
+    }
+
+    pub fn port_tile(&self, position: IVec2, rotation: Rotation, port: PortID) -> IVec2 {
+        position + rotation.apply(self.ports[port as usize])
+    }
+
+}
+
+/// Footprints by machine kind, kinds without one are a single tile.
+#[derive(Default)]
+pub struct FootprintRegistry(HashMap<MachineUUID, Footprint>);
+
+impl FootprintRegistry {
+
+    pub fn register(&mut self, kind: MachineUUID, footprint: Footprint) {
+        self.0.insert(kind, footprint);
+    }
+
+    pub fn get(&self, kind: MachineUUID) -> Footprint {
+        self.0.get(&kind).copied().unwrap_or_default()
+    }
+
+}
+
+pub fn register_footprint(app: &mut bevy::prelude::App, kind: MachineUUID, footprint: Footprint) {
+    app.world.get_resource_or_insert_with(FootprintRegistry::default).register(kind, footprint);
+}
+
+/// Tiles a connection runs along, from the tile at the port it takes from to
+/// the tile at the port it delivers into. Each tile is one tick of travel.
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct PipePath(pub Vec<IVec2>);
+
+impl PipePath {
+
+    pub fn length(&self) -> u32 {
+        self.0.len() as u32
+    }
+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridError {
+    Occupied{ tile: IVec2, by: Entity },
+    NotPlaced(Entity),
+    /// Consecutive tiles of a path must share an edge.
+    Disjoint{ from: IVec2, to: IVec2 },
+    EmptyPath,
+    /// A path can't cross itself.
+    Revisited(IVec2),
+    /// The path doesn't end at the tile of the port it connects to.
+    PortMismatch{ end: ConnectionEnd, expected: IVec2, found: IVec2 },
+    Machine(MachineError),
+    Connection(ConnectionError),
+}
+
+impl std::fmt::Display for GridError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Occupied{ tile, by }                 => write!(f, "tile {} is occupied by {}", tile, by.id()),
+            Self::NotPlaced(e)                         => write!(f, "entity {} has no grid position", e.id()),
+            Self::Disjoint{ from, to }                 => write!(f, "path tiles {} and {} aren't adjacent", from, to),
+            Self::EmptyPath                            => write!(f, "path has no tiles"),
+            Self::Revisited(tile)                      => write!(f, "path visits tile {} more than once", tile),
+            Self::PortMismatch{ end, expected, found } => write!(f, "{:?} end of path is at {}, expected {}", end, found, expected),
+            Self::Machine(e)                           => e.fmt(f),
+            Self::Connection(e)                        => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for GridError {}
+
+impl From<MachineError> for GridError {
+    fn from(e: MachineError) -> Self {
+        Self::Machine(e)
+    }
+}
+
+impl From<ConnectionError> for GridError {
+    fn from(e: ConnectionError) -> Self {
+        Self::Connection(e)
+    }
+}
+
+/// The machine or connection occupying a tile, from the `FactorySpatialIndex`
+/// if there is one. Placements changed since the last frame aren't indexed
+/// until `Last`, so those are checked directly.
+pub fn tile_occupant(world: &mut World, tile: IVec2) -> Option<Entity> {
+    if world.contains_resource::<FactorySpatialIndex>() {
+        let moved = world.query_filtered::<Entity, Or<(Changed<GridPosition>, Changed<Rotation>, Changed<Footprint>, Changed<PipePath>)>>()
+            .iter(world)
+            .find(|&e| placement_tiles(world, e).contains(&tile));
+        let index = world.get_resource::<FactorySpatialIndex>().unwrap();
+        return moved.or_else(|| index.get(tile).filter(|&e| placement_tiles(world, e).contains(&tile)));
+    }
+
+    let machine = world.query::<(Entity, &GridPosition, &Rotation, &Footprint)>()
+        .iter(world)
+        .find(|(_, position, &rotation, footprint)| footprint.tiles(position.0, rotation).any(|v| v == tile))
+        .map(|(e, ..)| e);
+
+    machine.or_else(|| world.query::<(Entity, &PipePath)>().iter(world).find(|(_, path)| path.0.contains(&tile)).map(|(e, _)| e))
+}
+
+/// The tile outside a placed machine that the port connects through.
+pub fn port_tile(world: &World, machine: Entity, port: PortID) -> Result<IVec2, GridError> {
+    let entity = world.get_entity(machine).ok_or(GridError::NotPlaced(machine))?;
+    match (entity.get::<GridPosition>(), entity.get::<Rotation>(), entity.get::<Footprint>()) {
+        (Some(position), Some(&rotation), Some(footprint)) => Ok(footprint.port_tile(position.0, rotation, port)),
+        _ => Err(GridError::NotPlaced(machine)),
+    }
+}
+
+/// Spawns a machine with the footprint registered for its kind, if every
+/// tile it covers is free.
+pub fn place_machine(world: &mut World, kind: MachineUUID, recipe: Option<ResourceID>, position: IVec2, rotation: Rotation) -> Result<Entity, GridError> {
+    let footprint = world.get_resource::<FootprintRegistry>().map_or_else(Footprint::default, |v| v.get(kind));
+    for tile in footprint.tiles(position, rotation) {
+        if let Some(by) = tile_occupant(world, tile) { return Err(GridError::Occupied{ tile, by }); }
+    }
+
+    let entity = spawn_machine(world, kind, recipe)?;
+    world.entity_mut(entity).insert_bundle((GridPosition(position), rotation, footprint));
+    index_placement(world, entity)?;
+    Ok(entity)
+}
+
+/// Connects two placed machines with a simple pipe along `path`, its length
+/// is the number of tiles.
+pub fn place_pipe(world: &mut World, from: Entity, from_port: PortID, to: Entity, to_port: PortID, path: Vec<IVec2>) -> Result<Entity, GridError> {
+    let (&first, &last) = path.first().zip(path.last()).ok_or(GridError::EmptyPath)?;
+    if path.len() > MAX_PIPE_LENGTH as usize {
+        return Err(ConnectionError::TooLong(u32::try_from(path.len()).unwrap_or(u32::MAX)).into());
+    }
+    for (end, machine, port, found) in [(ConnectionEnd::Recv, from, from_port, first), (ConnectionEnd::Send, to, to_port, last)] {
+        let expected = port_tile(world, machine, port)?;
+        if expected != found { return Err(GridError::PortMismatch{ end, expected, found }); }
+    }
+
+    for pair in path.windows(2) {
+        let step = (pair[1] - pair[0]).abs();
+        if step.x + step.y != 1 { return Err(GridError::Disjoint{ from: pair[0], to: pair[1] }); }
+    }
+    let mut visited = HashSet::default();
+    if let Some(&tile) = path.iter().find(|&&v| !visited.insert(v)) { return Err(GridError::Revisited(tile)); }
+    for &tile in path.iter() {
+        if let Some(by) = tile_occupant(world, tile) { return Err(GridError::Occupied{ tile, by }); }
+    }
+
+    let path   = PipePath(path);
+    let entity = ConnectionBuilder::new(path.length())
+        .recv_from(from, from_port)
+        .send_to(to, to_port)
+        .build::<PipeSimple>(world)?;
+    world.entity_mut(entity).insert(path);
+    index_placement(world, entity)?;
+    Ok(entity)
+}
+
+pub struct FactoryGridPlugin;
+
+impl Plugin for FactoryGridPlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.init_resource::<FootprintRegistry>();
+        app.init_resource::<FactorySpatialIndex>();
+        app.add_system_to_stage(CoreStage::Last, update_spatial_index);
+    }
+}
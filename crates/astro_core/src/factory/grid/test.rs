@@ -0,0 +1,157 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::prelude::{App, Entity};
+
+use super::*;
+use crate::factory::{FactoryCommand, FactoryHistory, FactoryPlugins, FactorySnapshot, Pipe, execute_command, MACHINE_SOURCE, MACHINE_SINK, MACHINE_PASSTHROUGH};
+
+fn tiles(tiles: &[(i32, i32)]) -> Vec<IVec2> {
+    tiles.iter().map(|&(x, y)| IVec2::new(x, y)).collect()
+}
+
+#[test]
+fn rotated_footprint() {
+    let footprint = Footprint{ size: IVec2::new(2, 1), ports: tiles(&[(-1, 0), (2, 0), (0, 1), (1, -1)]).try_into().unwrap() };
+    assert_eq!(footprint.tiles(IVec2::ZERO, Rotation::North).collect::<Vec<_>>(), tiles(&[(0, 0), (1, 0)]));
+    assert_eq!(footprint.tiles(IVec2::ZERO, Rotation::East).collect::<Vec<_>>(),  tiles(&[(0, 0), (0, -1)]));
+    assert_eq!(footprint.tiles(IVec2::ZERO, Rotation::South).collect::<Vec<_>>(), tiles(&[(0, 0), (-1, 0)]));
+
+    let position = IVec2::new(5, 5);
+    assert_eq!(footprint.port_tile(position, Rotation::North, PortID::B), IVec2::new(7, 5));
+    assert_eq!(footprint.port_tile(position, Rotation::East,  PortID::B), IVec2::new(5, 3));
+    assert_eq!(footprint.port_tile(position, Rotation::West,  PortID::C), IVec2::new(4, 5));
+    assert_eq!(Rotation::ALL.map(Rotation::clockwise), [Rotation::East, Rotation::South, Rotation::West, Rotation::North]);
+    assert_eq!(Rotation::ALL.map(|v| format!("{:?}", v).parse::<Rotation>()), Rotation::ALL.map(Ok));
+}
+
+#[test]
+fn placement_collisions() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+    register_footprint(&mut app, MACHINE_PASSTHROUGH, Footprint{ size: IVec2::new(2, 2), ..Footprint::UNIT });
+
+    let wide = place_machine(&mut app.world, MACHINE_PASSTHROUGH, None, IVec2::ZERO, Rotation::North).unwrap();
+    assert_eq!(place_machine(&mut app.world, MACHINE_SINK, None, IVec2::new(1, 1), Rotation::North), Err(GridError::Occupied{ tile: IVec2::new(1, 1), by: wide }));
+    assert_eq!(tile_occupant(&mut app.world, IVec2::new(2, 2)), None);
+
+    let sink = place_machine(&mut app.world, MACHINE_SINK, None, IVec2::new(2, 2), Rotation::North).unwrap();
+    assert_eq!(app.world.get::<Footprint>(sink), Some(&Footprint::UNIT));
+    assert_eq!(place_machine(&mut app.world, MACHINE_SINK, None, IVec2::new(2, 2), Rotation::East).map_err(|_| ()), Err(()));
+
+    app.world.despawn(sink);
+    assert!(place_machine(&mut app.world, MACHINE_SINK, None, IVec2::new(2, 2), Rotation::East).is_ok());
+}
+
+#[test]
+fn pipe_paths() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+
+    let source = place_machine(&mut app.world, MACHINE_SOURCE, None, IVec2::new(0, 0), Rotation::North).unwrap();
+    let sink   = place_machine(&mut app.world, MACHINE_SINK,   None, IVec2::new(4, 2), Rotation::North).unwrap();
+    let path   = tiles(&[(1, 0), (2, 0), (3, 0), (3, 1), (3, 2)]);
+
+    assert_eq!(place_pipe(&mut app.world, source, PortID::B, sink, PortID::B, path.clone()), Err(GridError::PortMismatch{ end: ConnectionEnd::Send, expected: IVec2::new(5, 2), found: IVec2::new(3, 2) }));
+    assert_eq!(place_pipe(&mut app.world, source, PortID::B, sink, PortID::A, tiles(&[(1, 0), (3, 2)])), Err(GridError::Disjoint{ from: IVec2::new(1, 0), to: IVec2::new(3, 2) }));
+    assert_eq!(place_pipe(&mut app.world, source, PortID::B, sink, PortID::A, Vec::new()), Err(GridError::EmptyPath));
+    let looped = tiles(&[(1, 0), (2, 0), (2, 1), (1, 1), (1, 0), (2, 0), (3, 0), (3, 1), (3, 2)]);
+    assert_eq!(place_pipe(&mut app.world, source, PortID::B, sink, PortID::A, looped), Err(GridError::Revisited(IVec2::new(1, 0))));
+
+    let pipe = place_pipe(&mut app.world, source, PortID::B, sink, PortID::A, path.clone()).unwrap();
+    assert_eq!(app.world.get::<PipeSimple>(pipe).unwrap().length(), 5);
+    assert_eq!(tile_occupant(&mut app.world, IVec2::new(3, 1)), Some(pipe));
+    assert_eq!(place_machine(&mut app.world, MACHINE_SINK, None, IVec2::new(2, 0), Rotation::North), Err(GridError::Occupied{ tile: IVec2::new(2, 0), by: pipe }));
+
+    let other = place_machine(&mut app.world, MACHINE_SINK, None, IVec2::new(2, -2), Rotation::North).unwrap();
+    assert_eq!(port_tile(&app.world, other, PortID::C), Ok(IVec2::new(2, -1)));
+    assert_eq!(place_pipe(&mut app.world, other, PortID::C, sink, PortID::A, tiles(&[(2, -1), (2, 0), (3, 0)])).map_err(|_| ()), Err(()));
+    assert_eq!(port_tile(&app.world, pipe, PortID::A), Err(GridError::NotPlaced(pipe)));
+}
+
+#[test]
+fn spatial_queries() {
+    let (a, b, c) = (Entity::from_raw(1), Entity::from_raw(2), Entity::from_raw(3));
+    let mut index = FactorySpatialIndex::default();
+    index.sync(a, tiles(&[(-1, -1), (0, -1)])).unwrap();
+    index.sync(b, tiles(&[(31, 0), (32, 0)])).unwrap();
+    index.sync(c, tiles(&[(100, 100)])).unwrap();
+    assert_eq!(index.sync(c, tiles(&[(0, -1)])), Err(GridError::Occupied{ tile: IVec2::new(0, -1), by: a }));
+    assert_eq!(index.tiles_of(c), tiles(&[(100, 100)]));
+
+    assert_eq!(chunk_of(IVec2::new(-1, 31)), IVec2::new(-1, 0));
+    assert_eq!(index.get(IVec2::new(32, 0)), Some(b));
+    assert_eq!(index.chunk(IVec2::new(-1, -1)).map(|v| v.entities().to_vec()), Some(vec![a]));
+    assert_eq!(index.chunks().count(), 5);
+
+    assert_eq!(index.in_rect(IVec2::new(-5, -5), IVec2::new(31, 31)), vec![a, b]);
+    assert_eq!(index.in_rect(IVec2::new(32, 0), IVec2::new(1000, 1000)), vec![b, c]);
+    assert_eq!(index.in_rect(IVec2::new(i32::MIN, i32::MIN), IVec2::new(i32::MAX, i32::MAX)), vec![a, b, c]);
+    assert_eq!(index.in_radius(IVec2::new(0, 0), 1), vec![a]);
+    assert_eq!(index.in_radius(IVec2::new(100, 98), 2), vec![c]);
+    assert_eq!(index.in_radius(IVec2::new(101, 98), 2), vec![]);
+
+    index.sync(b, tiles(&[(5, 5)])).unwrap();
+    assert_eq!(index.get(IVec2::new(32, 0)), None);
+    assert_eq!(index.tiles_of(b), tiles(&[(5, 5)]));
+    index.remove(c);
+    assert_eq!((index.len(), index.chunks().count()), (2, 3));
+}
+
+#[test]
+fn spatial_index_follows_world() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+
+    let source = place_machine(&mut app.world, MACHINE_SOURCE, None, IVec2::new(0, 0), Rotation::North).unwrap();
+    let sink   = place_machine(&mut app.world, MACHINE_SINK,   None, IVec2::new(3, 0), Rotation::North).unwrap();
+    let pipe   = place_pipe(&mut app.world, source, PortID::B, sink, PortID::A, tiles(&[(1, 0), (2, 0)])).unwrap();
+
+    let index = app.world.get_resource::<FactorySpatialIndex>().unwrap();
+    assert_eq!(index.in_rect(IVec2::new(0, 0), IVec2::new(2, 0)), vec![source, pipe]);
+
+    app.world.despawn(pipe);
+    assert_eq!(tile_occupant(&mut app.world, IVec2::new(1, 0)), None);
+    app.world.entity_mut(sink).insert(GridPosition(IVec2::new(40, 0)));
+    assert_eq!(tile_occupant(&mut app.world, IVec2::new(40, 0)), Some(sink));
+    assert_eq!(place_machine(&mut app.world, MACHINE_SINK, None, IVec2::new(40, 0), Rotation::North), Err(GridError::Occupied{ tile: IVec2::new(40, 0), by: sink }));
+    let moved = place_machine(&mut app.world, MACHINE_SINK, None, IVec2::new(3, 0), Rotation::North).unwrap();
+    app.update();
+
+    let index = app.world.get_resource::<FactorySpatialIndex>().unwrap();
+    assert_eq!(index.get(IVec2::new(1, 0)), None);
+    assert_eq!(index.get(IVec2::new(40, 0)), Some(sink));
+    assert_eq!(index.get(IVec2::new(3, 0)), Some(moved));
+    assert_eq!(index.len(), 3);
+}
+
+#[test]
+fn placement_commands() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins).insert_resource(FactoryHistory::new(8));
+    let world = &mut app.world;
+
+    let source = execute_command(world, FactoryCommand::PlaceMachineAt{ kind: MACHINE_SOURCE, recipe: None, position: IVec2::new(0, 0), rotation: Rotation::North }).unwrap().unwrap();
+    let sink   = execute_command(world, FactoryCommand::PlaceMachineAt{ kind: MACHINE_SINK,   recipe: None, position: IVec2::new(3, 0), rotation: Rotation::North }).unwrap().unwrap();
+    let pipe   = execute_command(world, FactoryCommand::PlacePipe{ from: source, from_port: PortID::B, to: sink, to_port: PortID::A, path: tiles(&[(1, 0), (2, 0)]) }).unwrap().unwrap();
+    assert_eq!(tile_occupant(world, IVec2::new(2, 0)), Some(pipe));
+
+    execute_command(world, FactoryCommand::Undo).unwrap();
+    assert_eq!(tile_occupant(world, IVec2::new(2, 0)), None);
+    execute_command(world, FactoryCommand::Redo).unwrap();
+    let pipe = tile_occupant(world, IVec2::new(2, 0)).unwrap();
+    assert_eq!(world.get::<PipePath>(pipe).map(|v| v.0.clone()), Some(tiles(&[(1, 0), (2, 0)])));
+
+    let snapshot = FactorySnapshot::capture(world).unwrap();
+    let mut other = App::new();
+    other.add_plugins(FactoryPlugins);
+    let restored = snapshot.restore(&mut other.world).unwrap();
+    let sink = restored.machines[1];
+    assert_eq!(other.world.get::<GridPosition>(sink), Some(&GridPosition(IVec2::new(3, 0))));
+    assert_eq!(other.world.get::<Rotation>(sink), Some(&Rotation::North));
+    assert_eq!(other.world.get::<Footprint>(sink), Some(&Footprint::UNIT));
+    assert_eq!(tile_occupant(&mut other.world, IVec2::new(3, 0)), Some(sink));
+    assert_eq!(tile_occupant(&mut other.world, IVec2::new(1, 0)), Some(restored.connections[0]));
+    assert_eq!(FactorySnapshot::capture(&mut other.world), Ok(snapshot));
+}
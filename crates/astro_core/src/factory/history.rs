@@ -0,0 +1,419 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, utils::HashMap};
+
+use super::{
+    CommandError, ConnectionBuilder, FactoryCommand, FactoryTick, FlowMonitor, Footprint, GridPosition, Machine, MachineUUID,
+    PipeContents, PipePath, PipeRegistry, Ports, PortFilter, PortID, PortSend, PortRecv, ReplayRecorder, ResourceID, Rotation, SharedConnection,
+    capture_connection, index_placement, is_stranded, port_connections, spawn_machine, spill_packets, take_connection
+};
+
+/// Undo and redo stacks for commands passed to `execute_command` while it's
+/// a resource. Only the most recent `depth` edits can be undone.
+///
+/// Packets in connections an edit removed are held by its inverse rather
+/// than spilled, and are spilled once the inverse is dropped.
+pub struct FactoryHistory {
+    depth:   usize,
+    undo:    VecDeque<FactoryEdit>,
+    redo:    Vec<FactoryEdit>,
+    dropped: Vec<FactoryEdit>,
+    /// Edits pushed since `begin_group`.
+    group:   Option<Vec<FactoryEdit>>,
+}
+
+impl Default for FactoryHistory {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+/// The inverse of a command, applying it returns the inverse again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FactoryEdit {
+    Spawn(Vec<SavedEntity>),
+    Despawn(Vec<Entity>),
+    SetRecipe{ machine: Entity, recipe: Option<ResourceID> },
+    SetFilter{ machine: Entity, filter: Option<PortFilter> },
+    /// Edits applied in order as a single step.
+    Batch(Vec<FactoryEdit>),
+}
+
+/// A despawned machine or connection, including the resources it held and
+/// where it was placed on the grid. A connection's packets are only saved if
+/// they were taken out of it, see `capture`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SavedEntity {
+    Machine{
+        entity:    Entity,
+        kind:      Option<MachineUUID>,
+        recipe:    Option<ResourceID>,
+        ports:     [Option<(ResourceID, u16)>; 4],
+        filter:    Option<PortFilter>,
+        placement: Option<(GridPosition, Rotation, Footprint)>,
+    },
+    Connection{ entity: Entity, pipe: &'static str, contents: PipeContents, send: Option<PortSend>, recv: Option<PortRecv>, path: Option<PipePath>, shared: bool },
+}
+
+impl FactoryHistory {
+
+    pub fn new(depth: usize) -> Self {
+        Self{ depth, undo: VecDeque::new(), redo: Vec::new(), dropped: Vec::new(), group: None }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Changes the depth, dropping the oldest edits if there are too many.
+    /// Their packets are spilled by the next command.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        self.trim();
+    }
+
+    pub fn undo_len(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn redo_len(&self) -> usize {
+        self.redo.len()
+    }
+
+    /// Drops every edit, their packets are spilled by the next command.
+    pub fn clear(&mut self) {
+        self.dropped.extend(self.group.take().into_iter().flatten());
+        self.dropped.extend(self.undo.drain(..));
+        self.dropped.append(&mut self.redo);
+    }
+
+    /// Records the inverse of a new edit, which invalidates anything undone.
+    pub fn push(&mut self, inverse: FactoryEdit) {
+        self.dropped.append(&mut self.redo);
+        match self.group.as_mut() {
+            Some(group) => group.push(inverse),
+            None        => { self.undo.push_back(inverse); self.trim(); },
+        }
+    }
+
+    /// Collects the edits pushed until `end_group` so they're undone in one
+    /// step, however many there are.
+    pub fn begin_group(&mut self) {
+        self.group.get_or_insert_with(Vec::new);
+    }
+
+    pub fn end_group(&mut self) {
+        let mut edits = match self.group.take() {
+            Some(v) => v,
+            None    => return,
+        };
+        match edits.len() {
+            0 => return,
+            1 => self.undo.extend(edits.pop()),
+            _ => self.undo.push_back(FactoryEdit::Batch(edits.into_iter().rev().collect())),
+        }
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        while self.undo.len() > self.depth { self.dropped.extend(self.undo.pop_front()); }
+    }
+
+    /// Points edits at entities respawned by an undo or redo.
+    fn remap(&mut self, map: &HashMap<Entity, Entity>) {
+        if map.is_empty() { return; }
+        self.undo.iter_mut().chain(self.redo.iter_mut()).for_each(|v| v.remap(map));
+    }
+
+}
+
+impl FactoryEdit {
+
+    /// The edit undoing `command`, captured before it's applied. `None` for
+    /// commands that spawn, their inverse is known once they have.
+    pub fn inverse_of(world: &mut World, command: &FactoryCommand) -> Result<Option<Self>, CommandError> {
+        Ok(match *command {
+            FactoryCommand::Remove{ entity } => Some(Self::Spawn(capture(world, &[entity], false)?)),
+            FactoryCommand::Disconnect{ machine, port } => {
+                let connections = port_connections(world, machine, port);
+                Some(Self::Spawn(capture(world, &connections, false)?))
+            },
+            FactoryCommand::SetRecipe{ machine, .. } => {
+                Some(Self::SetRecipe{ machine, recipe: world.get::<Machine>(machine).ok_or(CommandError::NotAMachine(machine))?.recipe })
+            },
+            FactoryCommand::ConfigurePortFilter{ machine, .. } => {
+                Some(Self::SetFilter{ machine, filter: world.get::<PortFilter>(machine).copied() })
+            },
+            _ => None,
+        })
+    }
+
+    /// Applies the edit, returning its inverse and the entities it respawned
+    /// by their previous ID. An active `ReplayRecorder` records the commands
+    /// it resolves to.
+    pub fn apply(self, world: &mut World) -> Result<(Self, HashMap<Entity, Entity>), CommandError> {
+        let mut respawned = HashMap::default();
+        let inverse = match self {
+            Self::Spawn(saved) => {
+                let replaced = saved.iter().map(SavedEntity::entity).filter(|&e| world.get_entity(e).is_some()).collect::<Vec<_>>();
+                let mut spawned = Vec::with_capacity(saved.len());
+                for saved in saved.iter() {
+                    let entity = saved.restore(world, &respawned)?;
+                    respawned.insert(saved.entity(), entity);
+                    spawned.push(entity);
+                }
+                if world.contains_resource::<ReplayRecorder>() {
+                    let tick = world.get_resource::<FactoryTick>().map_or(0, |v| v.0);
+                    world.resource_scope(|world, mut recorder: Mut<ReplayRecorder>| recorder.record_restore(world, tick, &replaced, &spawned));
+                }
+                Self::Despawn(spawned)
+            },
+            Self::Despawn(entities) => {
+                let saved = capture(world, &entities, true)?;
+                for saved in saved.iter() {
+                    world.despawn(saved.entity());
+                    record(world, FactoryCommand::Remove{ entity: saved.entity() });
+                }
+                Self::Spawn(saved)
+            },
+            Self::SetRecipe{ machine, recipe } => {
+                let mut current = world.get_mut::<Machine>(machine).ok_or(CommandError::NotAMachine(machine))?;
+                let previous = std::mem::replace(&mut current.recipe, recipe);
+                record(world, FactoryCommand::SetRecipe{ machine, recipe });
+                Self::SetRecipe{ machine, recipe: previous }
+            },
+            Self::SetFilter{ machine, filter } => {
+                let mut entity = world.get_entity_mut(machine).filter(|v| v.contains::<Ports>()).ok_or(CommandError::NotAMachine(machine))?;
+                let previous = entity.get::<PortFilter>().copied();
+                match filter {
+                    Some(filter) => { entity.insert(filter); },
+                    None         => { entity.remove::<PortFilter>(); },
+                }
+                for port in PortID::ALL {
+                    record(world, FactoryCommand::ConfigurePortFilter{ machine, port, filter: filter.and_then(|v| v.get(port)) });
+                }
+                Self::SetFilter{ machine, filter: previous }
+            },
+            Self::Batch(mut edits) => {
+                let mut inverses = Vec::with_capacity(edits.len());
+                edits.reverse();
+                while let Some(edit) = edits.pop() {
+                    let (inverse, map) = edit.apply(world)?;
+                    edits.iter_mut().chain(inverses.iter_mut()).for_each(|v: &mut Self| v.remap(&map));
+                    inverses.push(inverse);
+                    respawned.extend(map);
+                }
+                inverses.reverse();
+                Self::Batch(inverses)
+            },
+        };
+        Ok((inverse, respawned))
+    }
+
+    /// Calls `f` with every packet the edit holds.
+    fn for_each_held(&self, f: &mut impl FnMut(ResourceID)) {
+        match self {
+            Self::Spawn(saved) => for saved in saved.iter() {
+                if let SavedEntity::Connection{ contents, .. } = saved { contents.packets.iter().for_each(|&(_, v)| f(v)); }
+            },
+            Self::Batch(edits) => edits.iter().for_each(|v| v.for_each_held(f)),
+            _ => {},
+        }
+    }
+
+    fn remap(&mut self, map: &HashMap<Entity, Entity>) {
+        let get = |e: &mut Entity| if let Some(&v) = map.get(e) { *e = v; };
+        match self {
+            Self::Spawn(saved) => saved.iter_mut().for_each(|v| v.remap(map)),
+            Self::Despawn(entities) => entities.iter_mut().for_each(get),
+            Self::SetRecipe{ machine, .. } | Self::SetFilter{ machine, .. } => get(machine),
+            Self::Batch(edits) => edits.iter_mut().for_each(|v| v.remap(map)),
+        }
+    }
+
+}
+
+impl SavedEntity {
+
+    pub fn entity(&self) -> Entity {
+        match *self {
+            Self::Machine{ entity, .. } | Self::Connection{ entity, .. } => entity,
+        }
+    }
+
+    /// Spawns the entity, connecting to respawned machines where the
+    /// connection's targets were respawned. Replaces the original if it
+    /// still exists, e.g. a connection that kept delivering, which keeps the
+    /// packets it gained behind the saved ones. Any that don't fit are spilled.
+    fn restore(&self, world: &mut World, respawned: &HashMap<Entity, Entity>) -> Result<Entity, CommandError> {
+        let current = take_connection(world, self.entity()).map_or_else(Vec::new, |v| v.1.packets);
+        if world.get_entity(self.entity()).is_some() { world.despawn(self.entity()); }
+
+        match self {
+            Self::Machine{ kind, recipe, ports, filter, placement, .. } => {
+                let entity = match *kind {
+                    Some(kind) => spawn_machine(world, kind, *recipe)?,
+                    None       => world.spawn().insert_bundle((Ports::default(), FlowMonitor::default())).id(),
+                };
+
+                let mut entity = world.entity_mut(entity);
+                let mut store  = entity.get_mut::<Ports>().unwrap();
+                for (port, contents) in PortID::ALL.into_iter().zip(ports.iter()) {
+                    if let Some((resource, count)) = *contents { store.get_mut(port).set(resource, count); }
+                }
+                if let Some(filter) = filter { entity.insert(*filter); }
+                if let Some(placement) = placement { entity.insert_bundle(*placement); }
+
+                let entity = entity.id();
+                index_placement(world, entity)?;
+                Ok(entity)
+            },
+            Self::Connection{ entity, pipe, contents, send, recv, path, shared } => {
+                let descriptor = world.get_resource::<PipeRegistry>()
+                    .and_then(|v| v.get(pipe).copied())
+                    .ok_or(CommandError::NotFound(*entity))?;
+
+                let mut contents = PipeContents{ length: contents.length, packets: contents.packets.iter().copied().chain(current).collect() };
+                let overflow     = contents.packets.split_off(contents.packets.len().min(contents.length as usize));
+                spill_packets(world, overflow.into_iter().map(|v| v.1));
+
+                let target      = |e: Entity| respawned.get(&e).copied().unwrap_or(e);
+                let mut builder = ConnectionBuilder::new(contents.length).exclusive(!shared);
+                if let Some(&PortSend(e, port)) = send.as_ref() { builder = builder.send_to(target(e), port); }
+                if let Some(&PortRecv(e, port)) = recv.as_ref() { builder = builder.recv_from(target(e), port); }
+                let connection = builder.build_with(world, &descriptor, &contents)?;
+                if let Some(path) = path { world.entity_mut(connection).insert(path.clone()); }
+                index_placement(world, connection)?;
+                Ok(connection)
+            },
+        }
+    }
+
+    fn remap(&mut self, map: &HashMap<Entity, Entity>) {
+        let get = |e: &mut Entity| if let Some(&v) = map.get(e) { *e = v; };
+        match self {
+            Self::Machine{ entity, .. } => get(entity),
+            Self::Connection{ entity, send, recv, .. } => {
+                get(entity);
+                if let Some(PortSend(e, _)) = send { get(e); }
+                if let Some(PortRecv(e, _)) = recv { get(e); }
+            },
+        }
+    }
+
+}
+
+/// Undoes the most recent edit.
+pub fn undo(world: &mut World) -> Result<(), CommandError> {
+    step(world, true)
+}
+
+/// Reapplies the most recently undone edit.
+pub fn redo(world: &mut World) -> Result<(), CommandError> {
+    step(world, false)
+}
+
+fn step(world: &mut World, undo: bool) -> Result<(), CommandError> {
+    if !world.contains_resource::<FactoryHistory>() { return Err(CommandError::EmptyHistory); }
+
+    world.resource_scope(|world, mut history: Mut<FactoryHistory>| {
+        let edit = if undo { history.undo.pop_back() } else { history.redo.pop() };
+        let (inverse, respawned) = edit.ok_or(CommandError::EmptyHistory)?.apply(world)?;
+        history.remap(&respawned);
+        if undo {
+            history.redo.push(inverse);
+        } else {
+            history.undo.push_back(inverse);
+            history.trim();
+        }
+        Ok::<_, CommandError>(())
+    })?;
+    spill_dropped(world);
+    Ok(())
+}
+
+/// Spills the packets held by edits the history has dropped.
+pub(crate) fn spill_dropped(world: &mut World) {
+    let dropped = match world.get_resource_mut::<FactoryHistory>() {
+        Some(mut history) if !history.dropped.is_empty() => std::mem::take(&mut history.dropped),
+        _ => return,
+    };
+
+    let mut packets = Vec::new();
+    for edit in dropped.iter() { edit.for_each_held(&mut |v| packets.push(v)); }
+    spill_packets(world, packets);
+}
+
+/// Records a command an edit resolved to if a `ReplayRecorder` is active.
+fn record(world: &mut World, command: FactoryCommand) {
+    let tick = world.get_resource::<FactoryTick>().map_or(0, |v| v.0);
+    if let Some(mut recorder) = world.get_resource_mut::<ReplayRecorder>() { recorder.record(tick, &command, None); }
+}
+
+/// Saves the entities, along with the connections attached to any machines
+/// among them. Machines come first so they're respawned before connections.
+///
+/// Connections that can't deliver once the entities are gone are emptied,
+/// their packets are held by the saved entity instead of being spilled.
+/// Those are the connections among the entities, stranded connections, and
+/// any attached connection if `attached_despawned`. Others keep delivering
+/// and are saved empty.
+fn capture(world: &mut World, entities: &[Entity], attached_despawned: bool) -> Result<Vec<SavedEntity>, CommandError> {
+    let mut connections = entities.iter().copied().filter(|&e| world.get::<Ports>(e).is_none()).collect::<Vec<_>>();
+    let machines        = entities.iter().copied().filter(|&e| world.get::<Ports>(e).is_some()).collect::<Vec<_>>();
+    for &machine in machines.iter() {
+        for port in PortID::ALL {
+            for connection in port_connections(world, machine, port) {
+                if !connections.contains(&connection) { connections.push(connection); }
+            }
+        }
+    }
+
+    let mut result = Vec::with_capacity(machines.len() + connections.len());
+    for &entity in machines.iter() {
+        let ports   = world.get::<Ports>(entity).unwrap();
+        let machine = world.get::<Machine>(entity);
+        result.push(SavedEntity::Machine{
+            entity,
+            kind:      machine.map(|v| v.kind),
+            recipe:    machine.and_then(|v| v.recipe),
+            ports:     PortID::ALL.map(|port| ports.get(port).get()),
+            filter:    world.get::<PortFilter>(entity).copied(),
+            placement: match (world.get::<GridPosition>(entity), world.get::<Rotation>(entity), world.get::<Footprint>(entity)) {
+                (Some(&position), Some(&rotation), Some(&footprint)) => Some((position, rotation, footprint)),
+                _ => None,
+            },
+        });
+    }
+
+    for entity in connections {
+        let (pipe, mut contents) = capture_connection(world, entity).ok_or(CommandError::NotFound(entity))?;
+        let send = world.get::<PortSend>(entity).copied();
+        let held = attached_despawned || entities.contains(&entity) || is_stranded(world, entity, &machines);
+        if !held { contents.packets.clear(); }
+        result.push(SavedEntity::Connection{
+            entity,
+            pipe,
+            contents,
+            send,
+            recv: world.get::<PortRecv>(entity).copied(),
+            path: world.get::<PipePath>(entity).cloned(),
+            shared: world.get::<SharedConnection>(entity).is_some(),
+        });
+    }
+
+    // Emptied once everything's captured, so a failed capture leaves the world untouched.
+    for saved in result.iter() {
+        if let SavedEntity::Connection{ entity, contents, .. } = saved {
+            if !contents.packets.is_empty() { take_connection(world, *entity); }
+        }
+    }
+
+    Ok(result)
+}
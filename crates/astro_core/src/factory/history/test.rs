@@ -0,0 +1,129 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+use crate::factory::{FactoryPlugins, PipeSimple, Pipe, ResourceUUID, SpilledResources, execute_command, MACHINE_SOURCE, MACHINE_SINK};
+
+fn build_app(depth: usize) -> (App, Entity, Entity, Entity) {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins).insert_resource(FactoryHistory::new(depth));
+
+    let world  = &mut app.world;
+    let speed  = ResourceID::intern(ResourceUUID::new("SPEED"));
+    let source = execute_command(world, FactoryCommand::PlaceMachine{ kind: MACHINE_SOURCE, recipe: Some(speed) }).unwrap().unwrap();
+    let sink   = execute_command(world, FactoryCommand::PlaceMachine{ kind: MACHINE_SINK, recipe: None }).unwrap().unwrap();
+    let pipe   = execute_command(world, FactoryCommand::Connect{ from: source, from_port: PortID::B, to: sink, to_port: PortID::A, length: 8 }).unwrap().unwrap();
+    (app, source, sink, pipe)
+}
+
+fn count<T: Component>(app: &mut App) -> usize {
+    app.world.query::<&T>().iter(&app.world).count()
+}
+
+#[test]
+fn undo_redo() {
+    let (mut app, _, _, _) = build_app(10);
+    assert_eq!(app.world.get_resource::<FactoryHistory>().unwrap().undo_len(), 3);
+
+    undo(&mut app.world).unwrap();
+    undo(&mut app.world).unwrap();
+    assert_eq!(count::<Machine>(&mut app), 1);
+    assert_eq!(count::<PipeSimple>(&mut app), 0);
+
+    redo(&mut app.world).unwrap();
+    redo(&mut app.world).unwrap();
+    assert_eq!(redo(&mut app.world), Err(CommandError::EmptyHistory));
+    assert_eq!(count::<Machine>(&mut app), 2);
+
+    let &PortSend(target, _) = app.world.query::<&PortSend>().iter(&app.world).next().unwrap();
+    assert_eq!(app.world.get::<Machine>(target).map(|v| v.kind), Some(MACHINE_SINK));
+
+    execute_command(&mut app.world, FactoryCommand::Undo).unwrap();
+    assert_eq!(count::<PipeSimple>(&mut app), 0);
+}
+
+fn spilled(app: &App) -> u64 {
+    app.world.get_resource::<SpilledResources>().map_or(0, |v| v.iter().map(|(_, v)| v).sum())
+}
+
+/// Runs the factory until the pipe is holding packets, then stops the source.
+fn fill(app: &mut App, source: Entity, pipe: Entity) -> PipeContents {
+    for _ in 0..5 { app.update(); }
+    execute_command(&mut app.world, FactoryCommand::SetRecipe{ machine: source, recipe: None }).unwrap();
+    app.world.get_mut::<Ports>(source).unwrap().get_mut(PortID::B).clear();
+
+    let contents = PipeContents::from_pipe(app.world.get::<PipeSimple>(pipe).unwrap());
+    assert!(!contents.packets.is_empty());
+    contents
+}
+
+#[test]
+fn restores_contents() {
+    let (mut app, source, sink, pipe) = build_app(10);
+    let contents = fill(&mut app, source, pipe);
+    let iron = ResourceID::intern(ResourceUUID::new("IRON"));
+    app.world.get_mut::<Ports>(sink).unwrap().get_mut(PortID::C).set(iron, 3);
+
+    execute_command(&mut app.world, FactoryCommand::Remove{ entity: sink }).unwrap();
+    for _ in 0..3 { app.update(); }
+    assert_eq!(spilled(&app), 0);
+    execute_command(&mut app.world, FactoryCommand::Undo).unwrap();
+
+    let (pipe, restored, &PortSend(sink, _), &PortRecv(recv, _)) = app.world.query::<(Entity, &PipeSimple, &PortSend, &PortRecv)>().iter(&app.world).next().unwrap();
+    assert_eq!(PipeContents::from_pipe(restored), contents);
+    assert_eq!(recv, source);
+    assert_eq!(app.world.get::<Ports>(sink).unwrap().get(PortID::C).get(), Some((iron, 3)));
+    assert_eq!(spilled(&app), 0);
+
+    execute_command(&mut app.world, FactoryCommand::Disconnect{ machine: sink, port: PortID::A }).unwrap();
+    assert!(app.world.get_entity(pipe).is_none());
+    execute_command(&mut app.world, FactoryCommand::Undo).unwrap();
+    assert_eq!(count::<PipeSimple>(&mut app), 1);
+}
+
+#[test]
+fn restore_merges_delivered() {
+    let (mut app, source, sink, pipe) = build_app(10);
+    let contents = fill(&mut app, source, pipe);
+
+    // The pipe outlives its source and keeps what it's given afterwards.
+    execute_command(&mut app.world, FactoryCommand::Remove{ entity: source }).unwrap();
+    let current = PipeContents::from_pipe(app.world.get::<PipeSimple>(pipe).unwrap());
+    execute_command(&mut app.world, FactoryCommand::Undo).unwrap();
+
+    let (restored, &PortSend(target, _)) = app.world.query::<(&PipeSimple, &PortSend)>().iter(&app.world).next().unwrap();
+    assert_eq!(target, sink);
+    assert_eq!(PipeContents::from_pipe(restored), current);
+    assert_eq!(current, contents);
+    assert_eq!(spilled(&app), 0);
+}
+
+#[test]
+fn dropped_edits_spill() {
+    let (mut app, source, _, pipe) = build_app(10);
+    let contents = fill(&mut app, source, pipe);
+
+    execute_command(&mut app.world, FactoryCommand::Remove{ entity: pipe }).unwrap();
+    for _ in 0..3 { app.update(); }
+    assert_eq!(spilled(&app), 0);
+
+    app.world.get_resource_mut::<FactoryHistory>().unwrap().set_depth(1);
+    execute_command(&mut app.world, FactoryCommand::SetRecipe{ machine: source, recipe: None }).unwrap();
+    assert_eq!(spilled(&app), contents.packets.len() as u64);
+}
+
+#[test]
+fn depth() {
+    let (mut app, source, _, _) = build_app(2);
+    execute_command(&mut app.world, FactoryCommand::SetRecipe{ machine: source, recipe: None }).unwrap();
+
+    let history = app.world.get_resource::<FactoryHistory>().unwrap();
+    assert_eq!((history.undo_len(), history.redo_len()), (2, 0));
+
+    undo(&mut app.world).unwrap();
+    assert_eq!(app.world.get::<Machine>(source).unwrap().recipe, Some(ResourceID::intern(ResourceUUID::new("SPEED"))));
+    undo(&mut app.world).unwrap();
+    assert_eq!(undo(&mut app.world), Err(CommandError::EmptyHistory));
+    assert_eq!(count::<Machine>(&mut app), 2);
+}
@@ -0,0 +1,159 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+#[cfg(feature = "client")] mod panel;
+#[cfg(feature = "client")] pub use panel::*;
+
+use bevy::prelude::{Entity, World};
+
+use super::{ConnectionIndex, Dormant, FlowMonitor, FlowStatus, Machine, MachineUUID, PortFilter, PortID, PortRecv, PortSend, Ports, ResourceID, capture_connection};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortInspection {
+    pub port:        PortID,
+    /// Resource held and how many, if any.
+    pub contents:    Option<(ResourceID, u16)>,
+    pub filter:      Option<ResourceID>,
+    /// Connections delivering into or taking from the port.
+    pub connections: Vec<Entity>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketInspection {
+    pub resource: ResourceID,
+    pub enqueued: u32,
+    /// Earliest tick it can be delivered on, a tick after the packet ahead
+    /// of it at the soonest.
+    pub arrival:  u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineInspection {
+    pub entity: Entity,
+    /// `None` for machines driven by systems outside the `MachineRegistry`.
+    pub kind:   Option<MachineUUID>,
+    pub recipe: Option<ResourceID>,
+    pub ports:  Vec<PortInspection>,
+    pub status: Option<FlowStatus>,
+    pub asleep: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipeInspection {
+    pub entity:  Entity,
+    pub from:    Option<PortRecv>,
+    pub to:      Option<PortSend>,
+    pub length:  u32,
+    /// Packets from head to tail.
+    pub packets: Vec<PacketInspection>,
+    pub status:  Option<FlowStatus>,
+    pub asleep:  bool,
+}
+
+/// What a machine or connection holds, for showing to players.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inspection {
+    Machine(MachineInspection),
+    Pipe(PipeInspection),
+}
+
+/// Describes a machine or connection, `None` if the entity is neither.
+pub fn inspect(world: &World, entity: Entity) -> Option<Inspection> {
+    if let Some(ports) = world.get::<Ports>(entity) {
+        return Some(Inspection::Machine(inspect_machine(world, entity, ports)));
+    }
+    let (_, contents) = capture_connection(world, entity)?;
+
+    let mut packets = Vec::with_capacity(contents.packets.len());
+    let mut ahead: Option<u32> = None;
+    for &(enqueued, resource) in contents.packets.iter() {
+        let earliest = enqueued.wrapping_add(contents.length);
+        let arrival  = ahead.map_or(earliest, |v| earliest.max(v.wrapping_add(1)));
+        packets.push(PacketInspection{ resource, enqueued, arrival });
+        ahead = Some(arrival);
+    }
+
+    Some(Inspection::Pipe(PipeInspection{
+        entity,
+        from:    world.get::<PortRecv>(entity).copied(),
+        to:      world.get::<PortSend>(entity).copied(),
+        length:  contents.length,
+        packets,
+        status:  world.get::<FlowMonitor>(entity).map(|v| v.status()),
+        asleep:  world.get::<Dormant>(entity).is_some(),
+    }))
+}
+
+fn inspect_machine(world: &World, entity: Entity, ports: &Ports) -> MachineInspection {
+    let machine = world.get::<Machine>(entity);
+    let filter  = world.get::<PortFilter>(entity);
+    let index   = world.get_resource::<ConnectionIndex>();
+    let ports   = PortID::ALL.into_iter().map(|port| PortInspection{
+        port,
+        contents:    ports.get(port).get(),
+        filter:      filter.and_then(|v| v.get(port)),
+        connections: index.map_or_else(Vec::new, |v| v.senders(entity, port).iter().chain(v.receivers(entity, port)).copied().collect()),
+    }).collect();
+
+    MachineInspection{
+        entity,
+        kind:   machine.map(|v| v.kind),
+        recipe: machine.and_then(|v| v.recipe),
+        ports,
+        status: world.get::<FlowMonitor>(entity).map(|v| v.status()),
+        asleep: world.get::<Dormant>(entity).is_some(),
+    }
+}
+
+/// A resource's name, or its id if it wasn't interned from one.
+pub fn resource_name(resource: ResourceID) -> String {
+    resource.uuid().map_or_else(|| format!("{:?}", resource), |v| v.to_string())
+}
+
+fn write_status(f: &mut std::fmt::Formatter<'_>, status: Option<FlowStatus>, asleep: bool) -> std::fmt::Result {
+    if let Some(status) = status { write!(f, " {:?}", status)?; }
+    if asleep { write!(f, " asleep")?; }
+    Ok(())
+}
+
+impl std::fmt::Display for MachineInspection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{} {}", self.entity.id(), self.kind.map_or_else(|| "machine".to_string(), |v| v.to_string()))?;
+        if let Some(recipe) = self.recipe { write!(f, " making {}", resource_name(recipe))?; }
+        write_status(f, self.status, self.asleep)?;
+        for port in self.ports.iter() {
+            match port.contents {
+                Some((resource, count)) => write!(f, "\n  {:?} {} x{}", port.port, resource_name(resource), count)?,
+                None                    => write!(f, "\n  {:?} empty", port.port)?,
+            }
+            if let Some(filter) = port.filter { write!(f, " only {}", resource_name(filter))?; }
+            for connection in port.connections.iter() { write!(f, " #{}", connection.id())?; }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for PipeInspection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let from = self.from.map_or_else(|| "-".to_string(), |PortRecv(target, port)| format!("#{}.{:?}", target.id(), port));
+        let to   = self.to.map_or_else(|| "-".to_string(), |PortSend(target, port)| format!("#{}.{:?}", target.id(), port));
+        write!(f, "#{} pipe {} -> {} length {}", self.entity.id(), from, to, self.length)?;
+        write_status(f, self.status, self.asleep)?;
+        for packet in self.packets.iter() {
+            write!(f, "\n  {} enqueued {} arrives {}", resource_name(packet.resource), packet.enqueued, packet.arrival)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Inspection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Machine(v) => v.fmt(f),
+            Self::Pipe(v)    => v.fmt(f),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use bevy::prelude::*;
+
+use crate::factory::{FactorySpatialIndex, TILE_SIZE, inspect};
+
+/// Font the inspector panel is drawn with, the game provides its own.
+pub struct InspectorFont(pub Handle<Font>);
+
+/// Entity shown in the inspector panel, chosen by clicking its tile.
+#[derive(Debug, Default)]
+pub struct Inspected(pub Option<Entity>);
+
+#[derive(Component)]
+pub struct InspectorPanel;
+
+/// Inspector panel drawn with the given font.
+pub struct FactoryInspectorPlugin(pub Handle<Font>);
+
+impl Plugin for FactoryInspectorPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(InspectorFont(self.0.clone()))
+            .init_resource::<Inspected>()
+            .add_startup_system(spawn_inspector_panel)
+            .add_system(select_inspected)
+            .add_system_to_stage(CoreStage::PostUpdate, update_inspector_panel.exclusive_system());
+    }
+}
+
+pub fn spawn_inspector_panel(mut commands: Commands, font: Res<InspectorFont>) {
+    commands.spawn_bundle(TextBundle{
+        style: Style{
+            position_type: PositionType::Absolute,
+            position:      Rect{ top: Val::Px(8.0), left: Val::Px(8.0), ..Default::default() },
+            ..Default::default()
+        },
+        text: Text::with_section("", TextStyle{ font: font.0.clone(), font_size: 16.0, color: Color::WHITE }, Default::default()),
+        ..Default::default()
+    }).insert(InspectorPanel);
+}
+
+/// Inspects whatever occupies the clicked tile, or nothing if it's empty.
+pub fn select_inspected(
+    buttons:       Res<Input<MouseButton>>,
+    windows:       Res<Windows>,
+    index:         Res<FactorySpatialIndex>,
+    cameras:       Query<(&GlobalTransform, &OrthographicProjection)>,
+    mut inspected: ResMut<Inspected>,
+) {
+    if !buttons.just_pressed(MouseButton::Left) { return; }
+    let window = if let Some(v) = windows.get_primary() { v } else { return; };
+    let cursor = if let Some(v) = window.cursor_position() { v } else { return; };
+    let (transform, projection) = if let Some(v) = cameras.iter().next() { v } else { return; };
+
+    let window = Vec2::new(window.width(), window.height());
+    inspected.0 = index.get(cursor_tile(cursor, window, transform.translation.truncate(), projection.scale));
+}
+
+/// Tile under a cursor, given from the bottom left of a window of the given
+/// size, for a camera centred on `camera` zoomed out by `scale`.
+pub fn cursor_tile(cursor: Vec2, window: Vec2, camera: Vec2, scale: f32) -> IVec2 {
+    let point = camera + (cursor - window / 2.0) * scale;
+    (point / TILE_SIZE).round().as_ivec2()
+}
+
+/// Redraws the panel every frame so it follows the inspected entity's
+/// contents.
+pub fn update_inspector_panel(world: &mut World) {
+    let inspected = world.get_resource::<Inspected>().unwrap().0;
+    let text = inspected.and_then(|v| inspect(world, v)).map_or_else(String::new, |v| v.to_string());
+
+    let mut panels = world.query_filtered::<&mut Text, With<InspectorPanel>>();
+    for mut panel in panels.iter_mut(world) {
+        if panel.sections[0].value != text { panel.sections[0].value.clone_from(&text); }
+    }
+}
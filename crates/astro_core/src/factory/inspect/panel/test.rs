@@ -0,0 +1,48 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+use crate::factory::{FactoryPlugins, Rotation, MACHINE_SINK, place_machine};
+
+const WINDOW: Vec2 = Vec2::new(800.0, 600.0);
+
+#[test]
+fn cursor_tiles() {
+    let centre = WINDOW / 2.0;
+    assert_eq!(cursor_tile(centre, WINDOW, Vec2::ZERO, 1.0), IVec2::ZERO);
+    assert_eq!(cursor_tile(centre + Vec2::new(TILE_SIZE * 2.4, TILE_SIZE * -0.6), WINDOW, Vec2::ZERO, 1.0), IVec2::new(2, -1));
+    assert_eq!(cursor_tile(centre, WINDOW, Vec2::new(TILE_SIZE * 3.0, 0.0), 1.0), IVec2::new(3, 0));
+
+    // Zoomed out, the same distance on screen covers twice as many tiles.
+    assert_eq!(cursor_tile(centre + Vec2::new(TILE_SIZE, 0.0), WINDOW, Vec2::ZERO, 2.0), IVec2::new(2, 0));
+}
+
+/// Runs the panel systems without any rendering plugins, so no window or
+/// GPU is needed.
+#[test]
+fn panel_follows_inspected_headless() {
+    let mut app = App::new();
+    app
+        .add_plugins(FactoryPlugins)
+        .insert_resource(InspectorFont(Handle::default()))
+        .init_resource::<Inspected>()
+        .add_startup_system(spawn_inspector_panel)
+        .add_system_to_stage(CoreStage::PostUpdate, update_inspector_panel.exclusive_system());
+
+    let sink = place_machine(&mut app.world, MACHINE_SINK, None, IVec2::new(2, 0), Rotation::North).unwrap();
+    app.update();
+    let mut panel = app.world.query_filtered::<&Text, With<InspectorPanel>>();
+    assert_eq!(panel.single(&app.world).sections[0].value, "");
+
+    let tile   = cursor_tile(WINDOW / 2.0 + Vec2::new(TILE_SIZE * 2.0, 0.0), WINDOW, Vec2::ZERO, 1.0);
+    let picked = app.world.get_resource::<FactorySpatialIndex>().unwrap().get(tile);
+    assert_eq!(picked, Some(sink));
+    app.world.insert_resource(Inspected(picked));
+    app.update();
+    assert_eq!(panel.single(&app.world).sections[0].value, inspect(&app.world, sink).unwrap().to_string());
+
+    app.world.despawn(sink);
+    app.update();
+    assert_eq!(panel.single(&app.world).sections[0].value, "");
+}
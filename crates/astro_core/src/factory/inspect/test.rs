@@ -0,0 +1,38 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::prelude::App;
+
+use super::*;
+use crate::factory::{ConnectionBuilder, FactoryCommand, FactoryPlugins, PipeSimple, ResourceUUID, MACHINE_SOURCE, MACHINE_SINK, execute_command};
+
+#[test]
+fn machines_and_pipes() {
+    let speed = ResourceID::intern(ResourceUUID::new("SPEED"));
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+
+    let source = execute_command(&mut app.world, FactoryCommand::PlaceMachine{ kind: MACHINE_SOURCE, recipe: Some(speed) }).unwrap().unwrap();
+    let sink   = execute_command(&mut app.world, FactoryCommand::PlaceMachine{ kind: MACHINE_SINK,   recipe: None }).unwrap().unwrap();
+    execute_command(&mut app.world, FactoryCommand::ConfigurePortFilter{ machine: sink, port: PortID::A, filter: Some(speed) }).unwrap();
+    let pipe   = ConnectionBuilder::new(3).recv_from(source, PortID::B).send_to(sink, PortID::A).build::<PipeSimple>(&mut app.world).unwrap();
+    app.update();
+    app.update();
+
+    let machine = match inspect(&app.world, sink) { Some(Inspection::Machine(v)) => v, v => panic!("{:?}", v) };
+    assert_eq!((machine.kind, machine.recipe), (Some(MACHINE_SINK), None));
+    assert_eq!(machine.ports[0], PortInspection{ port: PortID::A, contents: None, filter: Some(speed), connections: vec![pipe] });
+    assert_eq!(machine.to_string().lines().nth(1), Some(format!("  A empty only SPEED #{}", pipe.id()).as_str()));
+
+    let pipe = match inspect(&app.world, pipe) { Some(Inspection::Pipe(v)) => v, v => panic!("{:?}", v) };
+    assert_eq!((pipe.from, pipe.to, pipe.length), (Some(PortRecv(source, PortID::B)), Some(PortSend(sink, PortID::A)), 3));
+    assert_eq!(pipe.packets, vec![
+        PacketInspection{ resource: speed, enqueued: 1, arrival: 4 },
+        PacketInspection{ resource: speed, enqueued: 2, arrival: 5 },
+    ]);
+    assert_eq!(pipe.to_string().lines().count(), 3);
+
+    app.world.despawn(sink);
+    assert_eq!(inspect(&app.world, sink), None);
+}
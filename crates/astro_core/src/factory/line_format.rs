@@ -0,0 +1,71 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+//! Pieces shared by the line based snapshot and replay formats. Both start
+//! with a `<name> <version>` header, skip blank and `#` lines, and write
+//! missing values as `-`.
+
+use std::str::FromStr;
+
+use bevy::prelude::IVec2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatError {
+    Parse{ line: usize, message: &'static str },
+    UnsupportedVersion{ format: &'static str, version: u32 },
+}
+
+impl std::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse{ line, message }               => write!(f, "line {}: {}", line, message),
+            Self::UnsupportedVersion{ format, version } => write!(f, "unsupported {} version {}", format, version),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// Writes `-` for `None`.
+pub(crate) struct Optional<T>(pub Option<T>);
+
+impl<T: std::fmt::Display> std::fmt::Display for Optional<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Some(v) => v.fmt(f),
+            None    => f.write_str("-"),
+        }
+    }
+}
+
+/// Trimmed lines with their 1-based numbers, skipping blank lines and comments.
+pub(crate) fn format_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, v)| (i + 1, v.trim()))
+        .filter(|(_, v)| !v.is_empty() && !v.starts_with('#'))
+}
+
+/// Reads the `<format> <version>` header, returning its line.
+pub(crate) fn parse_header<'a>(lines: &mut impl Iterator<Item = (usize, &'a str)>, format: &'static str, version: u32) -> Result<usize, FormatError> {
+    let (line, header) = lines.next().ok_or(FormatError::Parse{ line: 1, message: "Missing header" })?;
+    let mut tokens = header.split_whitespace();
+    if tokens.next() != Some(format) { return Err(FormatError::Parse{ line, message: "Missing header" }); }
+    let found = parse_number(line, tokens.next())?;
+    if found != version { return Err(FormatError::UnsupportedVersion{ format, version: found }); }
+    Ok(line)
+}
+
+pub(crate) fn parse_number<T: FromStr>(line: usize, token: Option<&str>) -> Result<T, FormatError> {
+    token
+        .ok_or(FormatError::Parse{ line, message: "Missing number" })?
+        .parse()
+        .map_err(|_| FormatError::Parse{ line, message: "Invalid number" })
+}
+
+/// Parses a tile written as `x,y`.
+pub fn parse_tile(value: &str) -> Option<IVec2> {
+    let (x, y) = value.split_once(',')?;
+    Some(IVec2::new(x.parse().ok()?, y.parse().ok()?))
+}
@@ -0,0 +1,107 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::prelude::{Commands, Component, Entity, Query, Res, ResMut, With, Without, ParallelSystemDescriptorCoercion};
+
+use crate::factory::{Dormant, FactoryStage, FactorySystem, FactoryTick, PortID, ResourceFlow, sleep};
+
+use super::{Machine, MachineUUID, Ports, FlowMonitor, FlowStatus, register_machine};
+
+pub const MACHINE_SOURCE:      MachineUUID = MachineUUID::new("SOURCE");
+pub const MACHINE_SINK:        MachineUUID = MachineUUID::new("SINK");
+pub const MACHINE_PASSTHROUGH: MachineUUID = MachineUUID::new("PASSTHROUGH");
+
+/// Keeps port B stocked with its recipe resource.
+#[derive(Component, Default)]
+pub struct MachineSource;
+
+/// Destroys everything delivered to port A.
+#[derive(Component, Default)]
+pub struct MachineSink;
+
+/// Moves resources from port A to port B.
+#[derive(Component, Default)]
+pub struct MachinePassthrough;
+
+pub fn register_basic_machines(app: &mut bevy::prelude::App) {
+    register_machine(app, MACHINE_SOURCE,      |e| { e.insert(MachineSource);      });
+    register_machine(app, MACHINE_SINK,        |e| { e.insert(MachineSink);        });
+    register_machine(app, MACHINE_PASSTHROUGH, |e| { e.insert(MachinePassthrough); });
+
+    app.add_system_to_stage(FactoryStage::Machine, update_machine_source     .label(FactorySystem::MachineSource));
+    app.add_system_to_stage(FactoryStage::Machine, update_machine_passthrough.label(FactorySystem::MachinePassthrough).after(FactorySystem::MachineSource));
+    app.add_system_to_stage(FactoryStage::Machine, update_machine_sink       .label(FactorySystem::MachineSink).after(FactorySystem::MachinePassthrough));
+}
+
+// The basic machines only stall waiting on their own ports or recipe, so
+// they sleep whenever they stall.
+
+pub fn update_machine_source(
+    mut commands: Commands,
+    tick: Res<FactoryTick>,
+    mut flow: ResMut<ResourceFlow>,
+    mut q: Query<(Entity, &Machine, &mut Ports, &mut FlowMonitor), (With<MachineSource>, Without<Dormant>)>
+) {
+    for (entity, machine, mut ports, mut monitor) in q.iter_mut() {
+        let status = if let Some(resource) = machine.recipe {
+            if ports.get(PortID::B).count() == 0 {
+                ports.get_mut(PortID::B).set(resource, 1);
+                flow.record_produced(resource, 1);
+                FlowStatus::Running
+            } else {
+                FlowStatus::OutputBlocked
+            }
+        } else {
+            FlowStatus::InputStarved
+        };
+        record(&mut commands, tick.0, entity, &mut monitor, status);
+    }
+}
+
+pub fn update_machine_sink(
+    mut commands: Commands,
+    tick: Res<FactoryTick>,
+    mut flow: ResMut<ResourceFlow>,
+    mut q: Query<(Entity, &mut Ports, &mut FlowMonitor), (With<MachineSink>, Without<Dormant>)>
+) {
+    for (entity, mut ports, mut monitor) in q.iter_mut() {
+        let status = if let Some((resource, count)) = ports.get(PortID::A).get() {
+            ports.get_mut(PortID::A).clear();
+            flow.record_consumed(resource, count as u32);
+            FlowStatus::Running
+        } else {
+            FlowStatus::InputStarved
+        };
+        record(&mut commands, tick.0, entity, &mut monitor, status);
+    }
+}
+
+pub fn update_machine_passthrough(
+    mut commands: Commands,
+    tick: Res<FactoryTick>,
+    mut q: Query<(Entity, &mut Ports, &mut FlowMonitor), (With<MachinePassthrough>, Without<Dormant>)>
+) {
+    for (entity, mut ports, mut monitor) in q.iter_mut() {
+        let status = if let Some((resource, count_send)) = ports.get(PortID::A).get() {
+            let (resource_recv, count_recv) = ports.get(PortID::B).get_or(resource);
+            if resource_recv != resource {
+                FlowStatus::ResourceMismatch
+            } else if count_recv == u16::MAX {
+                FlowStatus::OutputBlocked
+            } else {
+                ports.get_mut(PortID::A).set(resource, count_send-1);
+                ports.get_mut(PortID::B).set(resource, count_recv+1);
+                FlowStatus::Running
+            }
+        } else {
+            FlowStatus::InputStarved
+        };
+        record(&mut commands, tick.0, entity, &mut monitor, status);
+    }
+}
+
+fn record(commands: &mut Commands, tick: u32, entity: Entity, monitor: &mut FlowMonitor, status: FlowStatus) {
+    monitor.record(status);
+    if status.is_stalled() { sleep(commands, entity, tick, status); }
+}
@@ -0,0 +1,83 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::{prelude::{Component, Entity, World, Plugin}, ecs::world::EntityMut, utils::HashMap};
+use compact_str::{CompactStr128, newtype_compactstr};
+
+use super::{ResourceID, Ports, FlowMonitor, FlowStatus};
+
+mod basic;
+pub use basic::*;
+
+newtype_compactstr!(pub, MachineUUID, CompactStr128);
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Machine {
+    pub kind:   MachineUUID,
+    pub recipe: Option<ResourceID>,
+}
+
+/// Inserts the behaviour components of a machine kind.
+#[derive(Clone, Copy)]
+pub struct MachineDescriptor {
+    pub kind:   MachineUUID,
+    pub insert: fn(&mut EntityMut),
+}
+
+#[derive(Default)]
+pub struct MachineRegistry(HashMap<MachineUUID, MachineDescriptor>);
+
+impl MachineRegistry {
+
+    pub fn register(&mut self, kind: MachineUUID, insert: fn(&mut EntityMut)) {
+        self.0.insert(kind, MachineDescriptor{ kind, insert });
+    }
+
+    pub fn get(&self, kind: MachineUUID) -> Option<&MachineDescriptor> {
+        self.0.get(&kind)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MachineDescriptor> {
+        self.0.values()
+    }
+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachineError {
+    UnknownKind(MachineUUID),
+}
+
+impl std::fmt::Display for MachineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownKind(kind) => write!(f, "unknown machine kind {}", kind),
+        }
+    }
+}
+
+impl std::error::Error for MachineError {}
+
+pub fn register_machine(app: &mut bevy::prelude::App, kind: MachineUUID, insert: fn(&mut EntityMut)) {
+    app.world.get_resource_or_insert_with(MachineRegistry::default).register(kind, insert);
+}
+
+pub fn spawn_machine(world: &mut World, kind: MachineUUID, recipe: Option<ResourceID>) -> Result<Entity, MachineError> {
+    let descriptor = world.get_resource::<MachineRegistry>()
+        .and_then(|v| v.get(kind).copied())
+        .ok_or(MachineError::UnknownKind(kind))?;
+
+    let mut entity = world.spawn();
+    entity.insert_bundle((Ports::default(), Machine{ kind, recipe }, FlowMonitor::default()));
+    (descriptor.insert)(&mut entity);
+    Ok(entity.id())
+}
+
+pub struct FactoryMachinePlugin;
+
+impl Plugin for FactoryMachinePlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        register_basic_machines(app);
+    }
+}
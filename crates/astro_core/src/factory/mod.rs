@@ -2,11 +2,61 @@
 ** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
 \*=====================================================================*/
 
-use bevy::prelude::{ResMut, PluginGroup, Plugin, CoreStage, SystemStage, StageLabel};
+use bevy::prelude::{ResMut, PluginGroup, Plugin, CoreStage, SystemStage, StageLabel, SystemLabel, ParallelSystemDescriptorCoercion};
 
 mod resources;
 pub use resources::*;
 
+mod stats;
+pub use stats::*;
+
+mod dormancy;
+pub use dormancy::*;
+
+mod machine;
+pub use machine::*;
+
+mod line_format;
+pub use line_format::*;
+
+mod snapshot;
+pub use snapshot::*;
+
+mod determinism;
+pub use determinism::*;
+
+mod command;
+pub use command::*;
+
+mod history;
+pub use history::*;
+
+mod replay;
+pub use replay::*;
+
+mod blueprint;
+pub use blueprint::*;
+
+mod grid;
+pub use grid::*;
+
+mod text;
+pub use text::*;
+
+mod inspect;
+pub use inspect::*;
+
+pub mod net;
+
+#[cfg(feature = "client")] mod render;
+#[cfg(feature = "client")] pub use render::*;
+
+#[cfg(feature = "scripting")] mod script;
+#[cfg(feature = "scripting")] pub use script::*;
+
+#[cfg(feature = "profile")] mod profile;
+#[cfg(feature = "profile")] pub use profile::*;
+
 #[derive(StageLabel, Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum FactoryStage {
     Machine,
@@ -18,15 +68,37 @@ impl PluginGroup for FactoryPlugins {
     fn build(&mut self, group: &mut bevy::app::PluginGroupBuilder) {
         group.add(FactoryStagePlugin);
         group.add(FactoryResourcePlugin);
+        group.add(FactoryStatsPlugin);
+        group.add(FactoryMachinePlugin);
+        group.add(FactoryCommandPlugin);
+        group.add(FactoryGridPlugin);
+        group.add(FactoryDormancyPlugin);
+        #[cfg(feature = "scripting")] group.add(FactoryScriptPlugin);
+        #[cfg(feature = "profile")] group.add(FactoryProfilePlugin);
     }
 }
 
 #[derive(StageLabel, Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum FactoryStageInternal {
     Tick,
+    Wake,
     Machine,
 }
 
+#[derive(SystemLabel, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum FactorySystem {
+    UpdateTick,
+    ApplyCommands,
+    MachineSource,
+    MachinePassthrough,
+    MachineSink,
+    #[cfg(feature = "scripting")] MachineScript,
+    ConnectionSendRecv,
+    ConnectionRecv,
+    ConnectionSend,
+    ConnectionPooled,
+}
+
 pub struct FactoryTick(pub u32);
 
 pub fn update_tick(mut tick: ResMut<FactoryTick>) {
@@ -41,6 +113,6 @@ impl Plugin for FactoryStagePlugin {
         app.schedule.add_stage_after(FactoryStageInternal::Tick,      FactoryStage::Machine, SystemStage::single_threaded());
 
         app.insert_resource(FactoryTick(0));
-        app.schedule.add_system_to_stage(FactoryStageInternal::Tick, update_tick);
+        app.schedule.add_system_to_stage(FactoryStageInternal::Tick, update_tick.label(FactorySystem::UpdateTick));
     }
 }
@@ -0,0 +1,206 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use std::net::ToSocketAddrs;
+
+use bevy::{prelude::*, ecs::event::Events, utils::HashMap};
+
+use super::{NetConnection, NetError, ServerMessage, ClientMessage, BuildRequest, FactoryDelta, PortValues};
+use crate::factory::{
+    FactorySnapshot, FactoryTick, Footprint, GridPosition, Machine, PipeContents, PipeDescriptor, PipeGap, PipePath, PipeRegistry, PipeSimple,
+    PortFilter, Ports, PortID, POOLED_PIPE, PortSend, PortRecv, ResourceID, Rotation
+};
+
+/// Connection to a replication server, insert it before adding
+/// `ReplicationClientPlugin`.
+pub struct ReplicationClient {
+    connection:   NetConnection,
+    entities:     HashMap<u64, Replica>,
+    next_request: u32,
+    connected:    bool,
+}
+
+#[derive(Clone, Copy)]
+struct Replica {
+    entity: Entity,
+    pipe:   Option<(PipeDescriptor, u32)>,
+}
+
+/// Marks an entity mirrored from the server, holding the server's entity bits.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Replicated(pub u64);
+
+/// Reply to a build request sent with `ReplicationClient::request`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildResponse {
+    pub request: u32,
+    pub result:  Result<Option<u64>, String>,
+}
+
+impl ReplicationClient {
+
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, NetError> {
+        Ok(Self{ connection: NetConnection::connect(addr)?, entities: HashMap::default(), next_request: 0, connected: true })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Queues a build request, the returned id is echoed in its `BuildResponse`.
+    pub fn request(&mut self, command: BuildRequest) -> u32 {
+        let request = self.next_request;
+        self.next_request = self.next_request.wrapping_add(1);
+        self.connection.send(&ClientMessage::Build{ request, command });
+        request
+    }
+
+    /// The local mirror of a server entity.
+    pub fn local(&self, server: u64) -> Option<Entity> {
+        self.entities.get(&server).map(|v| v.entity)
+    }
+
+}
+
+pub struct ReplicationClientPlugin;
+
+impl Plugin for ReplicationClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.world.get_resource_or_insert_with(|| FactoryTick(0));
+        app.world.get_resource_or_insert_with(PipeRegistry::default).register::<PipeSimple>();
+        app.world.get_resource_or_insert_with(PipeRegistry::default).register::<PipeGap>();
+        app.world.get_resource_or_insert_with(PipeRegistry::default).register_descriptor(POOLED_PIPE);
+
+        app
+            .add_event::<BuildResponse>()
+            .add_system_to_stage(CoreStage::First, receive_replication.exclusive_system());
+    }
+}
+
+pub fn receive_replication(world: &mut World) {
+    if !world.contains_resource::<ReplicationClient>() { return; }
+
+    world.resource_scope(|world, mut client: Mut<ReplicationClient>| {
+        if !client.connected { return; }
+
+        let messages = match client.connection.receive::<ServerMessage>() {
+            Ok(messages) => messages,
+            Err(_)       => { client.connected = false; return; },
+        };
+
+        for message in messages {
+            match message {
+                ServerMessage::Snapshot{ snapshot, machines, connections } => {
+                    if let Ok(snapshot) = snapshot.parse::<FactorySnapshot>() {
+                        for (_, replica) in client.entities.drain() { world.despawn(replica.entity); }
+                        apply_snapshot(world, &mut client, &snapshot, &machines, &connections);
+                    }
+                },
+                ServerMessage::Structure{ snapshot, machines, connections, removed } => {
+                    if let Ok(snapshot) = snapshot.parse::<FactorySnapshot>() {
+                        for replica in removed.iter().filter_map(|v| client.entities.remove(v)) { world.despawn(replica.entity); }
+                        apply_snapshot(world, &mut client, &snapshot, &machines, &connections);
+                    }
+                },
+                ServerMessage::Delta(delta) => apply_delta(world, &client, delta),
+                ServerMessage::BuildResult{ request, result } => {
+                    world.get_resource_mut::<Events<BuildResponse>>().unwrap().send(BuildResponse{ request, result });
+                },
+            }
+        }
+
+        if client.connection.flush().is_err() { client.connected = false; }
+    });
+}
+
+/// Spawns or replaces the replica of every entity in the snapshot.
+fn apply_snapshot(world: &mut World, client: &mut ReplicationClient, snapshot: &FactorySnapshot, machines: &[u64], connections: &[u64]) {
+    let mut locals = Vec::with_capacity(machines.len());
+    for (machine, &bits) in snapshot.machines.iter().zip(machines.iter()) {
+        let mut ports = Ports::default();
+        write_ports(&mut ports, &machine.ports);
+
+        let entity     = client.local(bits).unwrap_or_else(|| world.spawn().insert(Replicated(bits)).id());
+        let mut entity = world.entity_mut(entity);
+        entity.insert(ports);
+        match machine.kind {
+            Some(kind) => { entity.insert(Machine{ kind, recipe: machine.recipe.map(ResourceID::intern) }); },
+            None       => { entity.remove::<Machine>(); },
+        }
+        match machine.port_filter() {
+            Some(filter) => { entity.insert(filter); },
+            None         => { entity.remove::<PortFilter>(); },
+        }
+        match machine.placement {
+            Some((position, rotation, footprint)) => { entity.insert_bundle((GridPosition(position), rotation, footprint)); },
+            None                                  => { entity.remove_bundle::<(GridPosition, Rotation, Footprint)>(); },
+        }
+
+        locals.push(entity.id());
+        client.entities.insert(bits, Replica{ entity: entity.id(), pipe: None });
+    }
+
+    for (connection, &bits) in snapshot.connections.iter().zip(connections.iter()) {
+        let pipe = match world.get_resource::<PipeRegistry>().and_then(|v| v.get(&connection.pipe).copied()) {
+            Some(v) => v,
+            None    => continue,
+        };
+
+        let contents = PipeContents{
+            length:  connection.length,
+            packets: connection.packets.iter().map(|&(tick, r)| (tick, ResourceID::intern(r))).collect(),
+        };
+
+        let entity = client.local(bits).unwrap_or_else(|| world.spawn().insert(Replicated(bits)).id());
+        (pipe.insert)(world, entity, &contents);
+        let mut entity = world.entity_mut(entity);
+        let local      = |end: Option<(u32, PortID)>| end.and_then(|(idx, port)| Some((*locals.get(idx as usize)?, port)));
+        match local(connection.send) {
+            Some((target, port)) => { entity.insert(PortSend(target, port)); },
+            None                 => { entity.remove::<PortSend>(); },
+        }
+        match local(connection.recv) {
+            Some((target, port)) => { entity.insert(PortRecv(target, port)); },
+            None                 => { entity.remove::<PortRecv>(); },
+        }
+        match connection.path.as_ref() {
+            Some(path) => { entity.insert(PipePath(path.clone())); },
+            None       => { entity.remove::<PipePath>(); },
+        }
+
+        client.entities.insert(bits, Replica{ entity: entity.id(), pipe: Some((pipe, connection.length)) });
+    }
+
+    world.insert_resource(FactoryTick(snapshot.tick));
+}
+
+fn apply_delta(world: &mut World, client: &ReplicationClient, delta: FactoryDelta) {
+    for (bits, values) in delta.ports.iter() {
+        let ports = client.entities.get(bits).and_then(|v| world.get_mut::<Ports>(v.entity));
+        if let Some(mut ports) = ports { write_ports(&mut ports, values); }
+    }
+
+    for (bits, packets) in delta.pipes.iter() {
+        if let Some(&Replica{ entity, pipe: Some((pipe, length)) }) = client.entities.get(bits) {
+            let contents = PipeContents{ length, packets: packets.iter().map(|&(tick, r)| (tick, ResourceID::intern(r))).collect() };
+            (pipe.insert)(world, entity, &contents);
+        }
+    }
+
+    for (bits, recipe) in delta.machines.iter() {
+        let machine = client.entities.get(bits).and_then(|v| world.get_mut::<Machine>(v.entity));
+        if let Some(mut machine) = machine { machine.recipe = recipe.map(ResourceID::intern); }
+    }
+
+    world.insert_resource(FactoryTick(delta.tick));
+}
+
+fn write_ports(ports: &mut Ports, values: &PortValues) {
+    for (port, value) in PortID::ALL.into_iter().zip(values.iter()) {
+        match value {
+            Some((resource, count)) => ports.get_mut(port).set(ResourceID::intern(*resource), *count),
+            None                    => ports.get_mut(port).clear(),
+        }
+    }
+}
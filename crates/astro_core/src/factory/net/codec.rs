@@ -0,0 +1,221 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::prelude::IVec2;
+
+use super::{NetError, PortID, ResourceUUID, MachineUUID, Rotation};
+
+/// Little-endian binary encoding used by the replication protocol.
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+pub trait Decode: Sized {
+    fn decode(input: &mut Reader) -> Result<Self, NetError>;
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+
+    pub fn new(data: &'a [u8]) -> Self {
+        Self{ data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], NetError> {
+        if len > self.data.len() { return Err(NetError::Decode("Unexpected end of message")); }
+        let (result, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(result)
+    }
+
+    pub fn take_array<const N: usize>(&mut self) -> Result<[u8; N], NetError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+}
+
+macro_rules! impl_codec_int {
+    ($($T:ty),*) => {$(
+        impl Encode for $T {
+            fn encode(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+        }
+
+        impl Decode for $T {
+            fn decode(input: &mut Reader) -> Result<Self, NetError> {
+                Ok(<$T>::from_le_bytes(input.take_array()?))
+            }
+        }
+    )*};
+}
+
+impl_codec_int!(u8, u16, u32, u64, u128, i32);
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u8).encode(out);
+    }
+}
+
+impl Decode for bool {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(NetError::Decode("Invalid bool")),
+        }
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        let len = u32::decode(input)? as usize;
+        String::from_utf8(input.take(len)?.to_vec()).map_err(|_| NetError::Decode("Invalid string"))
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.is_some().encode(out);
+        if let Some(v) = self { v.encode(out); }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        Ok(if bool::decode(input)? { Some(T::decode(input)?) } else { None })
+    }
+}
+
+impl<T: Encode, E: Encode> Encode for Result<T, E> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.is_ok().encode(out);
+        match self {
+            Ok(v)  => v.encode(out),
+            Err(e) => e.encode(out),
+        }
+    }
+}
+
+impl<T: Decode, E: Decode> Decode for Result<T, E> {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        Ok(if bool::decode(input)? { Ok(T::decode(input)?) } else { Err(E::decode(input)?) })
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        self.iter().for_each(|v| v.encode(out));
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        let len = u32::decode(input)? as usize;
+        (0..len).map(|_| T::decode(input)).collect()
+    }
+}
+
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.iter().for_each(|v| v.encode(out));
+    }
+}
+
+impl<T: Decode, const N: usize> Decode for [T; N] {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        let values = (0..N).map(|_| T::decode(input)).collect::<Result<Vec<_>, _>>()?;
+        values.try_into().map_err(|_| NetError::Decode("Invalid array"))
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        Ok((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+impl Encode for PortID {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u8).encode(out);
+    }
+}
+
+impl Decode for PortID {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        PortID::ALL.get(u8::decode(input)? as usize).copied().ok_or(NetError::Decode("Invalid port"))
+    }
+}
+
+impl Encode for ResourceUUID {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.to_raw().encode(out);
+    }
+}
+
+impl Decode for ResourceUUID {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        ResourceUUID::try_from_raw(u128::decode(input)?).ok_or(NetError::Decode("Invalid resource"))
+    }
+}
+
+impl Encode for MachineUUID {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.to_raw().encode(out);
+    }
+}
+
+impl Decode for MachineUUID {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        MachineUUID::try_from_raw(u128::decode(input)?).ok_or(NetError::Decode("Invalid machine"))
+    }
+}
+
+impl Encode for IVec2 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.x.encode(out);
+        self.y.encode(out);
+    }
+}
+
+impl Decode for IVec2 {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        Ok(IVec2::new(i32::decode(input)?, i32::decode(input)?))
+    }
+}
+
+impl Encode for Rotation {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u8).encode(out);
+    }
+}
+
+impl Decode for Rotation {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        Rotation::ALL.get(u8::decode(input)? as usize).copied().ok_or(NetError::Decode("Invalid rotation"))
+    }
+}
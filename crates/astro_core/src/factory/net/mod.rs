@@ -0,0 +1,53 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+//! Replication of factory state over TCP. The server sends a full snapshot
+//! when a client joins, a partial snapshot of the machines and connections
+//! added, removed or rewired in a frame, and a delta of changed ports, pipes
+//! and machines every frame. Clients never simulate, they mirror the server
+//! and send build requests back to it.
+
+#[cfg(test)] mod test;
+
+use super::{FactorySnapshot, Machine, MachineUUID, Ports, PortID, ResourceID, ResourceUUID, Rotation, capture_ports};
+
+mod codec;
+pub use codec::*;
+
+mod protocol;
+pub use protocol::*;
+
+mod transport;
+pub use transport::*;
+
+mod server;
+pub use server::*;
+
+#[cfg(feature = "client")] mod client;
+#[cfg(feature = "client")] pub use client::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetError {
+    Io(std::io::ErrorKind),
+    Decode(&'static str),
+    Closed,
+}
+
+impl std::fmt::Display for NetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(kind)  => write!(f, "io error: {:?}", kind),
+            Self::Decode(v) => write!(f, "malformed message: {}", v),
+            Self::Closed    => f.write_str("connection closed"),
+        }
+    }
+}
+
+impl std::error::Error for NetError {}
+
+impl From<std::io::Error> for NetError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e.kind())
+    }
+}
@@ -0,0 +1,229 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::prelude::IVec2;
+
+use super::{Encode, Decode, Reader, NetError, MachineUUID, ResourceUUID, PortID, Rotation};
+
+pub type PortValues = [Option<(ResourceUUID, u16)>; 4];
+
+/// Entities are referred to by their bits on the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerMessage {
+    /// Full state in the snapshot text format, with the server entity of
+    /// each machine and connection in snapshot order.
+    Snapshot{ snapshot: String, machines: Vec<u64>, connections: Vec<u64> },
+    Delta(FactoryDelta),
+    BuildResult{ request: u32, result: Result<Option<u64>, String> },
+    /// Like `Snapshot`, holding only entities that were added or rewired,
+    /// which replace their previous state, along with those removed.
+    Structure{ snapshot: String, machines: Vec<u64>, connections: Vec<u64>, removed: Vec<u64> },
+}
+
+/// State that changed since the previous snapshot or delta.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FactoryDelta {
+    pub tick:     u32,
+    pub ports:    Vec<(u64, PortValues)>,
+    pub pipes:    Vec<(u64, Vec<(u32, ResourceUUID)>)>,
+    pub machines: Vec<(u64, Option<ResourceUUID>)>,
+}
+
+impl FactoryDelta {
+    pub fn is_empty(&self) -> bool {
+        self.ports.is_empty() && self.pipes.is_empty() && self.machines.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientMessage {
+    Build{ request: u32, command: BuildRequest },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildRequest {
+    PlaceMachine{ kind: MachineUUID, recipe: Option<ResourceUUID> },
+    Connect{ from: u64, from_port: PortID, to: u64, to_port: PortID, length: u32 },
+    Remove{ entity: u64 },
+    SetRecipe{ machine: u64, recipe: Option<ResourceUUID> },
+    Disconnect{ machine: u64, port: PortID },
+    ConfigurePortFilter{ machine: u64, port: PortID, filter: Option<ResourceUUID> },
+    PlaceMachineAt{ kind: MachineUUID, recipe: Option<ResourceUUID>, position: IVec2, rotation: Rotation },
+    PlacePipe{ from: u64, from_port: PortID, to: u64, to_port: PortID, path: Vec<IVec2> },
+}
+
+impl Encode for ServerMessage {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Snapshot{ snapshot, machines, connections } => {
+                0u8.encode(out);
+                snapshot.encode(out);
+                machines.encode(out);
+                connections.encode(out);
+            },
+            Self::Delta(delta) => {
+                1u8.encode(out);
+                delta.encode(out);
+            },
+            Self::BuildResult{ request, result } => {
+                2u8.encode(out);
+                request.encode(out);
+                result.encode(out);
+            },
+            Self::Structure{ snapshot, machines, connections, removed } => {
+                3u8.encode(out);
+                snapshot.encode(out);
+                machines.encode(out);
+                connections.encode(out);
+                removed.encode(out);
+            },
+        }
+    }
+}
+
+impl Decode for ServerMessage {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        match u8::decode(input)? {
+            0 => Ok(Self::Snapshot{ snapshot: Decode::decode(input)?, machines: Decode::decode(input)?, connections: Decode::decode(input)? }),
+            1 => Ok(Self::Delta(Decode::decode(input)?)),
+            2 => Ok(Self::BuildResult{ request: Decode::decode(input)?, result: Decode::decode(input)? }),
+            3 => Ok(Self::Structure{
+                snapshot:    Decode::decode(input)?,
+                machines:    Decode::decode(input)?,
+                connections: Decode::decode(input)?,
+                removed:     Decode::decode(input)?,
+            }),
+            _ => Err(NetError::Decode("Unknown server message")),
+        }
+    }
+}
+
+impl Encode for FactoryDelta {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.tick.encode(out);
+        self.ports.encode(out);
+        self.pipes.encode(out);
+        self.machines.encode(out);
+    }
+}
+
+impl Decode for FactoryDelta {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        Ok(Self{
+            tick:     Decode::decode(input)?,
+            ports:    Decode::decode(input)?,
+            pipes:    Decode::decode(input)?,
+            machines: Decode::decode(input)?,
+        })
+    }
+}
+
+impl Encode for ClientMessage {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Build{ request, command } => {
+                0u8.encode(out);
+                request.encode(out);
+                command.encode(out);
+            },
+        }
+    }
+}
+
+impl Decode for ClientMessage {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        match u8::decode(input)? {
+            0 => Ok(Self::Build{ request: Decode::decode(input)?, command: Decode::decode(input)? }),
+            _ => Err(NetError::Decode("Unknown client message")),
+        }
+    }
+}
+
+impl Encode for BuildRequest {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::PlaceMachine{ kind, recipe } => {
+                0u8.encode(out);
+                kind.encode(out);
+                recipe.encode(out);
+            },
+            Self::Connect{ from, from_port, to, to_port, length } => {
+                1u8.encode(out);
+                from.encode(out);
+                from_port.encode(out);
+                to.encode(out);
+                to_port.encode(out);
+                length.encode(out);
+            },
+            Self::Remove{ entity } => {
+                2u8.encode(out);
+                entity.encode(out);
+            },
+            Self::SetRecipe{ machine, recipe } => {
+                3u8.encode(out);
+                machine.encode(out);
+                recipe.encode(out);
+            },
+            Self::Disconnect{ machine, port } => {
+                4u8.encode(out);
+                machine.encode(out);
+                port.encode(out);
+            },
+            Self::ConfigurePortFilter{ machine, port, filter } => {
+                5u8.encode(out);
+                machine.encode(out);
+                port.encode(out);
+                filter.encode(out);
+            },
+            Self::PlaceMachineAt{ kind, recipe, position, rotation } => {
+                6u8.encode(out);
+                kind.encode(out);
+                recipe.encode(out);
+                position.encode(out);
+                rotation.encode(out);
+            },
+            Self::PlacePipe{ from, from_port, to, to_port, path } => {
+                7u8.encode(out);
+                from.encode(out);
+                from_port.encode(out);
+                to.encode(out);
+                to_port.encode(out);
+                path.encode(out);
+            },
+        }
+    }
+}
+
+impl Decode for BuildRequest {
+    fn decode(input: &mut Reader) -> Result<Self, NetError> {
+        match u8::decode(input)? {
+            0 => Ok(Self::PlaceMachine{ kind: Decode::decode(input)?, recipe: Decode::decode(input)? }),
+            1 => Ok(Self::Connect{
+                from:      Decode::decode(input)?,
+                from_port: Decode::decode(input)?,
+                to:        Decode::decode(input)?,
+                to_port:   Decode::decode(input)?,
+                length:    Decode::decode(input)?,
+            }),
+            2 => Ok(Self::Remove{ entity: Decode::decode(input)? }),
+            3 => Ok(Self::SetRecipe{ machine: Decode::decode(input)?, recipe: Decode::decode(input)? }),
+            4 => Ok(Self::Disconnect{ machine: Decode::decode(input)?, port: Decode::decode(input)? }),
+            5 => Ok(Self::ConfigurePortFilter{ machine: Decode::decode(input)?, port: Decode::decode(input)?, filter: Decode::decode(input)? }),
+            6 => Ok(Self::PlaceMachineAt{
+                kind:     Decode::decode(input)?,
+                recipe:   Decode::decode(input)?,
+                position: Decode::decode(input)?,
+                rotation: Decode::decode(input)?,
+            }),
+            7 => Ok(Self::PlacePipe{
+                from:      Decode::decode(input)?,
+                from_port: Decode::decode(input)?,
+                to:        Decode::decode(input)?,
+                to_port:   Decode::decode(input)?,
+                path:      Decode::decode(input)?,
+            }),
+            _ => Err(NetError::Decode("Unknown build request")),
+        }
+    }
+}
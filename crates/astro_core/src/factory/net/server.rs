@@ -0,0 +1,278 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use std::net::{TcpListener, SocketAddr, ToSocketAddrs};
+
+use bevy::{prelude::*, ecs::event::{Events, ManualEventReader}, utils::HashMap};
+
+use super::{
+    NetConnection, NetError, ServerMessage, ClientMessage, BuildRequest, FactoryDelta, FactorySnapshot, Machine,
+    Ports, ResourceID, capture_ports
+};
+use crate::factory::{
+    CommandError, CommandId, ConnectionIndex, FactoryCommand, FactoryCommandQueue, FactoryCommandResult, FactoryTick, GridPosition, Pipe,
+    PipeGap, PipeHandle, PipePath, PipePool, PipeSimple, PortFilter, PortSend, PortRecv, Rotation, capture_connection
+};
+
+/// Accepts clients and replicates the factory to them, insert it before
+/// adding `ReplicationServerPlugin`.
+pub struct ReplicationServer {
+    listener:    TcpListener,
+    clients:     Vec<(u32, NetConnection)>,
+    next_client: u32,
+    /// Queued build requests by command, with the client and request ID to answer.
+    requests:    HashMap<CommandId, (u32, u32)>,
+    results:     ManualEventReader<FactoryCommandResult>,
+}
+
+impl ReplicationServer {
+
+    pub fn bind(addr: impl ToSocketAddrs) -> Result<Self, NetError> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self{ listener, clients: Vec::new(), next_client: 0, requests: HashMap::default(), results: ManualEventReader::default() })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr().ok()
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+}
+
+/// Changes collected this frame, sent at the end of `CoreStage::Last`.
+#[derive(Default)]
+pub struct PendingReplication {
+    pub delta:     FactoryDelta,
+    /// Entities added, removed or rewired, sent as a partial snapshot.
+    pub structure: Vec<Entity>,
+}
+
+pub struct ReplicationServerPlugin;
+
+impl Plugin for ReplicationServerPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<PendingReplication>()
+            .add_system_to_stage(CoreStage::First, receive_clients.exclusive_system())
+            .add_system_to_stage(CoreStage::Last, collect_port_changes)
+            .add_system_to_stage(CoreStage::Last, collect_machine_changes)
+            .add_system_to_stage(CoreStage::Last, collect_structure_changes)
+            .add_system_to_stage(CoreStage::Last, collect_pooled_pipe_changes)
+            .add_system_to_stage(CoreStage::Last, send_replication.exclusive_system().at_end());
+
+        register_replicated_pipe::<PipeSimple>(app);
+        register_replicated_pipe::<PipeGap>(app);
+    }
+}
+
+pub fn register_replicated_pipe<T: Pipe + Component>(app: &mut App) {
+    app.add_system_to_stage(CoreStage::Last, collect_pipe_changes::<T>);
+}
+
+/// Accepts new clients, sending them a snapshot, and queues their valid build
+/// requests, answered once applied by `send_replication`.
+pub fn receive_clients(world: &mut World) {
+    if !world.contains_resource::<ReplicationServer>() { return; }
+
+    world.resource_scope(|world, mut server: Mut<ReplicationServer>| {
+        while let Ok((stream, _)) = server.listener.accept() {
+            if let Ok(mut client) = NetConnection::new(stream) {
+                if let Some(snapshot) = snapshot_message(world) { client.send(&snapshot); }
+                let id = server.next_client;
+                server.next_client = id.wrapping_add(1);
+                server.clients.push((id, client));
+            }
+        }
+
+        let clients = std::mem::take(&mut server.clients);
+        for (id, mut client) in clients {
+            let messages = match client.receive::<ClientMessage>() {
+                Ok(messages) => messages,
+                Err(_)       => continue,
+            };
+
+            for message in messages {
+                match message {
+                    ClientMessage::Build{ request, command } => match validate_request(world, &command) {
+                        Ok(()) => {
+                            let command = world.get_resource_mut::<FactoryCommandQueue>().unwrap().push(build_command(command));
+                            server.requests.insert(command, (id, request));
+                        },
+                        Err(e) => client.send(&ServerMessage::BuildResult{ request, result: Err(e.to_string()) }),
+                    },
+                }
+            }
+
+            if client.flush().is_ok() { server.clients.push((id, client)); }
+        }
+    });
+}
+
+/// Rejects requests naming entities outside the factory before they're
+/// queued, since clients may send the bits of any entity.
+pub fn validate_request(world: &World, request: &BuildRequest) -> Result<(), CommandError> {
+    if let BuildRequest::Remove{ entity } = *request {
+        let entity = Entity::from_bits(entity);
+        let found  = world.get_entity(entity).map_or(false, |v| v.contains::<Ports>() || v.contains::<PortSend>() || v.contains::<PortRecv>());
+        if !found { return Err(CommandError::NotFound(entity)); }
+    }
+    Ok(())
+}
+
+/// Converts a request into a command, entities are sent as their bits.
+pub fn build_command(request: BuildRequest) -> FactoryCommand {
+    match request {
+        BuildRequest::PlaceMachine{ kind, recipe } => FactoryCommand::PlaceMachine{ kind, recipe: recipe.map(ResourceID::intern) },
+        BuildRequest::Connect{ from, from_port, to, to_port, length } => FactoryCommand::Connect{
+            from: Entity::from_bits(from),
+            from_port,
+            to:   Entity::from_bits(to),
+            to_port,
+            length,
+        },
+        BuildRequest::Remove{ entity } => FactoryCommand::Remove{ entity: Entity::from_bits(entity) },
+        BuildRequest::SetRecipe{ machine, recipe } => FactoryCommand::SetRecipe{ machine: Entity::from_bits(machine), recipe: recipe.map(ResourceID::intern) },
+        BuildRequest::Disconnect{ machine, port } => FactoryCommand::Disconnect{ machine: Entity::from_bits(machine), port },
+        BuildRequest::ConfigurePortFilter{ machine, port, filter } => FactoryCommand::ConfigurePortFilter{
+            machine: Entity::from_bits(machine),
+            port,
+            filter:  filter.map(ResourceID::intern),
+        },
+        BuildRequest::PlaceMachineAt{ kind, recipe, position, rotation } => FactoryCommand::PlaceMachineAt{
+            kind,
+            recipe: recipe.map(ResourceID::intern),
+            position,
+            rotation,
+        },
+        BuildRequest::PlacePipe{ from, from_port, to, to_port, path } => FactoryCommand::PlacePipe{
+            from: Entity::from_bits(from),
+            from_port,
+            to:   Entity::from_bits(to),
+            to_port,
+            path,
+        },
+    }
+}
+
+pub fn snapshot_message(world: &mut World) -> Option<ServerMessage> {
+    let (snapshot, entities) = FactorySnapshot::capture_entities(world).ok()?;
+    Some(ServerMessage::Snapshot{
+        snapshot:    snapshot.to_string(),
+        machines:    entities.machines.iter().map(|e| e.to_bits()).collect(),
+        connections: entities.connections.iter().map(|e| e.to_bits()).collect(),
+    })
+}
+
+/// A partial snapshot of entities that changed structurally, along with the
+/// machines their connections refer to, and those no longer replicated.
+pub fn structure_message(world: &World, mut entities: Vec<Entity>) -> Option<ServerMessage> {
+    // Connections of a removed machine lose that end.
+    if let Some(index) = world.get_resource::<ConnectionIndex>() {
+        let attached = entities.iter().filter(|&&e| world.get::<Ports>(e).is_none()).flat_map(|&e| index.attached(e)).collect::<Vec<_>>();
+        entities.extend(attached);
+    }
+    entities.sort_unstable();
+    entities.dedup();
+
+    let is_machine    = |e: Entity| world.get::<Ports>(e).is_some();
+    let is_connection = |e: Entity| (world.get::<PortSend>(e).is_some() || world.get::<PortRecv>(e).is_some()) && capture_connection(world, e).is_some();
+    let connections   = entities.iter().copied().filter(|&e| is_connection(e)).collect::<Vec<_>>();
+    let removed       = entities.iter().copied().filter(|&e| !is_machine(e) && !is_connection(e)).map(Entity::to_bits).collect();
+
+    let ends = connections.iter().flat_map(|&e| [world.get::<PortSend>(e).map(|v| v.0), world.get::<PortRecv>(e).map(|v| v.0)]).flatten();
+    let mut machines = entities.iter().copied().chain(ends).filter(|&e| is_machine(e)).collect::<Vec<_>>();
+    machines.sort_unstable();
+    machines.dedup();
+
+    let snapshot = FactorySnapshot::capture_with(world, &machines, &connections).ok()?;
+    Some(ServerMessage::Structure{
+        snapshot:    snapshot.to_string(),
+        machines:    machines.iter().map(|e| e.to_bits()).collect(),
+        connections: connections.iter().map(|e| e.to_bits()).collect(),
+        removed,
+    })
+}
+
+pub fn collect_port_changes(mut pending: ResMut<PendingReplication>, query: Query<(Entity, &Ports), Changed<Ports>>) {
+    for (entity, ports) in query.iter() {
+        if let Ok(values) = capture_ports(ports) {
+            pending.delta.ports.push((entity.to_bits(), values));
+        }
+    }
+}
+
+pub fn collect_pipe_changes<T: Pipe + Component>(mut pending: ResMut<PendingReplication>, query: Query<(Entity, &T), Changed<T>>) {
+    for (entity, pipe) in query.iter() {
+        let mut packets = Vec::new();
+        pipe.for_each_packet(&mut |tick, resource| if let Some(uuid) = resource.uuid() { packets.push((tick, uuid)); });
+        pending.delta.pipes.push((entity.to_bits(), packets));
+    }
+}
+
+/// Pooled connections have no component to detect changes on, so the pool
+/// flags the rings it touched.
+pub fn collect_pooled_pipe_changes(mut pending: ResMut<PendingReplication>, mut pool: ResMut<PipePool>, query: Query<(Entity, &PipeHandle)>) {
+    for (entity, &handle) in query.iter() {
+        if !pool.take_changed(handle) { continue; }
+        let packets = pool.packets(handle).filter_map(|(tick, resource)| resource.uuid().map(|uuid| (tick, uuid))).collect();
+        pending.delta.pipes.push((entity.to_bits(), packets));
+    }
+}
+
+pub fn collect_machine_changes(mut pending: ResMut<PendingReplication>, query: Query<(Entity, &Machine), Changed<Machine>>) {
+    for (entity, machine) in query.iter() {
+        pending.delta.machines.push((entity.to_bits(), machine.recipe.and_then(|v| v.uuid())));
+    }
+}
+
+pub fn collect_structure_changes(
+    mut pending:    ResMut<PendingReplication>,
+    changed:        Query<Entity, Or<(Added<Ports>, Changed<PortSend>, Changed<PortRecv>, Changed<PortFilter>, Changed<GridPosition>, Changed<Rotation>, Changed<PipePath>)>>,
+    removed_ports:  RemovedComponents<Ports>,
+    removed_filter: RemovedComponents<PortFilter>,
+    removed_send:   RemovedComponents<PortSend>,
+    removed_recv:   RemovedComponents<PortRecv>,
+) {
+    let removed = removed_ports.iter().chain(removed_filter.iter()).chain(removed_send.iter()).chain(removed_recv.iter());
+    pending.structure.extend(changed.iter().chain(removed));
+}
+
+/// Answers applied build requests, then sends this frame's structural
+/// changes, if any, and delta to every client.
+pub fn send_replication(world: &mut World) {
+    if world.contains_resource::<ReplicationServer>() {
+        world.resource_scope(|world, mut server: Mut<ReplicationServer>| {
+            let server  = &mut *server;
+            let results = world.get_resource::<Events<FactoryCommandResult>>().unwrap();
+            for applied in server.results.iter(results) {
+                let (client, request) = match server.requests.remove(&applied.id) {
+                    Some(v) => v,
+                    None    => continue,
+                };
+                let result = applied.result.map(|v| v.map(|e| e.to_bits())).map_err(|e| e.to_string());
+                if let Some((_, connection)) = server.clients.iter_mut().find(|(id, _)| *id == client) {
+                    connection.send(&ServerMessage::BuildResult{ request, result });
+                }
+            }
+        });
+    }
+
+    let pending = std::mem::take(&mut *world.get_resource_mut::<PendingReplication>().unwrap());
+    if world.get_resource::<ReplicationServer>().map_or(true, |v| v.clients.is_empty()) { return; }
+
+    let mut messages = Vec::with_capacity(2);
+    if !pending.structure.is_empty() { messages.extend(structure_message(world, pending.structure)); }
+    let tick = world.get_resource::<FactoryTick>().map_or(0, |v| v.0);
+    messages.push(ServerMessage::Delta(FactoryDelta{ tick, ..pending.delta }));
+
+    let mut server = world.get_resource_mut::<ReplicationServer>().unwrap();
+    server.clients.retain_mut(|(_, client)| {
+        messages.iter().for_each(|v| client.send(v));
+        client.flush().is_ok()
+    });
+}
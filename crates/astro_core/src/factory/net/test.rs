@@ -0,0 +1,257 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use std::{net::{TcpListener, SocketAddr}, time::Duration};
+
+use bevy::prelude::*;
+
+use super::*;
+use crate::factory::{ConnectionBuilder, ConnectionError, FactoryPlugins, FactoryTick, PipeSimple, MACHINE_SOURCE, MACHINE_SINK, MAX_PIPE_LENGTH, spawn_machine};
+
+fn round_trip<T: Encode + Decode + PartialEq + std::fmt::Debug>(value: T) {
+    let mut buffer = Vec::new();
+    value.encode(&mut buffer);
+    let mut reader = Reader::new(&buffer);
+    assert_eq!(T::decode(&mut reader), Ok(value));
+    assert!(reader.is_empty());
+}
+
+#[test]
+fn codec_round_trip() {
+    let speed = ResourceUUID::new("SPEED");
+    round_trip(ServerMessage::Delta(FactoryDelta{
+        tick:     42,
+        ports:    vec![(7, [None, Some((speed, 3)), None, None])],
+        pipes:    vec![(9, vec![(40, speed), (41, speed)])],
+        machines: vec![(7, Some(speed)), (8, None)],
+    }));
+    round_trip(ServerMessage::Snapshot{ snapshot: "astro 1\ntick 0\n".to_string(), machines: vec![1, 2], connections: vec![3] });
+    round_trip(ServerMessage::BuildResult{ request: 3, result: Err("unknown machine kind X".to_string()) });
+    round_trip(ServerMessage::Structure{ snapshot: "astro 1\ntick 0\n".to_string(), machines: vec![1], connections: vec![], removed: vec![4, 5] });
+    round_trip(ClientMessage::Build{ request: 1, command: BuildRequest::PlaceMachine{ kind: MACHINE_SINK, recipe: None } });
+    round_trip(ClientMessage::Build{ request: 2, command: BuildRequest::Connect{ from: 1, from_port: PortID::B, to: 2, to_port: PortID::A, length: 8 } });
+    round_trip(ClientMessage::Build{ request: 3, command: BuildRequest::Remove{ entity: 5 } });
+    round_trip(ClientMessage::Build{ request: 4, command: BuildRequest::SetRecipe{ machine: 5, recipe: Some(speed) } });
+    round_trip(ClientMessage::Build{ request: 5, command: BuildRequest::Disconnect{ machine: 5, port: PortID::C } });
+    round_trip(ClientMessage::Build{ request: 6, command: BuildRequest::ConfigurePortFilter{ machine: 5, port: PortID::A, filter: None } });
+    round_trip(ClientMessage::Build{ request: 7, command: BuildRequest::PlaceMachineAt{ kind: MACHINE_SINK, recipe: None, position: IVec2::new(-3, 7), rotation: Rotation::West } });
+    round_trip(ClientMessage::Build{ request: 8, command: BuildRequest::PlacePipe{ from: 1, from_port: PortID::B, to: 2, to_port: PortID::A, path: vec![IVec2::new(1, 0), IVec2::new(2, 0)] } });
+}
+
+#[test]
+fn codec_truncated() {
+    let mut buffer = Vec::new();
+    ServerMessage::Delta(FactoryDelta{ tick: 1, ..Default::default() }).encode(&mut buffer);
+    buffer.pop();
+    assert_eq!(ServerMessage::decode(&mut Reader::new(&buffer)), Err(NetError::Decode("Unexpected end of message")));
+    assert_eq!(ServerMessage::decode(&mut Reader::new(&[9])), Err(NetError::Decode("Unknown server message")));
+}
+
+/// Polls until messages arrive, updating the app in between.
+fn receive<M: Decode>(connection: &mut NetConnection, app: &mut App) -> Vec<M> {
+    for _ in 0..1000 {
+        app.update();
+        let messages = connection.receive::<M>().unwrap();
+        if !messages.is_empty() { return messages; }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    panic!("no message received");
+}
+
+#[test]
+fn transport_framing() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = NetConnection::connect(listener.local_addr().unwrap()).unwrap();
+    let mut server = NetConnection::new(listener.accept().unwrap().0).unwrap();
+
+    let sent = (0..100u32).map(|i| ClientMessage::Build{ request: i, command: BuildRequest::Remove{ entity: i as u64 } }).collect::<Vec<_>>();
+    sent.iter().for_each(|v| client.send(v));
+    client.flush().unwrap();
+
+    let mut received = Vec::new();
+    for _ in 0..1000 {
+        received.extend(server.receive::<ClientMessage>().unwrap());
+        if received.len() == sent.len() { break; }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(received, sent);
+
+    drop(client);
+    std::thread::sleep(Duration::from_millis(10));
+    assert_eq!(server.receive::<ClientMessage>(), Err(NetError::Closed));
+}
+
+fn server_app() -> (App, SocketAddr) {
+    let server = ReplicationServer::bind("127.0.0.1:0").unwrap();
+    let addr   = server.local_addr().unwrap();
+
+    let mut app = App::new();
+    app
+        .add_plugins(FactoryPlugins)
+        .insert_resource(server)
+        .add_plugin(ReplicationServerPlugin);
+
+    let speed  = ResourceID::intern(ResourceUUID::new("SPEED"));
+    let source = spawn_machine(&mut app.world, MACHINE_SOURCE, Some(speed)).unwrap();
+    let sink   = spawn_machine(&mut app.world, MACHINE_SINK, None).unwrap();
+    ConnectionBuilder::new(4)
+        .recv_from(source, PortID::B)
+        .send_to(sink, PortID::A)
+        .build::<PipeSimple>(&mut app.world)
+        .unwrap();
+
+    (app, addr)
+}
+
+#[test]
+fn server_replication() {
+    let (mut app, addr) = server_app();
+    let mut client = NetConnection::connect(addr).unwrap();
+
+    let (snapshot, machines) = match receive::<ServerMessage>(&mut client, &mut app).remove(0) {
+        ServerMessage::Snapshot{ snapshot, machines, connections } => {
+            assert_eq!(connections.len(), 1);
+            (snapshot.parse::<FactorySnapshot>().unwrap(), machines)
+        },
+        message => panic!("expected snapshot, got {:?}", message),
+    };
+    assert_eq!(snapshot.machines.len(), 2);
+    assert_eq!(snapshot.connections.len(), 1);
+
+    let delta = loop {
+        let delta = receive::<ServerMessage>(&mut client, &mut app).into_iter().find_map(|v| match v {
+            ServerMessage::Delta(delta) => Some(delta),
+            _ => None,
+        });
+        if let Some(delta) = delta { break delta; }
+    };
+    assert!(delta.tick > 0);
+    assert!(delta.ports.iter().all(|(e, _)| machines.contains(e)));
+    assert!(!delta.ports.is_empty());
+
+    let other = app.world.spawn().id();
+    client.send(&ClientMessage::Build{ request: 7, command: BuildRequest::PlaceMachine{ kind: MACHINE_SINK, recipe: None } });
+    client.send(&ClientMessage::Build{ request: 8, command: BuildRequest::Remove{ entity: u64::MAX } });
+    client.send(&ClientMessage::Build{ request: 9, command: BuildRequest::Remove{ entity: other.to_bits() } });
+    client.send(&ClientMessage::Build{ request: 10, command: BuildRequest::Remove{ entity: machines[0] } });
+    client.flush().unwrap();
+
+    let mut results = Vec::new();
+    let mut added   = Vec::new();
+    let mut removed = Vec::new();
+    while results.len() < 4 || added.is_empty() || removed.is_empty() {
+        for message in receive::<ServerMessage>(&mut client, &mut app) {
+            match message {
+                ServerMessage::BuildResult{ request, result } => results.push((request, result.is_ok())),
+                ServerMessage::Structure{ snapshot, machines: structure, removed: gone, .. } => {
+                    let snapshot = snapshot.parse::<FactorySnapshot>().unwrap();
+                    added.extend(structure.into_iter().zip(snapshot.machines).filter(|(e, _)| !machines.contains(e)).map(|(_, v)| v.kind));
+                    removed.extend(gone);
+                },
+                ServerMessage::Snapshot{ .. } | ServerMessage::Delta(_) => {},
+            }
+        }
+    }
+    results.sort_unstable();
+    assert_eq!(results, vec![(7, true), (8, false), (9, false), (10, true)]);
+    assert_eq!(added, vec![Some(MACHINE_SINK)]);
+    assert!(removed.contains(&machines[0]));
+    assert!(app.world.get_entity(other).is_some());
+}
+
+/// Lengths are checked before a pipe allocates its slots, so one request
+/// can't exhaust the server's memory.
+#[test]
+fn server_rejects_oversized() {
+    let (mut app, addr) = server_app();
+    let mut client = NetConnection::connect(addr).unwrap();
+    let machines = match receive::<ServerMessage>(&mut client, &mut app).remove(0) {
+        ServerMessage::Snapshot{ machines, .. } => machines,
+        message => panic!("expected snapshot, got {:?}", message),
+    };
+
+    let path = (0..=MAX_PIPE_LENGTH as i32).map(|x| IVec2::new(x, 0)).collect();
+    client.send(&ClientMessage::Build{ request: 1, command: BuildRequest::Connect{ from: machines[0], from_port: PortID::C, to: machines[1], to_port: PortID::D, length: u32::MAX } });
+    client.send(&ClientMessage::Build{ request: 2, command: BuildRequest::PlacePipe{ from: machines[0], from_port: PortID::C, to: machines[1], to_port: PortID::D, path } });
+    client.flush().unwrap();
+
+    let mut results = Vec::new();
+    while results.len() < 2 {
+        for message in receive::<ServerMessage>(&mut client, &mut app) {
+            if let ServerMessage::BuildResult{ request, result } = message { results.push((request, result)); }
+        }
+    }
+    results.sort_unstable();
+    assert_eq!(results, vec![
+        (1, Err(ConnectionError::TooLong(u32::MAX).to_string())),
+        (2, Err(ConnectionError::TooLong(MAX_PIPE_LENGTH + 1).to_string())),
+    ]);
+    assert_eq!(app.world.query::<&PipeSimple>().iter(&app.world).count(), 1);
+}
+
+#[test]
+fn server_replicates_pooled() {
+    let (mut app, addr) = server_app();
+    let machines = app.world.query::<(Entity, &Machine)>().iter(&app.world).map(|(e, m)| (m.kind, e)).collect::<Vec<_>>();
+    let find     = |kind| machines.iter().find(|v| v.0 == kind).unwrap().1;
+    let (source, sink) = (find(MACHINE_SOURCE), find(MACHINE_SINK));
+    let pooled = ConnectionBuilder::new(4)
+        .recv_from(source, PortID::C)
+        .send_to(sink, PortID::D)
+        .build_pooled(&mut app.world)
+        .unwrap();
+    let mut client = NetConnection::connect(addr).unwrap();
+
+    let packets = loop {
+        let packets = receive::<ServerMessage>(&mut client, &mut app).into_iter().find_map(|v| match v {
+            ServerMessage::Delta(delta) => delta.pipes.into_iter().find(|(e, p)| *e == pooled.to_bits() && !p.is_empty()),
+            _ => None,
+        });
+        if let Some((_, packets)) = packets { break packets; }
+    };
+    assert!(packets.iter().all(|&(_, uuid)| uuid == ResourceUUID::new("SPEED")));
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn client_mirror() {
+    let (mut server, addr) = server_app();
+    let mut client = App::new();
+    client
+        .insert_resource(ReplicationClient::connect(addr).unwrap())
+        .add_plugin(ReplicationClientPlugin);
+
+    for _ in 0..1000 {
+        server.update();
+        client.update();
+        let tick = client.world.get_resource::<FactoryTick>().unwrap().0;
+        if tick > 10 { break; }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    let machines = client.world.query_filtered::<&Machine, With<Replicated>>().iter(&client.world).count();
+    let pipes    = client.world.query_filtered::<&PipeSimple, With<Replicated>>().iter(&client.world).count();
+    assert_eq!(machines, 2);
+    assert_eq!(pipes, 1);
+
+    let speed = ResourceID::intern(ResourceUUID::new("SPEED"));
+    let source = client.world.query::<&Machine>().iter(&client.world).find(|v| v.kind == MACHINE_SOURCE).copied();
+    assert_eq!(source.map(|v| v.recipe), Some(Some(speed)));
+
+    let source = client.world.query::<(&Replicated, &Machine)>().iter(&client.world).find(|(_, v)| v.kind == MACHINE_SOURCE).map(|(v, _)| v.0).unwrap();
+    let mut replication = client.world.get_resource_mut::<ReplicationClient>().unwrap();
+    replication.request(BuildRequest::PlaceMachine{ kind: MACHINE_SINK, recipe: None });
+    replication.request(BuildRequest::Remove{ entity: source });
+
+    let mut kinds = Vec::new();
+    for _ in 0..1000 {
+        server.update();
+        client.update();
+        kinds = client.world.query_filtered::<&Machine, With<Replicated>>().iter(&client.world).map(|v| v.kind).collect::<Vec<_>>();
+        if kinds == [MACHINE_SINK, MACHINE_SINK] { break; }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(kinds, vec![MACHINE_SINK, MACHINE_SINK]);
+    assert_eq!(client.world.get_resource::<ReplicationClient>().unwrap().local(source), None);
+}
@@ -0,0 +1,83 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use std::{io::{Read, Write, ErrorKind}, net::{TcpStream, ToSocketAddrs}};
+
+use super::{Encode, Decode, Reader, NetError};
+
+const MAX_FRAME_LENGTH: usize = 64 * 1024 * 1024;
+
+/// Non-blocking TCP stream carrying length prefixed messages.
+pub struct NetConnection {
+    stream: TcpStream,
+    read:   Vec<u8>,
+    write:  Vec<u8>,
+}
+
+impl NetConnection {
+
+    pub fn new(stream: TcpStream) -> Result<Self, NetError> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self{ stream, read: Vec::new(), write: Vec::new() })
+    }
+
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, NetError> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    /// Queues a message, it's written on the next `flush`.
+    pub fn send<M: Encode>(&mut self, message: &M) {
+        let start = self.write.len();
+        0u32.encode(&mut self.write);
+        message.encode(&mut self.write);
+        let length = (self.write.len() - start - 4) as u32;
+        self.write[start..start + 4].copy_from_slice(&length.to_le_bytes());
+    }
+
+    /// Writes as much of the queue as the socket accepts without blocking.
+    pub fn flush(&mut self) -> Result<(), NetError> {
+        while !self.write.is_empty() {
+            match self.stream.write(&self.write) {
+                Ok(0) => return Err(NetError::Closed),
+                Ok(n) => { self.write.drain(..n); },
+                Err(e) if e.kind() == ErrorKind::WouldBlock  => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads everything available and decodes the complete messages.
+    pub fn receive<M: Decode>(&mut self) -> Result<Vec<M>, NetError> {
+        let mut buffer = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(NetError::Closed),
+                Ok(n) => self.read.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock  => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut result = Vec::new();
+        let mut offset = 0;
+        while self.read.len() - offset >= 4 {
+            let length = u32::from_le_bytes(self.read[offset..offset + 4].try_into().unwrap()) as usize;
+            if length > MAX_FRAME_LENGTH { return Err(NetError::Decode("Message too large")); }
+            if self.read.len() - offset - 4 < length { break; }
+
+            let mut reader = Reader::new(&self.read[offset + 4..offset + 4 + length]);
+            result.push(M::decode(&mut reader)?);
+            if !reader.is_empty() { return Err(NetError::Decode("Trailing bytes")); }
+            offset += 4 + length;
+        }
+        self.read.drain(..offset);
+
+        Ok(result)
+    }
+
+}
@@ -0,0 +1,138 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use std::{ops::Range, time::{Duration, Instant}};
+
+use bevy::prelude::*;
+
+use super::{Dormant, FactoryStage, FactoryStageInternal, FactorySystem, FactoryTick, PortRecv, PortSend, Ports};
+
+/// Factory stages timed, by the name they're reported under.
+pub const PROFILED_STAGES: [&str; 4] = ["tick", "machine", "wake", "connection"];
+
+/// Labelled systems timed, in the order they run. Systems sharing a label,
+/// such as the connection systems of each pipe type, are timed together.
+pub const PROFILED_SYSTEMS: &[(&str, FactorySystem)] = &[
+    ("machine_source",       FactorySystem::MachineSource),
+    ("machine_passthrough",  FactorySystem::MachinePassthrough),
+    ("machine_sink",         FactorySystem::MachineSink),
+    #[cfg(feature = "scripting")]
+    ("machine_script",       FactorySystem::MachineScript),
+    ("connection_send_recv", FactorySystem::ConnectionSendRecv),
+    ("connection_recv",      FactorySystem::ConnectionRecv),
+    ("connection_send",      FactorySystem::ConnectionSend),
+    ("connection_pooled",    FactorySystem::ConnectionPooled),
+];
+
+/// Timings and entity counts of the last tick. Each labelled system is timed
+/// between its own start and end markers, ordered directly around it.
+#[derive(Debug, Clone, Default)]
+pub struct FactoryProfile {
+    /// Tick the profile was recorded on.
+    pub tick:        u32,
+    /// Time spent in each of `PROFILED_STAGES`.
+    pub stages:      [Duration; PROFILED_STAGES.len()],
+    /// Time spent in each of `PROFILED_SYSTEMS`.
+    pub systems:     [Duration; PROFILED_SYSTEMS.len()],
+    /// Entities with `Ports`.
+    pub machines:    u32,
+    pub connections: u32,
+    /// Machines and connections asleep at the end of the tick.
+    pub dormant:     u32,
+    stage_starts:    [Option<Instant>; PROFILED_STAGES.len()],
+    system_starts:   [Option<Instant>; PROFILED_SYSTEMS.len()],
+}
+
+impl FactoryProfile {
+
+    pub fn stages(&self) -> impl Iterator<Item = (&'static str, Duration)> + '_ {
+        PROFILED_STAGES.iter().copied().zip(self.stages.iter().copied())
+    }
+
+    pub fn systems(&self) -> impl Iterator<Item = (&'static str, Duration)> + '_ {
+        PROFILED_SYSTEMS.iter().map(|&(name, _)| name).zip(self.systems.iter().copied())
+    }
+
+    /// Time spent in all factory stages.
+    pub fn total(&self) -> Duration {
+        self.stages.iter().sum()
+    }
+
+    fn start_stage(&mut self, stage: usize) {
+        self.stage_starts[stage] = Some(Instant::now());
+    }
+
+    fn end_stage(&mut self, stage: usize) {
+        self.stages[stage] = self.stage_starts[stage].take().map_or(Duration::ZERO, |v| v.elapsed());
+    }
+
+    fn start_system(&mut self, system: usize) {
+        self.system_starts[system] = Some(Instant::now());
+    }
+
+    fn end_system(&mut self, system: usize) {
+        self.systems[system] = self.system_starts[system].take().map_or(Duration::ZERO, |v| v.elapsed());
+    }
+
+}
+
+pub fn count_profile_entities(
+    mut profile: ResMut<FactoryProfile>,
+    tick:        Res<FactoryTick>,
+    machines:    Query<(), With<Ports>>,
+    connections: Query<(), Or<(With<PortSend>, With<PortRecv>)>>,
+    dormant:     Query<(), With<Dormant>>,
+) {
+    profile.tick        = tick.0;
+    profile.machines    = machines.iter().count() as u32;
+    profile.connections = connections.iter().count() as u32;
+    profile.dormant     = dormant.iter().count() as u32;
+}
+
+pub struct FactoryProfilePlugin;
+
+impl Plugin for FactoryProfilePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FactoryProfile>();
+        profile_stage(app, 0, FactoryStageInternal::Tick);
+        profile_stage(app, 1, FactoryStage::Machine);
+        profile_stage(app, 2, FactoryStageInternal::Wake);
+        profile_stage(app, 3, FactoryStageInternal::Machine);
+        let machines = PROFILED_SYSTEMS.iter().position(|&(_, v)| v == FactorySystem::ConnectionSendRecv).unwrap();
+        profile_systems(app, FactoryStage::Machine,         0..machines);
+        profile_systems(app, FactoryStageInternal::Machine, machines..PROFILED_SYSTEMS.len());
+        app.add_system_to_stage(CoreStage::Last, count_profile_entities);
+    }
+}
+
+fn profile_stage(app: &mut App, stage: usize, label: impl StageLabel + Copy) {
+    let start = move |world: &mut World| world.get_resource_mut::<FactoryProfile>().unwrap().start_stage(stage);
+    let end   = move |world: &mut World| world.get_resource_mut::<FactoryProfile>().unwrap().end_stage(stage);
+    app.add_system_to_stage(label, start.exclusive_system().at_start());
+    app.add_system_to_stage(label, end.exclusive_system().at_end());
+}
+
+/// Brackets each labelled system of a stage with its own markers. The start
+/// marker also follows the previous system's end marker, so the brackets
+/// don't overlap.
+fn profile_systems(app: &mut App, label: impl StageLabel + Copy, systems: Range<usize>) {
+    for system in systems.clone() {
+        let target = PROFILED_SYSTEMS[system].1;
+        let start  = move |mut profile: ResMut<FactoryProfile>| profile.start_system(system);
+        let end    = move |mut profile: ResMut<FactoryProfile>| profile.end_system(system);
+        let start  = start.before(target);
+        if system > systems.start {
+            app.add_system_to_stage(label, start.after(SystemProfiled(system - 1)));
+        } else {
+            app.add_system_to_stage(label, start);
+        }
+        app.add_system_to_stage(label, end.label(SystemProfiled(system)).after(target));
+    }
+}
+
+/// End marker of a profiled system, by index into `PROFILED_SYSTEMS`.
+#[derive(SystemLabel, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+struct SystemProfiled(usize);
@@ -0,0 +1,33 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+use crate::factory::{FactoryCommand, FactoryPlugins, PortID, MACHINE_SOURCE, MACHINE_SINK, execute_command};
+
+#[test]
+fn records_each_tick() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+
+    let source = execute_command(&mut app.world, FactoryCommand::PlaceMachine{ kind: MACHINE_SOURCE, recipe: None }).unwrap().unwrap();
+    let sink   = execute_command(&mut app.world, FactoryCommand::PlaceMachine{ kind: MACHINE_SINK,   recipe: None }).unwrap().unwrap();
+    execute_command(&mut app.world, FactoryCommand::Connect{ from: source, from_port: PortID::B, to: sink, to_port: PortID::A, length: 3 }).unwrap();
+
+    app.update();
+    let profile = app.world.get_resource::<FactoryProfile>().unwrap();
+    assert_eq!((profile.tick, profile.machines, profile.connections), (1, 2, 1));
+    assert!(profile.stages().all(|(_, v)| v > Duration::ZERO));
+    assert!(profile.systems[0] > Duration::ZERO);
+    // Brackets don't overlap, so a stage's systems never add up to more than it.
+    let machines = PROFILED_SYSTEMS.iter().position(|&(_, v)| v == FactorySystem::ConnectionSendRecv).unwrap();
+    assert!(profile.systems[..machines].iter().sum::<Duration>() <= profile.stages[1]);
+    assert!(profile.systems[machines..].iter().sum::<Duration>() <= profile.stages[3]);
+    assert_eq!(profile.systems().map(|(name, _)| name).last(), Some("connection_pooled"));
+
+    app.update();
+    app.update();
+    let profile = app.world.get_resource::<FactoryProfile>().unwrap();
+    assert_eq!((profile.tick, profile.dormant), (3, 3));
+    assert!(profile.total() >= profile.stages[1]);
+}
@@ -0,0 +1,244 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use bevy::{prelude::*, transform::TransformSystem, utils::HashSet};
+
+use super::{Footprint, GridPosition, Machine, PacketPosition, Pipe, PipeGap, PipeHandle, PipePath, PipePool, PipeSimple, ResourceID, Rotation, FactoryTick};
+
+/// World units per grid tile.
+pub const TILE_SIZE: f32 = 32.0;
+
+const PIPE_Z:    f32 = 0.0;
+const MACHINE_Z: f32 = 1.0;
+const PACKET_Z:  f32 = 2.0;
+
+#[derive(SystemLabel, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum FactoryRenderSystem {
+    Clock,
+    Extract,
+    Draw,
+}
+
+/// A sprite drawing part of a factory entity, despawned along with it.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FactoryVisual(pub Entity);
+
+/// A sprite drawing a packet, reused for whichever packet needs it each frame.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FactoryPacketSprite;
+
+/// Where the frame falls between factory ticks, so packets can be drawn
+/// moving smoothly when ticks are slower than frames.
+#[derive(Debug, Default)]
+pub struct TickClock {
+    pub tick:     u32,
+    /// Seconds since startup the tick last advanced on.
+    pub changed:  f64,
+    /// Seconds between the last two ticks.
+    pub interval: f64,
+    /// Fraction of the interval passed since the last tick.
+    pub alpha:    f32,
+}
+
+impl TickClock {
+
+    pub fn update(&mut self, tick: u32, now: f64) {
+        if tick != self.tick {
+            self.interval = now - self.changed;
+            self.changed  = now;
+            self.tick     = tick;
+        }
+        self.alpha = if self.interval > 0.0 { ((now - self.changed) / self.interval).min(1.0) as f32 } else { 0.0 };
+    }
+
+}
+
+/// Packets extracted from every pipe this frame and the sprites they're
+/// drawn with. Buffers are kept across frames so drawing doesn't allocate.
+#[derive(Default)]
+pub struct FactoryPackets {
+    pub extracted: Vec<(Vec2, ResourceID)>,
+    positions:     Vec<PacketPosition>,
+    sprites:       Vec<Entity>,
+}
+
+/// Centre of a tile in world units.
+pub fn tile_translation(tile: IVec2) -> Vec2 {
+    tile.as_vec2() * TILE_SIZE
+}
+
+/// Point `position` tiles along a path, between tile centres. An empty path
+/// has no points, so is treated as the origin.
+pub fn path_point(path: &PipePath, position: f32) -> Vec2 {
+    if path.0.is_empty() { return Vec2::ZERO; }
+    let last  = path.0.len() - 1;
+    let index = (position.max(0.0) as usize).min(last);
+    let from  = tile_translation(path.0[index]);
+    let to    = tile_translation(path.0[(index + 1).min(last)]);
+    from.lerp(to, position - index as f32)
+}
+
+/// Colour a resource is drawn in, from its name so it's the same for every
+/// client whatever order resources were interned in.
+pub fn resource_color(resource: ResourceID) -> Color {
+    let hash = resource.uuid().map_or(resource.into_inner() as u32, |v| {
+        v.to_string().bytes().fold(0x811c9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+    });
+    Color::hsl((hash % 360) as f32, 0.7, 0.55)
+}
+
+/// Centre and size of the tiles a machine covers.
+pub fn machine_bounds(position: IVec2, rotation: Rotation, footprint: &Footprint) -> (Vec2, Vec2) {
+    let (min, max) = footprint.tiles(position, rotation).fold((position, position), |(min, max), v| (min.min(v), max.max(v)));
+    ((tile_translation(min) + tile_translation(max)) / 2.0, (max - min + IVec2::ONE).as_vec2() * TILE_SIZE)
+}
+
+pub struct FactoryRenderPlugin;
+
+impl Plugin for FactoryRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<TickClock>()
+            .init_resource::<FactoryPackets>()
+            .add_system_to_stage(CoreStage::PostUpdate, update_tick_clock.label(FactoryRenderSystem::Clock))
+            .add_system_to_stage(CoreStage::PostUpdate, spawn_machine_visuals)
+            .add_system_to_stage(CoreStage::PostUpdate, spawn_pipe_visuals)
+            .add_system_to_stage(CoreStage::PostUpdate, despawn_stale_visuals);
+        register_packet_extraction::<PipeSimple>(app);
+        register_packet_extraction::<PipeGap>(app);
+        app.add_system_to_stage(CoreStage::PostUpdate, extract_pooled_packets.label(FactoryRenderSystem::Extract).after(FactoryRenderSystem::Clock));
+        app.add_system_to_stage(CoreStage::PostUpdate, draw_packets
+            .label(FactoryRenderSystem::Draw)
+            .after(FactoryRenderSystem::Extract)
+            .before(TransformSystem::TransformPropagate));
+    }
+}
+
+/// Draws the packets of placed pipes of type `T`.
+pub fn register_packet_extraction<T: Pipe + Component>(app: &mut App) {
+    app.add_system_to_stage(CoreStage::PostUpdate, extract_packets::<T>.label(FactoryRenderSystem::Extract).after(FactoryRenderSystem::Clock));
+}
+
+pub fn update_tick_clock(time: Res<Time>, tick: Res<FactoryTick>, mut clock: ResMut<TickClock>) {
+    clock.update(tick.0, time.seconds_since_startup());
+}
+
+/// Machines are only drawn once placed on the grid, and redrawn whenever
+/// they move, turn or change recipe.
+pub fn spawn_machine_visuals(
+    mut commands: Commands,
+    visuals: Query<(Entity, &FactoryVisual)>,
+    q: Query<(Entity, &Machine, &GridPosition, &Rotation, &Footprint), Or<(Changed<GridPosition>, Changed<Rotation>, Changed<Footprint>, Changed<Machine>)>>,
+) {
+    despawn_visuals_of(&mut commands, &visuals, q.iter().map(|v| v.0));
+    for (entity, machine, position, &rotation, footprint) in q.iter() {
+        let (centre, size) = machine_bounds(position.0, rotation, footprint);
+        let color = machine.recipe.map_or(Color::GRAY, |v| resource_color(v).as_rgba() * 0.5);
+        commands.spawn_bundle(SpriteBundle{
+            sprite:    Sprite{ color, custom_size: Some(size - Vec2::splat(2.0)), ..Default::default() },
+            transform: Transform::from_translation(centre.extend(MACHINE_Z)),
+            ..Default::default()
+        }).insert(FactoryVisual(entity));
+    }
+}
+
+/// Pipes are redrawn whenever their path changes.
+pub fn spawn_pipe_visuals(
+    mut commands: Commands,
+    visuals: Query<(Entity, &FactoryVisual)>,
+    q: Query<(Entity, &PipePath), Changed<PipePath>>,
+) {
+    despawn_visuals_of(&mut commands, &visuals, q.iter().map(|v| v.0));
+    for (entity, path) in q.iter() {
+        for &tile in path.0.iter() {
+            commands.spawn_bundle(SpriteBundle{
+                sprite:    Sprite{ color: Color::DARK_GRAY, custom_size: Some(Vec2::splat(TILE_SIZE * 0.5)), ..Default::default() },
+                transform: Transform::from_translation(tile_translation(tile).extend(PIPE_Z)),
+                ..Default::default()
+            }).insert(FactoryVisual(entity));
+        }
+    }
+}
+
+/// Despawns visuals of entities that were removed or taken off the grid.
+pub fn despawn_stale_visuals(
+    mut commands: Commands,
+    visuals: Query<(Entity, &FactoryVisual)>,
+    drawn:   Query<(), Or<(With<GridPosition>, With<PipePath>)>>,
+) {
+    for (entity, &FactoryVisual(target)) in visuals.iter() {
+        if drawn.get(target).is_err() { commands.entity(entity).despawn(); }
+    }
+}
+
+fn despawn_visuals_of(commands: &mut Commands, visuals: &Query<(Entity, &FactoryVisual)>, targets: impl Iterator<Item = Entity>) {
+    let targets = targets.collect::<HashSet<_>>();
+    if targets.is_empty() { return; }
+    for (entity, &FactoryVisual(target)) in visuals.iter() {
+        if targets.contains(&target) { commands.entity(entity).despawn(); }
+    }
+}
+
+/// Appends the position of every packet in placed pipes of type `T`.
+pub fn extract_packets<T: Pipe + Component>(
+    tick:        Res<FactoryTick>,
+    clock:       Res<TickClock>,
+    mut packets: ResMut<FactoryPackets>,
+    q:           Query<(&T, &PipePath)>,
+) {
+    let packets = &mut *packets;
+    for (pipe, path) in q.iter() {
+        pipe.resolve_into(tick.0, clock.alpha, &mut packets.positions);
+        packets.extracted.extend(packets.positions.iter().map(|v| (path_point(path, v.position), v.resource)));
+    }
+}
+
+/// Appends the position of every packet in placed pooled pipes.
+pub fn extract_pooled_packets(
+    tick:        Res<FactoryTick>,
+    clock:       Res<TickClock>,
+    pool:        Res<PipePool>,
+    mut packets: ResMut<FactoryPackets>,
+    q:           Query<(&PipeHandle, &PipePath)>,
+) {
+    let packets = &mut *packets;
+    for (&handle, path) in q.iter() {
+        pool.resolve_into(handle, tick.0, clock.alpha, &mut packets.positions);
+        packets.extracted.extend(packets.positions.iter().map(|v| (path_point(path, v.position), v.resource)));
+    }
+}
+
+/// Moves a sprite onto each extracted packet, spawning more when there
+/// aren't enough and hiding the rest.
+pub fn draw_packets(
+    mut commands: Commands,
+    mut packets:  ResMut<FactoryPackets>,
+    mut sprites:  Query<(&mut Transform, &mut Sprite, &mut Visibility), With<FactoryPacketSprite>>,
+) {
+    let packets = &mut *packets;
+    for (i, &(point, resource)) in packets.extracted.iter().enumerate() {
+        let translation = point.extend(PACKET_Z);
+        let color       = resource_color(resource);
+        if let Some(&entity) = packets.sprites.get(i) {
+            if let Ok((mut transform, mut sprite, mut visibility)) = sprites.get_mut(entity) {
+                transform.translation = translation;
+                sprite.color          = color;
+                visibility.is_visible = true;
+            }
+        } else {
+            packets.sprites.push(commands.spawn_bundle(SpriteBundle{
+                sprite:    Sprite{ color, custom_size: Some(Vec2::splat(TILE_SIZE * 0.4)), ..Default::default() },
+                transform: Transform::from_translation(translation),
+                ..Default::default()
+            }).insert(FactoryPacketSprite).id());
+        }
+    }
+
+    for &entity in packets.sprites.iter().skip(packets.extracted.len()) {
+        if let Ok((_, _, mut visibility)) = sprites.get_mut(entity) { visibility.is_visible = false; }
+    }
+    packets.extracted.clear();
+}
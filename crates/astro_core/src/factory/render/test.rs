@@ -0,0 +1,133 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+use crate::factory::{ConnectionBuilder, FactoryPlugins, PortID, ResourceUUID, MACHINE_SOURCE, MACHINE_SINK, place_machine, place_pipe};
+
+fn speed() -> ResourceID {
+    ResourceID::intern(ResourceUUID::new("SPEED"))
+}
+
+#[test]
+fn tick_clock() {
+    let mut clock = TickClock::default();
+    clock.update(1, 1.0);
+    clock.update(2, 1.5);
+    assert_eq!((clock.interval, clock.alpha), (0.5, 0.0));
+    clock.update(2, 1.75);
+    assert_eq!(clock.alpha, 0.5);
+    clock.update(2, 3.0);
+    assert_eq!(clock.alpha, 1.0);
+}
+
+#[test]
+fn path_points() {
+    let path = PipePath(vec![IVec2::new(0, 0), IVec2::new(1, 0), IVec2::new(1, 1)]);
+    assert_eq!(path_point(&path, 0.0), Vec2::ZERO);
+    assert_eq!(path_point(&path, 1.5), Vec2::new(TILE_SIZE, TILE_SIZE * 0.5));
+    assert_eq!(path_point(&path, 2.0), Vec2::new(TILE_SIZE, TILE_SIZE));
+    assert_eq!(path_point(&PipePath(Vec::new()), 1.0), Vec2::ZERO);
+
+    let (centre, size) = machine_bounds(IVec2::ZERO, Rotation::East, &Footprint{ size: IVec2::new(2, 1), ..Footprint::UNIT });
+    assert_eq!((centre, size), (Vec2::new(0.0, -TILE_SIZE * 0.5), Vec2::new(TILE_SIZE, TILE_SIZE * 2.0)));
+}
+
+#[test]
+fn resource_colors() {
+    let iron = ResourceID::intern(ResourceUUID::new("IRON"));
+    assert_eq!(resource_color(speed()), resource_color(speed()));
+    assert_ne!(resource_color(speed()), resource_color(iron));
+}
+
+/// The render systems without any rendering plugins, so no GPU is needed.
+fn headless_app() -> App {
+    let mut app = App::new();
+    app
+        .add_plugins(FactoryPlugins)
+        .insert_resource(TickClock{ alpha: 0.5, ..Default::default() })
+        .init_resource::<FactoryPackets>()
+        .add_system_to_stage(CoreStage::PostUpdate, spawn_machine_visuals)
+        .add_system_to_stage(CoreStage::PostUpdate, spawn_pipe_visuals)
+        .add_system_to_stage(CoreStage::PostUpdate, despawn_stale_visuals)
+        .add_system_to_stage(CoreStage::PostUpdate, extract_packets::<PipeSimple>.label(FactoryRenderSystem::Extract))
+        .add_system_to_stage(CoreStage::PostUpdate, extract_pooled_packets.label(FactoryRenderSystem::Extract))
+        .add_system_to_stage(CoreStage::PostUpdate, draw_packets.after(FactoryRenderSystem::Extract));
+    app
+}
+
+#[test]
+fn draws_packets_headless() {
+    let mut app = headless_app();
+
+    let source = place_machine(&mut app.world, MACHINE_SOURCE, Some(speed()), IVec2::new(0, 0), Rotation::North).unwrap();
+    let sink   = place_machine(&mut app.world, MACHINE_SINK,   None,          IVec2::new(4, 0), Rotation::North).unwrap();
+    let pipe   = place_pipe(&mut app.world, source, PortID::B, sink, PortID::A, vec![IVec2::new(1, 0), IVec2::new(2, 0), IVec2::new(3, 0)]).unwrap();
+    app.update();
+    app.update();
+
+    let mut visuals = app.world.query::<&FactoryVisual>();
+    assert_eq!(visuals.iter(&app.world).count(), 5);
+
+    let mut packets = app.world.query_filtered::<(&Transform, &Sprite, &Visibility), With<FactoryPacketSprite>>();
+    let drawn = packets.iter(&app.world).map(|(transform, sprite, visibility)| (transform.translation.truncate(), sprite.color, visibility.is_visible)).collect::<Vec<_>>();
+    assert_eq!(drawn.len(), 2);
+    for point in [Vec2::new(TILE_SIZE * 2.5, 0.0), Vec2::new(TILE_SIZE * 1.5, 0.0)] {
+        assert!(drawn.contains(&(point, resource_color(speed()), true)));
+    }
+
+    app.world.despawn(pipe);
+    app.update();
+    assert_eq!(visuals.iter(&app.world).count(), 2);
+    assert!(packets.iter(&app.world).all(|(_, _, visibility)| !visibility.is_visible));
+}
+
+#[test]
+fn draws_pooled_packets_headless() {
+    let mut app = headless_app();
+    let source = place_machine(&mut app.world, MACHINE_SOURCE, Some(speed()), IVec2::new(0, 0), Rotation::North).unwrap();
+    let sink   = place_machine(&mut app.world, MACHINE_SINK,   None,          IVec2::new(4, 0), Rotation::North).unwrap();
+    let pipe   = ConnectionBuilder::new(3).recv_from(source, PortID::B).send_to(sink, PortID::A).build_pooled(&mut app.world).unwrap();
+    app.world.entity_mut(pipe).insert(PipePath(vec![IVec2::new(1, 0), IVec2::new(2, 0), IVec2::new(3, 0)]));
+    app.update();
+    app.update();
+
+    let mut packets = app.world.query_filtered::<(&Transform, &Visibility), With<FactoryPacketSprite>>();
+    let drawn = packets.iter(&app.world).filter(|(_, visibility)| visibility.is_visible).map(|(transform, _)| transform.translation.truncate()).collect::<Vec<_>>();
+    assert_eq!(drawn.len(), 2);
+    for point in [Vec2::new(TILE_SIZE * 2.5, 0.0), Vec2::new(TILE_SIZE * 1.5, 0.0)] {
+        assert!(drawn.contains(&point));
+    }
+}
+
+fn drawn(app: &mut App, target: Entity) -> Vec<(Vec2, Color)> {
+    app.world.query::<(&FactoryVisual, &Transform, &Sprite)>()
+        .iter(&app.world)
+        .filter(|(visual, _, _)| visual.0 == target)
+        .map(|(_, transform, sprite)| (transform.translation.truncate(), sprite.color))
+        .collect()
+}
+
+#[test]
+fn redraws_changed_visuals() {
+    let mut app  = headless_app();
+    let machine  = place_machine(&mut app.world, MACHINE_SINK, None, IVec2::new(0, 0), Rotation::North).unwrap();
+    let mut path = PipePath(vec![IVec2::new(0, 2), IVec2::new(1, 2)]);
+    let pipe     = app.world.spawn().insert(path.clone()).id();
+    app.update();
+
+    assert_eq!(drawn(&mut app, machine), vec![(Vec2::ZERO, Color::GRAY)]);
+    assert_eq!(drawn(&mut app, pipe).len(), 2);
+
+    app.world.entity_mut(machine).insert(GridPosition(IVec2::new(3, 0)));
+    app.world.get_mut::<Machine>(machine).unwrap().recipe = Some(speed());
+    path.0.push(IVec2::new(2, 2));
+    app.world.entity_mut(pipe).insert(path);
+    app.update();
+    assert_eq!(drawn(&mut app, machine), vec![(tile_translation(IVec2::new(3, 0)), resource_color(speed()).as_rgba() * 0.5)]);
+    assert_eq!(drawn(&mut app, pipe).len(), 3);
+
+    app.world.entity_mut(machine).remove::<GridPosition>();
+    app.update();
+    assert!(drawn(&mut app, machine).is_empty());
+}
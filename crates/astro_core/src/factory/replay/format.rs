@@ -0,0 +1,187 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+//! Line based text format wrapping a snapshot:
+//!
+//! ```text
+//! replay 1
+//! snapshot
+//! astro 1
+//! tick 120
+//! end
+//! command 120 place SOURCE SPEED
+//! command 120 place SINK -
+//! command 125 place_at SINK - 4,0 North
+//! command 130 connect 0:B 1:A 16
+//! command 135 pipe 0:B 2:A 1,0 2,0 3,0
+//! command 140 filter 1:A SPEED
+//! command 190 disconnect 1:A
+//! command 200 recipe 0 -
+//! command 210 remove 2
+//! command 220 restore 2
+//! astro 1
+//! tick 220
+//! end
+//! checksum 180 8f2c0a14d3e7b951
+//! ```
+//!
+//! A restore is followed by a snapshot of the entities it respawns.
+
+use std::str::FromStr;
+
+use crate::factory::{FormatError, Optional, format_lines, parse_header, parse_number, parse_tile};
+
+use super::{Replay, ReplayCommand, ReplayError, FactorySnapshot, MachineUUID, ResourceUUID, PortID};
+
+pub const REPLAY_VERSION: u32 = 1;
+
+impl std::fmt::Display for Replay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "replay {}", REPLAY_VERSION)?;
+        writeln!(f, "snapshot")?;
+        write!(f, "{}", self.snapshot)?;
+        writeln!(f, "end")?;
+
+        for (tick, command) in self.commands.iter() {
+            write!(f, "command {} ", tick)?;
+            match command {
+                ReplayCommand::PlaceMachine{ kind, recipe }                    => writeln!(f, "place {} {}", kind, Optional(*recipe))?,
+                ReplayCommand::PlaceMachineAt{ kind, recipe, position, rotation } => {
+                    writeln!(f, "place_at {} {} {},{} {:?}", kind, Optional(*recipe), position.x, position.y, rotation)?;
+                },
+                ReplayCommand::Connect{ from, from_port, to, to_port, length } => writeln!(f, "connect {}:{:?} {}:{:?} {}", from, from_port, to, to_port, length)?,
+                ReplayCommand::PlacePipe{ from, from_port, to, to_port, path }  => {
+                    write!(f, "pipe {}:{:?} {}:{:?}", from, from_port, to, to_port)?;
+                    for tile in path.iter() { write!(f, " {},{}", tile.x, tile.y)?; }
+                    writeln!(f)?;
+                },
+                ReplayCommand::Disconnect{ machine, port }                     => writeln!(f, "disconnect {}:{:?}", machine, port)?,
+                ReplayCommand::SetRecipe{ machine, recipe }                    => writeln!(f, "recipe {} {}", machine, Optional(*recipe))?,
+                ReplayCommand::ConfigurePortFilter{ machine, port, filter }    => writeln!(f, "filter {}:{:?} {}", machine, port, Optional(*filter))?,
+                ReplayCommand::Remove{ entity }                                => writeln!(f, "remove {}", entity)?,
+                ReplayCommand::Restore{ replaces, snapshot }                   => {
+                    f.write_str("restore")?;
+                    for id in replaces.iter() { write!(f, " {}", id)?; }
+                    writeln!(f)?;
+                    write!(f, "{}", snapshot)?;
+                    writeln!(f, "end")?;
+                },
+            }
+        }
+
+        for (tick, checksum) in self.checksums.iter() {
+            writeln!(f, "checksum {} {:016x}", tick, checksum)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Replay {
+    type Err = ReplayError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut lines = format_lines(text);
+        let line      = parse_header(&mut lines, "replay", REPLAY_VERSION)?;
+
+        let (line, start) = lines.next().ok_or(FormatError::Parse{ line, message: "Missing snapshot" })?;
+        if start != "snapshot" { return Err(FormatError::Parse{ line, message: "Missing snapshot" }.into()); }
+
+        let mut result = Replay{ snapshot: parse_snapshot(line, &mut lines)?, ..Default::default() };
+        while let Some((line, text)) = lines.next() {
+            let mut tokens = text.split_whitespace();
+            match tokens.next() {
+                Some("command")  => {
+                    let tick = parse_number(line, tokens.next())?;
+                    let mut command = parse_command(line, &mut tokens)?;
+                    if let ReplayCommand::Restore{ snapshot, .. } = &mut command { *snapshot = parse_snapshot(line, &mut lines)?; }
+                    result.commands.push((tick, command));
+                },
+                Some("checksum") => {
+                    let tick     = parse_number(line, tokens.next())?;
+                    let checksum = tokens.next()
+                        .and_then(|v| u64::from_str_radix(v, 16).ok())
+                        .ok_or(FormatError::Parse{ line, message: "Invalid checksum" })?;
+                    result.checksums.push((tick, checksum));
+                },
+                _ => return Err(FormatError::Parse{ line, message: "Unknown entry" }.into()),
+            }
+            if tokens.next().is_some() { return Err(FormatError::Parse{ line, message: "Unexpected token" }.into()); }
+        }
+
+        Ok(result)
+    }
+}
+
+fn parse_command<'a>(line: usize, tokens: &mut impl Iterator<Item = &'a str>) -> Result<ReplayCommand, ReplayError> {
+    match tokens.next() {
+        Some("place") => {
+            let kind = tokens.next().ok_or(FormatError::Parse{ line, message: "Missing field" })?;
+            let kind = MachineUUID::try_new(kind).map_err(|message| FormatError::Parse{ line, message })?;
+            Ok(ReplayCommand::PlaceMachine{ kind, recipe: parse_resource(line, tokens.next())? })
+        },
+        Some("place_at") => {
+            let kind = tokens.next().ok_or(FormatError::Parse{ line, message: "Missing field" })?;
+            let kind = MachineUUID::try_new(kind).map_err(|message| FormatError::Parse{ line, message })?;
+            let recipe   = parse_resource(line, tokens.next())?;
+            let position = tokens.next().and_then(parse_tile).ok_or(FormatError::Parse{ line, message: "Invalid tile" })?;
+            let rotation = tokens.next().ok_or(FormatError::Parse{ line, message: "Missing field" })?;
+            let rotation = rotation.parse().map_err(|message| FormatError::Parse{ line, message })?;
+            Ok(ReplayCommand::PlaceMachineAt{ kind, recipe, position, rotation })
+        },
+        Some("connect") => {
+            let (from, from_port) = parse_endpoint(line, tokens.next())?;
+            let (to, to_port)     = parse_endpoint(line, tokens.next())?;
+            Ok(ReplayCommand::Connect{ from, from_port, to, to_port, length: parse_number(line, tokens.next())? })
+        },
+        Some("pipe") => {
+            let (from, from_port) = parse_endpoint(line, tokens.next())?;
+            let (to, to_port)     = parse_endpoint(line, tokens.next())?;
+            let path = tokens.map(|v| parse_tile(v).ok_or(FormatError::Parse{ line, message: "Invalid tile" })).collect::<Result<Vec<_>, _>>()?;
+            Ok(ReplayCommand::PlacePipe{ from, from_port, to, to_port, path })
+        },
+        Some("disconnect") => {
+            let (machine, port) = parse_endpoint(line, tokens.next())?;
+            Ok(ReplayCommand::Disconnect{ machine, port })
+        },
+        Some("filter") => {
+            let (machine, port) = parse_endpoint(line, tokens.next())?;
+            Ok(ReplayCommand::ConfigurePortFilter{ machine, port, filter: parse_resource(line, tokens.next())? })
+        },
+        Some("recipe") => Ok(ReplayCommand::SetRecipe{ machine: parse_number(line, tokens.next())?, recipe: parse_resource(line, tokens.next())? }),
+        Some("remove") => Ok(ReplayCommand::Remove{ entity: parse_number(line, tokens.next())? }),
+        Some("restore") => {
+            let replaces = tokens.map(|v| parse_number(line, Some(v))).collect::<Result<Vec<u32>, _>>()?;
+            Ok(ReplayCommand::Restore{ replaces, snapshot: FactorySnapshot::default() })
+        },
+        _ => Err(FormatError::Parse{ line, message: "Unknown command" }.into()),
+    }
+}
+
+/// Reads a snapshot up to its `end` line.
+fn parse_snapshot<'a>(line: usize, lines: &mut impl Iterator<Item = (usize, &'a str)>) -> Result<FactorySnapshot, ReplayError> {
+    let mut snapshot = String::new();
+    loop {
+        match lines.next() {
+            Some((_, "end")) => break,
+            Some((_, text))  => { snapshot.push_str(text); snapshot.push('\n'); },
+            None             => return Err(FormatError::Parse{ line, message: "Unterminated snapshot" }.into()),
+        }
+    }
+    Ok(snapshot.parse()?)
+}
+
+fn parse_resource(line: usize, token: Option<&str>) -> Result<Option<ResourceUUID>, ReplayError> {
+    match token {
+        None      => Err(FormatError::Parse{ line, message: "Missing field" }.into()),
+        Some("-") => Ok(None),
+        Some(v)   => ResourceUUID::try_new(v).map(Some).map_err(|message| FormatError::Parse{ line, message }.into()),
+    }
+}
+
+fn parse_endpoint(line: usize, token: Option<&str>) -> Result<(u32, PortID), ReplayError> {
+    let (id, port) = token.and_then(|v| v.split_once(':')).ok_or(FormatError::Parse{ line, message: "Invalid endpoint" })?;
+    let port = port.parse().map_err(|message| FormatError::Parse{ line, message })?;
+    Ok((parse_number(line, Some(id))?, port))
+}
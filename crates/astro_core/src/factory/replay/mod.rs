@@ -0,0 +1,363 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use bevy::{prelude::*, utils::HashMap};
+
+use super::{
+    CommandError, FactoryChecksum, FactoryCommand, FactoryPlugins, FormatError, FactorySnapshot, FactoryStageInternal, FactorySystem,
+    FactoryTick, MachineUUID, PortID, Ports, ResourceID, ResourceUUID, Rotation, SnapshotError, parse_tile, take_connection, with_checksum_buffers
+};
+
+mod format;
+pub use format::*;
+
+/// A recorded session: the factory when recording started, the commands
+/// applied since and checksums to verify against.
+///
+/// Commands refer to entities by replay ID, the entity's index in the
+/// snapshot, machines then connections, followed by entities spawned by
+/// commands in the order they were spawned.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Replay {
+    pub snapshot:  FactorySnapshot,
+    /// Commands with the tick they were applied on, before it was advanced.
+    pub commands:  Vec<(u32, ReplayCommand)>,
+    pub checksums: Vec<(u32, u64)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayCommand {
+    PlaceMachine{ kind: MachineUUID, recipe: Option<ResourceUUID> },
+    PlaceMachineAt{ kind: MachineUUID, recipe: Option<ResourceUUID>, position: IVec2, rotation: Rotation },
+    Connect{ from: u32, from_port: PortID, to: u32, to_port: PortID, length: u32 },
+    PlacePipe{ from: u32, from_port: PortID, to: u32, to_port: PortID, path: Vec<IVec2> },
+    Disconnect{ machine: u32, port: PortID },
+    SetRecipe{ machine: u32, recipe: Option<ResourceUUID> },
+    ConfigurePortFilter{ machine: u32, port: PortID, filter: Option<ResourceUUID> },
+    Remove{ entity: u32 },
+    /// Respawns entities removed by an undo or redo, replacing any of them
+    /// that still exist. The snapshot's machine indices are replay IDs.
+    Restore{ replaces: Vec<u32>, snapshot: FactorySnapshot },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    Format(FormatError),
+    Snapshot(SnapshotError),
+    UnnamedResource(ResourceID),
+    UnknownEntity(Entity),
+    InvalidId(u32),
+    Unrecordable(&'static str),
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Format(e)              => e.fmt(f),
+            Self::Snapshot(e)            => e.fmt(f),
+            Self::UnnamedResource(v)     => write!(f, "resource {:?} has no UUID", v),
+            Self::UnknownEntity(e)       => write!(f, "entity {} was not created by a recorded command", e.id()),
+            Self::InvalidId(v)           => write!(f, "replay ID {} is out of range", v),
+            Self::Unrecordable(v)        => write!(f, "{} can't be recorded", v),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<FormatError> for ReplayError {
+    fn from(e: FormatError) -> Self {
+        Self::Format(e)
+    }
+}
+
+impl From<SnapshotError> for ReplayError {
+    fn from(e: SnapshotError) -> Self {
+        Self::Snapshot(e)
+    }
+}
+
+/// Where a replay stopped matching the recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayDivergence {
+    Checksum{ tick: u32, expected: u64, actual: u64 },
+    Command{ tick: u32, error: CommandError },
+    InvalidId{ tick: u32, id: u32 },
+    Restore{ tick: u32, error: SnapshotError },
+}
+
+impl std::fmt::Display for ReplayDivergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Checksum{ tick, expected, actual } => write!(f, "tick {}: checksum {:016x}, expected {:016x}", tick, actual, expected),
+            Self::Command{ tick, error }             => write!(f, "tick {}: recorded command failed: {}", tick, error),
+            Self::InvalidId{ tick, id }              => write!(f, "tick {}: replay ID {} is out of range", tick, id),
+            Self::Restore{ tick, error }             => write!(f, "tick {}: recorded restore failed: {}", tick, error),
+        }
+    }
+}
+
+impl Replay {
+
+    /// Last tick with a command or checksum.
+    pub fn end_tick(&self) -> u32 {
+        let commands  = self.commands.last().map_or(0, |v| v.0);
+        let checksums = self.checksums.last().map_or(0, |v| v.0);
+        self.snapshot.tick.max(commands).max(checksums)
+    }
+
+}
+
+/// Records commands as they're applied while it's a resource.
+pub struct ReplayRecorder {
+    replay:   Replay,
+    order:    Vec<Entity>,
+    ids:      HashMap<Entity, u32>,
+    interval: u32,
+    error:    Option<ReplayError>,
+}
+
+impl ReplayRecorder {
+
+    /// Captures the factory to start recording from, checksumming every
+    /// `checksum_interval` ticks, or never if 0.
+    pub fn start(world: &mut World, checksum_interval: u32) -> Result<Self, ReplayError> {
+        let (snapshot, entities) = FactorySnapshot::capture_entities(world)?;
+        let order = entities.machines.into_iter().chain(entities.connections).collect::<Vec<_>>();
+        let ids   = order.iter().enumerate().map(|(i, &e)| (e, i as u32)).collect();
+        Ok(Self{ replay: Replay{ snapshot, ..Default::default() }, order, ids, interval: checksum_interval, error: None })
+    }
+
+    /// Records a successfully applied command. Commands referring to
+    /// entities the recorder doesn't know about make the replay incomplete,
+    /// see `error`.
+    pub fn record(&mut self, tick: u32, command: &FactoryCommand, spawned: Option<Entity>) {
+        if self.error.is_some() { return; }
+
+        match self.translate(command) {
+            Ok(command) => self.replay.commands.push((tick, command)),
+            Err(e)      => { self.error = Some(e); return; },
+        }
+
+        if let Some(entity) = spawned {
+            self.ids.insert(entity, self.order.len() as u32);
+            self.order.push(entity);
+        }
+    }
+
+    /// Records entities respawned by an undo or redo, see `ReplayCommand::Restore`.
+    pub fn record_restore(&mut self, world: &World, tick: u32, replaced: &[Entity], spawned: &[Entity]) {
+        if self.error.is_some() { return; }
+
+        let replaces = match replaced.iter().map(|&e| self.ids.get(&e).copied().ok_or(ReplayError::UnknownEntity(e))).collect::<Result<Vec<_>, _>>() {
+            Ok(v)  => v,
+            Err(e) => { self.error = Some(e); return; },
+        };
+
+        let (machines, connections): (Vec<Entity>, Vec<Entity>) = spawned.iter().copied().partition(|&e| world.get::<Ports>(e).is_some());
+        for &entity in machines.iter().chain(connections.iter()) {
+            self.ids.insert(entity, self.order.len() as u32);
+            self.order.push(entity);
+        }
+
+        match FactorySnapshot::capture_indexed(world, &machines, &connections, |e| self.ids.get(&e).copied()) {
+            Ok(snapshot) => self.replay.commands.push((tick, ReplayCommand::Restore{ replaces, snapshot })),
+            Err(e)       => self.error = Some(e.into()),
+        }
+    }
+
+    /// The first command that couldn't be recorded.
+    pub fn error(&self) -> Option<&ReplayError> {
+        self.error.as_ref()
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    pub fn finish(self) -> Result<Replay, ReplayError> {
+        match self.error {
+            Some(e) => Err(e),
+            None    => Ok(self.replay),
+        }
+    }
+
+    fn translate(&self, command: &FactoryCommand) -> Result<ReplayCommand, ReplayError> {
+        let id   = |e: Entity| self.ids.get(&e).copied().ok_or(ReplayError::UnknownEntity(e));
+        let uuid = |r: Option<ResourceID>| r.map(|r| r.uuid().ok_or(ReplayError::UnnamedResource(r))).transpose();
+
+        Ok(match *command {
+            FactoryCommand::PlaceMachine{ kind, recipe } => ReplayCommand::PlaceMachine{ kind, recipe: uuid(recipe)? },
+            FactoryCommand::PlaceMachineAt{ kind, recipe, position, rotation } => ReplayCommand::PlaceMachineAt{ kind, recipe: uuid(recipe)?, position, rotation },
+            FactoryCommand::Connect{ from, from_port, to, to_port, length } => ReplayCommand::Connect{ from: id(from)?, from_port, to: id(to)?, to_port, length },
+            FactoryCommand::PlacePipe{ from, from_port, to, to_port, ref path } => ReplayCommand::PlacePipe{ from: id(from)?, from_port, to: id(to)?, to_port, path: path.clone() },
+            FactoryCommand::Disconnect{ machine, port } => ReplayCommand::Disconnect{ machine: id(machine)?, port },
+            FactoryCommand::SetRecipe{ machine, recipe } => ReplayCommand::SetRecipe{ machine: id(machine)?, recipe: uuid(recipe)? },
+            FactoryCommand::ConfigurePortFilter{ machine, port, filter } => ReplayCommand::ConfigurePortFilter{ machine: id(machine)?, port, filter: uuid(filter)? },
+            FactoryCommand::Remove{ entity } => ReplayCommand::Remove{ entity: id(entity)? },
+            FactoryCommand::Undo => return Err(ReplayError::Unrecordable("undo")),
+            FactoryCommand::Redo => return Err(ReplayError::Unrecordable("redo")),
+            FactoryCommand::Paste{ .. } => return Err(ReplayError::Unrecordable("paste")),
+        })
+    }
+
+}
+
+/// Plays a replay back into the world it was started in.
+pub struct ReplayRunner {
+    replay:     Replay,
+    entities:   Vec<Entity>,
+    command:    usize,
+    checksum:   usize,
+    divergence: Option<ReplayDivergence>,
+}
+
+impl ReplayRunner {
+
+    /// Restores the replay's snapshot into the world.
+    pub fn start(world: &mut World, replay: Replay) -> Result<Self, ReplayError> {
+        let restored = replay.snapshot.restore(world)?;
+        let entities = restored.machines.into_iter().chain(restored.connections).collect();
+        Ok(Self{ replay, entities, command: 0, checksum: 0, divergence: None })
+    }
+
+    /// Every command has been applied and every checksum checked.
+    pub fn is_finished(&self) -> bool {
+        self.command >= self.replay.commands.len() && self.checksum >= self.replay.checksums.len()
+    }
+
+    /// The first point the replay stopped matching, later ones aren't reported.
+    pub fn divergence(&self) -> Option<&ReplayDivergence> {
+        self.divergence.as_ref()
+    }
+
+    fn diverge(&mut self, divergence: ReplayDivergence) {
+        self.divergence.get_or_insert(divergence);
+    }
+
+    fn entity(&self, tick: u32, id: u32) -> Result<Entity, ReplayDivergence> {
+        self.entities.get(id as usize).copied().ok_or(ReplayDivergence::InvalidId{ tick, id })
+    }
+
+    /// Applies a recorded command, keeping track of the entities it spawns.
+    fn apply(&mut self, world: &mut World, tick: u32, command: &ReplayCommand) -> Result<(), ReplayDivergence> {
+        if let ReplayCommand::Restore{ replaces, snapshot } = command {
+            for &id in replaces.iter() {
+                let entity = self.entity(tick, id)?;
+                take_connection(world, entity);
+                world.despawn(entity);
+            }
+            let restored = snapshot.restore_after(world, &self.entities).map_err(|error| ReplayDivergence::Restore{ tick, error })?;
+            self.entities.extend(restored.machines.into_iter().chain(restored.connections));
+            return Ok(());
+        }
+
+        let command = self.translate(tick, command)?;
+        if let Some(entity) = command.apply(world).map_err(|error| ReplayDivergence::Command{ tick, error })? {
+            self.entities.push(entity);
+        }
+        Ok(())
+    }
+
+    fn translate(&self, tick: u32, command: &ReplayCommand) -> Result<FactoryCommand, ReplayDivergence> {
+        let entity = |id: u32| self.entity(tick, id);
+
+        Ok(match *command {
+            ReplayCommand::PlaceMachine{ kind, recipe } => FactoryCommand::PlaceMachine{ kind, recipe: recipe.map(ResourceID::intern) },
+            ReplayCommand::PlaceMachineAt{ kind, recipe, position, rotation } => FactoryCommand::PlaceMachineAt{ kind, recipe: recipe.map(ResourceID::intern), position, rotation },
+            ReplayCommand::Connect{ from, from_port, to, to_port, length } => FactoryCommand::Connect{ from: entity(from)?, from_port, to: entity(to)?, to_port, length },
+            ReplayCommand::PlacePipe{ from, from_port, to, to_port, ref path } => FactoryCommand::PlacePipe{ from: entity(from)?, from_port, to: entity(to)?, to_port, path: path.clone() },
+            ReplayCommand::Disconnect{ machine, port } => FactoryCommand::Disconnect{ machine: entity(machine)?, port },
+            ReplayCommand::SetRecipe{ machine, recipe } => FactoryCommand::SetRecipe{ machine: entity(machine)?, recipe: recipe.map(ResourceID::intern) },
+            ReplayCommand::ConfigurePortFilter{ machine, port, filter } => FactoryCommand::ConfigurePortFilter{ machine: entity(machine)?, port, filter: filter.map(ResourceID::intern) },
+            ReplayCommand::Remove{ entity: id } => FactoryCommand::Remove{ entity: entity(id)? },
+            ReplayCommand::Restore{ .. } => unreachable!("restores are applied by the runner"),
+        })
+    }
+
+}
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.schedule.add_system_to_stage(FactoryStageInternal::Tick, run_replay_commands.exclusive_system().at_start().before(FactorySystem::ApplyCommands));
+        app.schedule.add_system_to_stage(CoreStage::Last, record_replay_checksum.exclusive_system());
+        app.schedule.add_system_to_stage(CoreStage::Last, verify_replay_checksum.exclusive_system());
+    }
+}
+
+/// Applies the commands recorded for the current tick, before it advances
+/// and ahead of the command queue.
+pub fn run_replay_commands(world: &mut World) {
+    if !world.contains_resource::<ReplayRunner>() { return; }
+    let tick = world.get_resource::<FactoryTick>().map_or(0, |v| v.0);
+
+    world.resource_scope(|world, mut runner: Mut<ReplayRunner>| {
+        while let Some((command_tick, command)) = runner.replay.commands.get(runner.command).cloned() {
+            if command_tick > tick { break; }
+            runner.command += 1;
+
+            if let Err(divergence) = runner.apply(world, tick, &command) { runner.diverge(divergence); }
+        }
+    });
+}
+
+pub fn record_replay_checksum(world: &mut World) {
+    let tick = world.get_resource::<FactoryTick>().map_or(0, |v| v.0);
+    let due = match world.get_resource::<ReplayRecorder>() {
+        Some(recorder) => recorder.interval > 0 && tick % recorder.interval == 0 && recorder.replay.checksums.last().map_or(true, |v| v.0 != tick),
+        None           => false,
+    };
+    if !due { return; }
+
+    let checksum = with_checksum_buffers(world, |world, buffers| {
+        FactoryChecksum::compute_ordered(world, &world.get_resource::<ReplayRecorder>().unwrap().order, buffers).value
+    });
+    world.get_resource_mut::<ReplayRecorder>().unwrap().replay.checksums.push((tick, checksum));
+}
+
+pub fn verify_replay_checksum(world: &mut World) {
+    let tick = world.get_resource::<FactoryTick>().map_or(0, |v| v.0);
+    if !world.contains_resource::<ReplayRunner>() { return; }
+
+    let (next, divergence) = with_checksum_buffers(world, |world, buffers| {
+        let runner = world.get_resource::<ReplayRunner>().unwrap();
+        let mut next = runner.checksum;
+        while runner.replay.checksums.get(next).map_or(false, |v| v.0 < tick) { next += 1; }
+
+        let divergence = match runner.replay.checksums.get(next) {
+            Some(&(checksum_tick, expected)) if checksum_tick == tick => {
+                next += 1;
+                let actual = FactoryChecksum::compute_ordered(world, &runner.entities, buffers).value;
+                (actual != expected).then(|| ReplayDivergence::Checksum{ tick, expected, actual })
+            },
+            _ => None,
+        };
+        (next, divergence)
+    });
+
+    let mut runner = world.get_resource_mut::<ReplayRunner>().unwrap();
+    runner.checksum = next;
+    if let Some(divergence) = divergence { runner.diverge(divergence); }
+}
+
+/// Replays into a fresh headless factory, returning the first divergence.
+pub fn verify_replay(replay: Replay) -> Result<Option<ReplayDivergence>, ReplayError> {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins).add_plugin(ReplayPlugin);
+
+    let runner = ReplayRunner::start(&mut app.world, replay)?;
+    app.insert_resource(runner);
+
+    loop {
+        let runner = app.world.get_resource::<ReplayRunner>().unwrap();
+        if runner.is_finished() || runner.divergence().is_some() {
+            return Ok(runner.divergence().cloned());
+        }
+        app.update();
+    }
+}
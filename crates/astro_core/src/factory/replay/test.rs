@@ -0,0 +1,147 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+use crate::factory::{execute_command, FactoryHistory, MACHINE_SOURCE, MACHINE_SINK, MACHINE_PASSTHROUGH};
+
+fn example() -> Replay {
+    let speed = ResourceUUID::new("SPEED");
+    Replay{
+        snapshot:  FactorySnapshot{ tick: 120, ..Default::default() },
+        commands:  vec![
+            (120, ReplayCommand::PlaceMachine{ kind: MACHINE_SOURCE, recipe: Some(speed) }),
+            (120, ReplayCommand::PlaceMachine{ kind: MACHINE_SINK, recipe: None }),
+            (125, ReplayCommand::PlaceMachineAt{ kind: MACHINE_SINK, recipe: None, position: IVec2::new(4, 0), rotation: Rotation::North }),
+            (130, ReplayCommand::Connect{ from: 0, from_port: PortID::B, to: 1, to_port: PortID::A, length: 16 }),
+            (135, ReplayCommand::PlacePipe{ from: 0, from_port: PortID::B, to: 2, to_port: PortID::A, path: vec![IVec2::new(1, 0), IVec2::new(2, 0), IVec2::new(3, 0)] }),
+            (140, ReplayCommand::ConfigurePortFilter{ machine: 1, port: PortID::A, filter: Some(speed) }),
+            (190, ReplayCommand::Disconnect{ machine: 1, port: PortID::A }),
+            (200, ReplayCommand::SetRecipe{ machine: 0, recipe: None }),
+            (210, ReplayCommand::Remove{ entity: 2 }),
+        ],
+        checksums: vec![(180, 0x8f2c0a14d3e7b951)],
+    }
+}
+
+#[test]
+fn format() {
+    assert_eq!(example().to_string(), "\
+replay 1
+snapshot
+astro 1
+tick 120
+end
+command 120 place SOURCE SPEED
+command 120 place SINK -
+command 125 place_at SINK - 4,0 North
+command 130 connect 0:B 1:A 16
+command 135 pipe 0:B 2:A 1,0 2,0 3,0
+command 140 filter 1:A SPEED
+command 190 disconnect 1:A
+command 200 recipe 0 -
+command 210 remove 2
+checksum 180 8f2c0a14d3e7b951
+");
+    assert_eq!(example().to_string().parse::<Replay>(), Ok(example()));
+}
+
+#[test]
+fn parse_errors() {
+    assert_eq!("replay 2\n".parse::<Replay>(), Err(ReplayError::Format(FormatError::UnsupportedVersion{ format: "replay", version: 2 })));
+    assert_eq!("replay 1\nsnapshot\nastro 1\n".parse::<Replay>(), Err(ReplayError::Format(FormatError::Parse{ line: 2, message: "Unterminated snapshot" })));
+    assert_eq!("replay 1\nsnapshot\nastro 1\nend\ncommand 5 place\n".parse::<Replay>(), Err(ReplayError::Format(FormatError::Parse{ line: 5, message: "Missing field" })));
+    assert_eq!("replay 1\nsnapshot\nastro 1\nend\ncommand 5 connect 0 1:A 3\n".parse::<Replay>(), Err(ReplayError::Format(FormatError::Parse{ line: 5, message: "Invalid endpoint" })));
+    assert_eq!("replay 1\nsnapshot\nastro 1\nend\nchecksum 5 xyz\n".parse::<Replay>(), Err(ReplayError::Format(FormatError::Parse{ line: 5, message: "Invalid checksum" })));
+}
+
+/// Records a session in a world whose entity IDs differ from a fresh one.
+fn record() -> Replay {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins).add_plugin(ReplayPlugin);
+
+    let world = &mut app.world;
+    for _ in 0..5 { let e = world.spawn().id(); world.despawn(e); }
+
+    let speed  = ResourceID::intern(ResourceUUID::new("SPEED"));
+    let source = execute_command(world, FactoryCommand::PlaceMachine{ kind: MACHINE_SOURCE, recipe: Some(speed) }).unwrap().unwrap();
+    let sink   = execute_command(world, FactoryCommand::PlaceMachine{ kind: MACHINE_SINK, recipe: None }).unwrap().unwrap();
+    execute_command(world, FactoryCommand::Connect{ from: source, from_port: PortID::B, to: sink, to_port: PortID::A, length: 4 }).unwrap();
+
+    let recorder = ReplayRecorder::start(&mut app.world, 5).unwrap();
+    app.insert_resource(recorder);
+    for _ in 0..10 { app.update(); }
+
+    let world = &mut app.world;
+    let middle = execute_command(world, FactoryCommand::PlaceMachine{ kind: MACHINE_PASSTHROUGH, recipe: None }).unwrap().unwrap();
+    let pipe   = world.query_filtered::<Entity, With<crate::factory::PortSend>>().iter(world).next().unwrap();
+    execute_command(world, FactoryCommand::Remove{ entity: pipe }).unwrap();
+    execute_command(world, FactoryCommand::Connect{ from: source, from_port: PortID::B, to: middle, to_port: PortID::A, length: 2 }).unwrap();
+    execute_command(world, FactoryCommand::Connect{ from: middle, from_port: PortID::B, to: sink, to_port: PortID::A, length: 3 }).unwrap();
+    for _ in 0..20 { app.update(); }
+
+    execute_command(&mut app.world, FactoryCommand::SetRecipe{ machine: source, recipe: None }).unwrap();
+    for _ in 0..10 { app.update(); }
+
+    app.world.remove_resource::<ReplayRecorder>().unwrap().finish().unwrap()
+}
+
+#[test]
+fn record_and_verify() {
+    let replay = record();
+    assert_eq!(replay.commands.len(), 5);
+    assert_eq!(replay.checksums.len(), 8);
+
+    let replay = replay.to_string().parse::<Replay>().unwrap();
+    assert_eq!(verify_replay(replay), Ok(None));
+}
+
+#[test]
+fn detects_divergence() {
+    let mut replay = record();
+    replay.checksums[3].1 ^= 1;
+    let tick = replay.checksums[3].0;
+    assert!(matches!(verify_replay(replay), Ok(Some(ReplayDivergence::Checksum{ tick: t, .. })) if t == tick));
+
+    let mut replay = record();
+    replay.commands.remove(0);
+    assert!(matches!(verify_replay(replay), Ok(Some(_))));
+}
+
+#[test]
+fn unknown_entity() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+
+    let mut recorder = ReplayRecorder::start(&mut app.world, 0).unwrap();
+    let stranger = app.world.spawn().id();
+    recorder.record(0, &FactoryCommand::Remove{ entity: stranger }, None);
+    assert_eq!(recorder.finish(), Err(ReplayError::UnknownEntity(stranger)));
+}
+
+#[test]
+fn records_undo_redo() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins).add_plugin(ReplayPlugin).insert_resource(FactoryHistory::default());
+
+    let world  = &mut app.world;
+    let speed  = ResourceID::intern(ResourceUUID::new("SPEED"));
+    let source = execute_command(world, FactoryCommand::PlaceMachine{ kind: MACHINE_SOURCE, recipe: Some(speed) }).unwrap().unwrap();
+    let sink   = execute_command(world, FactoryCommand::PlaceMachine{ kind: MACHINE_SINK, recipe: None }).unwrap().unwrap();
+    execute_command(world, FactoryCommand::Connect{ from: source, from_port: PortID::B, to: sink, to_port: PortID::A, length: 4 }).unwrap();
+
+    let recorder = ReplayRecorder::start(&mut app.world, 2).unwrap();
+    app.insert_resource(recorder);
+    for _ in 0..5 { app.update(); }
+
+    for command in [FactoryCommand::Remove{ entity: sink }, FactoryCommand::Undo, FactoryCommand::Redo, FactoryCommand::Undo] {
+        execute_command(&mut app.world, command).unwrap();
+        for _ in 0..5 { app.update(); }
+    }
+
+    let replay = app.world.remove_resource::<ReplayRecorder>().unwrap().finish().unwrap();
+    assert_eq!(replay.commands.iter().filter(|(_, v)| matches!(v, ReplayCommand::Restore{ .. })).count(), 2);
+
+    let replay = replay.to_string().parse::<Replay>().unwrap();
+    assert_eq!(verify_replay(replay), Ok(None));
+}
@@ -0,0 +1,58 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::ResourceID;
+
+/// Resources machines created and destroyed during the current tick, indexed
+/// by the inner value of their `ResourceID`. Resources only passing through
+/// ports and connections aren't counted, so a chain reports each item once.
+#[derive(Default)]
+pub struct ResourceFlow {
+    produced: Vec<u32>,
+    consumed: Vec<u32>,
+}
+
+impl ResourceFlow {
+
+    /// Records resources a machine created.
+    #[inline] pub fn record_produced(&mut self, resource: ResourceID, count: u32) {
+        increment(&mut self.produced, resource, count);
+    }
+
+    /// Records resources a machine destroyed.
+    #[inline] pub fn record_consumed(&mut self, resource: ResourceID, count: u32) {
+        increment(&mut self.consumed, resource, count);
+    }
+
+    pub fn produced(&self, resource: ResourceID) -> u32 {
+        self.produced.get(resource.into_inner() as usize).copied().unwrap_or(0)
+    }
+
+    pub fn consumed(&self, resource: ResourceID) -> u32 {
+        self.consumed.get(resource.into_inner() as usize).copied().unwrap_or(0)
+    }
+
+    /// Iterates resources with any flow as (resource, produced, consumed).
+    pub fn iter(&self) -> impl Iterator<Item = (ResourceID, u32, u32)> + '_ {
+        let len = self.produced.len().max(self.consumed.len());
+        (1..len).filter_map(move |i| {
+            let produced = self.produced.get(i).copied().unwrap_or(0);
+            let consumed = self.consumed.get(i).copied().unwrap_or(0);
+            if produced == 0 && consumed == 0 { return None; }
+            Some((ResourceID::try_from_inner(i as u16)?, produced, consumed))
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.produced.iter_mut().for_each(|v| *v = 0);
+        self.consumed.iter_mut().for_each(|v| *v = 0);
+    }
+
+}
+
+#[inline] fn increment(counts: &mut Vec<u32>, resource: ResourceID, count: u32) {
+    let idx = resource.into_inner() as usize;
+    if idx >= counts.len() { counts.resize(idx + 1, 0); }
+    counts[idx] = counts[idx].saturating_add(count);
+}
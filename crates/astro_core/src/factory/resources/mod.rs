@@ -1,7 +1,7 @@
 
-use bevy::prelude::{Plugin, SystemStage};
+use bevy::prelude::{Plugin, SystemStage, CoreStage, ExclusiveSystemDescriptorCoercion, IntoExclusiveSystem};
 
-use super::{FactoryStage, FactoryStageInternal};
+use super::{ArrivalSchedule, FactoryStage, FactoryStageInternal};
 
 mod pipe;
 pub use pipe::*;
@@ -12,11 +12,28 @@ pub use ports::*;
 mod resource;
 pub use resource::*;
 
+mod flow;
+pub use flow::*;
+
+mod status;
+pub use status::*;
+
 pub struct FactoryResourcePlugin;
 
 impl Plugin for FactoryResourcePlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.schedule.add_stage_after(FactoryStage::Machine, FactoryStageInternal::Machine, SystemStage::single_threaded());
+        app.add_event::<ConnectionBroken>();
+        app.init_resource::<ConnectionLifecycle>();
+        app.init_resource::<SpilledResources>();
+        app.init_resource::<ConnectionIndex>();
+        app.init_resource::<ArrivalSchedule>();
+        app.init_resource::<ResourceFlow>();
+        app.init_resource::<LateRemovals>();
+        app.schedule.add_system_to_stage(CoreStage::Last, update_connection_index);
+        app.schedule.add_system_to_stage(CoreStage::Last, track_late_removals.exclusive_system().at_end());
         register_connection_stage::<PipeSimple>(app);
+        register_connection_stage::<PipeGap>(app);
+        register_pooled_connection_stage(app);
     }
 }
\ No newline at end of file
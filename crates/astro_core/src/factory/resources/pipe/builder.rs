@@ -0,0 +1,175 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use bevy::{prelude::{Entity, Component, World}, ecs::world::EntityMut};
+
+use super::{Pipe, PortSend, PortRecv, PortID, Ports, ConnectionIndex, ConnectionEnd, FlowMonitor, PipeDescriptor, PipeContents, POOLED_PIPE};
+
+/// Longest connection that may be built. Pipes allocate a slot per tile up
+/// front, so lengths from clients and files must be bounded.
+pub const MAX_PIPE_LENGTH: u32 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionError {
+    ZeroLength,
+    TooLong(u32),
+    Unconnected,
+    MissingPorts{ end: ConnectionEnd, target: Entity },
+    PortOccupied{ end: ConnectionEnd, target: Entity, port: PortID, connection: Entity },
+}
+
+impl std::fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ZeroLength                                    => write!(f, "connection must have a length of at least 1"),
+            Self::TooLong(v)                                    => write!(f, "connection length {} is over the maximum of {}", v, MAX_PIPE_LENGTH),
+            Self::Unconnected                                   => write!(f, "connection has neither a send nor a recv port"),
+            Self::MissingPorts{ end, target }                   => write!(f, "{:?} target {:?} has no ports", end, target),
+            Self::PortOccupied{ end, target, port, connection } => write!(f, "{:?} port {:?} of {:?} is already used by {:?}", end, port, target, connection),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
+/// Marks a connection built non-exclusively, which may share its ports with
+/// other shared connections.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct SharedConnection;
+
+/// Marks a connection kept after losing both of its ends, see
+/// `ConnectionLifecycle::despawn_orphans`.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct DetachedConnection;
+
+/// Validated construction of a connection between two sets of `Ports`.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionBuilder {
+    length:    u32,
+    send:      Option<(Entity, PortID)>,
+    recv:      Option<(Entity, PortID)>,
+    exclusive: bool,
+}
+
+impl ConnectionBuilder {
+
+    pub fn new(length: u32) -> Self {
+        Self{ length, send: None, recv: None, exclusive: true }
+    }
+
+    /// The port the connection delivers packets into.
+    pub fn send_to(mut self, entity: Entity, port: PortID) -> Self {
+        self.send = Some((entity, port));
+        self
+    }
+
+    /// The port the connection takes packets from.
+    pub fn recv_from(mut self, entity: Entity, port: PortID) -> Self {
+        self.recv = Some((entity, port));
+        self
+    }
+
+    /// When exclusive (the default) no other connection may use either of
+    /// the same ports. Shared connections may only share ports with each other.
+    pub fn exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    pub fn validate(&self, world: &World) -> Result<(), ConnectionError> {
+        self.validate_length()?;
+        if self.send.is_none() && self.recv.is_none() { return Err(ConnectionError::Unconnected); }
+
+        for (end, target) in [(ConnectionEnd::Send, self.send), (ConnectionEnd::Recv, self.recv)] {
+            if let Some((target, _)) = target {
+                if world.get::<Ports>(target).is_none() {
+                    return Err(ConnectionError::MissingPorts{ end, target });
+                }
+            }
+        }
+
+        if let Some(index) = world.get_resource::<ConnectionIndex>() {
+            if let Some((target, port)) = self.send {
+                let existing = index.senders(target, port).iter().copied().filter(|&c| world.get::<PortSend>(c) == Some(&PortSend(target, port)));
+                self.check_shared(world, existing, ConnectionEnd::Send, target, port)?;
+            }
+            if let Some((target, port)) = self.recv {
+                let existing = index.receivers(target, port).iter().copied().filter(|&c| world.get::<PortRecv>(c) == Some(&PortRecv(target, port)));
+                self.check_shared(world, existing, ConnectionEnd::Recv, target, port)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn build<T: Pipe + Component>(self, world: &mut World) -> Result<Entity, ConnectionError> {
+        let length = self.length;
+        self.spawn(world, |entity| { entity.insert(T::with_length(length)); })
+    }
+
+    /// Builds a connection of a registered pipe type, pre-filled with packets.
+    /// The builder's length takes precedence over the length of the contents.
+    pub fn build_with(self, world: &mut World, pipe: &PipeDescriptor, contents: &PipeContents) -> Result<Entity, ConnectionError> {
+        self.validate(world)?;
+        Ok(self.insert_with(world, pipe, contents))
+    }
+
+    /// Like `build_with` with only the length checked, so a connection left
+    /// with neither end after its machines were removed can be restored as
+    /// it was.
+    pub(crate) fn build_detached_with(self, world: &mut World, pipe: &PipeDescriptor, contents: &PipeContents) -> Result<Entity, ConnectionError> {
+        self.validate_length()?;
+        Ok(self.insert_with(world, pipe, contents))
+    }
+
+    /// Builds a connection whose packets live in the shared `PipePool`.
+    pub fn build_pooled(self, world: &mut World) -> Result<Entity, ConnectionError> {
+        self.build_with(world, &POOLED_PIPE, &PipeContents::default())
+    }
+
+    fn check_shared(&self, world: &World, mut existing: impl Iterator<Item = Entity>, end: ConnectionEnd, target: Entity, port: PortID) -> Result<(), ConnectionError> {
+        match existing.find(|&c| self.exclusive || world.get::<SharedConnection>(c).is_none()) {
+            Some(connection) => Err(ConnectionError::PortOccupied{ end, target, port, connection }),
+            None             => Ok(()),
+        }
+    }
+
+    fn spawn(self, world: &mut World, insert: impl FnOnce(&mut EntityMut)) -> Result<Entity, ConnectionError> {
+        self.validate(world)?;
+        Ok(self.spawn_unchecked(world, insert))
+    }
+
+    fn validate_length(&self) -> Result<(), ConnectionError> {
+        if self.length == 0 { return Err(ConnectionError::ZeroLength); }
+        if self.length > MAX_PIPE_LENGTH { return Err(ConnectionError::TooLong(self.length)); }
+        Ok(())
+    }
+
+    fn insert_with(self, world: &mut World, pipe: &PipeDescriptor, contents: &PipeContents) -> Entity {
+        let contents = PipeContents{ length: self.length, packets: contents.packets.clone() };
+        let entity   = self.spawn_unchecked(world, |_| {});
+        (pipe.insert)(world, entity, &contents);
+        entity
+    }
+
+    fn spawn_unchecked(self, world: &mut World, insert: impl FnOnce(&mut EntityMut)) -> Entity {
+        let mut entity = world.spawn();
+        insert(&mut entity);
+        entity.insert(FlowMonitor::default());
+        if !self.exclusive { entity.insert(SharedConnection); }
+        if self.send.is_none() && self.recv.is_none() { entity.insert(DetachedConnection); }
+
+        let send = self.send.map(|(e, p)| PortSend(e, p));
+        let recv = self.recv.map(|(e, p)| PortRecv(e, p));
+        if let Some(send) = send { entity.insert(send); }
+        if let Some(recv) = recv { entity.insert(recv); }
+
+        let entity = entity.id();
+        world.get_resource_or_insert_with(ConnectionIndex::default).sync(entity, send, recv);
+        entity
+    }
+
+}
@@ -0,0 +1,63 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+use crate::factory::PipeSimple;
+
+fn machines(world: &mut World) -> (Entity, Entity, Entity) {
+    let mut spawn = || world.spawn().insert(Ports::default()).id();
+    (spawn(), spawn(), spawn())
+}
+
+#[test]
+fn invalid() {
+    let mut world = World::new();
+    let (a, b, _) = machines(&mut world);
+    let bare      = world.spawn().id();
+
+    assert_eq!(ConnectionBuilder::new(0).send_to(a, PortID::A).validate(&world), Err(ConnectionError::ZeroLength));
+    assert_eq!(ConnectionBuilder::new(MAX_PIPE_LENGTH + 1).send_to(a, PortID::A).validate(&world), Err(ConnectionError::TooLong(MAX_PIPE_LENGTH + 1)));
+    assert_eq!(ConnectionBuilder::new(4).validate(&world),                       Err(ConnectionError::Unconnected));
+    assert_eq!(ConnectionBuilder::new(4).send_to(bare, PortID::A).recv_from(a, PortID::B).validate(&world), Err(ConnectionError::MissingPorts{ end: ConnectionEnd::Send, target: bare }));
+    assert_eq!(ConnectionBuilder::new(4).send_to(b, PortID::A).recv_from(bare, PortID::B).validate(&world), Err(ConnectionError::MissingPorts{ end: ConnectionEnd::Recv, target: bare }));
+    assert!(ConnectionBuilder::new(4).send_to(b, PortID::A).build::<PipeSimple>(&mut world).is_ok());
+    assert!(ConnectionBuilder::new(4).recv_from(a, PortID::B).build::<PipeSimple>(&mut world).is_ok());
+}
+
+#[test]
+fn exclusive_ends() {
+    let mut world = World::new();
+    let (a, b, c) = machines(&mut world);
+    let first = ConnectionBuilder::new(4).recv_from(a, PortID::B).send_to(b, PortID::A).build::<PipeSimple>(&mut world).unwrap();
+    assert!(world.get::<SharedConnection>(first).is_none());
+
+    let sends = ConnectionBuilder::new(4).recv_from(c, PortID::B).send_to(b, PortID::A);
+    assert_eq!(sends.validate(&world), Err(ConnectionError::PortOccupied{ end: ConnectionEnd::Send, target: b, port: PortID::A, connection: first }));
+    let recvs = ConnectionBuilder::new(4).recv_from(a, PortID::B).send_to(c, PortID::A);
+    assert_eq!(recvs.validate(&world), Err(ConnectionError::PortOccupied{ end: ConnectionEnd::Recv, target: a, port: PortID::B, connection: first }));
+
+    // Shared connections can't join an exclusive one either.
+    assert_eq!(sends.exclusive(false).validate(&world), Err(ConnectionError::PortOccupied{ end: ConnectionEnd::Send, target: b, port: PortID::A, connection: first }));
+    assert!(ConnectionBuilder::new(4).recv_from(a, PortID::C).send_to(b, PortID::B).validate(&world).is_ok());
+
+    // Detached ends no longer occupy the port, even before the index resyncs.
+    world.entity_mut(first).remove::<PortSend>();
+    assert!(sends.build::<PipeSimple>(&mut world).is_ok());
+}
+
+#[test]
+fn shared_ends() {
+    let mut world = World::new();
+    let (a, b, c) = machines(&mut world);
+    let first  = ConnectionBuilder::new(4).recv_from(a, PortID::B).send_to(b, PortID::A).exclusive(false).build::<PipeSimple>(&mut world).unwrap();
+    let second = ConnectionBuilder::new(4).recv_from(c, PortID::B).send_to(b, PortID::A).exclusive(false).build::<PipeSimple>(&mut world).unwrap();
+    assert!(world.get::<SharedConnection>(first).is_some());
+    assert!(ConnectionBuilder::new(4).recv_from(a, PortID::B).send_to(c, PortID::A).exclusive(false).build::<PipeSimple>(&mut world).is_ok());
+
+    assert_eq!(
+        ConnectionBuilder::new(4).recv_from(c, PortID::C).send_to(b, PortID::A).validate(&world),
+        Err(ConnectionError::PortOccupied{ end: ConnectionEnd::Send, target: b, port: PortID::A, connection: first }),
+    );
+    assert_eq!(world.get_resource::<ConnectionIndex>().unwrap().senders(b, PortID::A), &[first, second]);
+}
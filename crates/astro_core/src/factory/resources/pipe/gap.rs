@@ -0,0 +1,107 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use std::collections::VecDeque;
+
+use bevy::prelude::Component;
+
+use super::{PacketPosition, ResourceID, Pipe, resolve_positions, resolve_slots};
+
+/// Pipe storing the ticks between packets rather than a slot per tile, so
+/// memory follows the packet count instead of the length. Suited to long
+/// trunk lines that are mostly empty.
+#[derive(Component, Debug, Clone)]
+pub struct PipeGap {
+    length:  u32,
+    /// Tick the head packet was enqueued on.
+    head:    u32,
+    /// Tick the tail packet was enqueued on.
+    tail:    u32,
+    /// Each packet with the ticks since the one ahead of it, zero for the head.
+    packets: VecDeque<(u32, ResourceID)>,
+}
+
+impl PipeGap {
+
+    pub fn new(length: u32) -> Self {
+        Self{ length, head: 0, tail: 0, packets: VecDeque::new() }
+    }
+
+    /// Number of stored entries, one per packet.
+    pub fn entries(&self) -> usize {
+        self.packets.len()
+    }
+
+    /// Each packet from head to tail with the tick it was enqueued on.
+    pub fn packets(&self) -> impl Iterator<Item = (u32, ResourceID)> + '_ {
+        self.packets.iter().scan(self.head, |tick, &(gap, resource)| {
+            *tick += gap;
+            Some((*tick, resource))
+        })
+    }
+
+}
+
+impl Pipe for PipeGap {
+
+    fn with_length(length: u32) -> Self {
+        Self::new(length)
+    }
+
+    fn name() -> &'static str {
+        "gap"
+    }
+
+    unsafe fn enqueue_unchecked(&mut self, tick: u32, resource: ResourceID) {
+        let gap = if self.packets.is_empty() { self.head = tick; 0 } else { tick - self.tail };
+        self.tail = tick;
+        self.packets.push_back((gap, resource));
+    }
+
+    unsafe fn consume_unchecked(&mut self) {
+        self.packets.pop_front();
+        if let Some((gap, _)) = self.packets.front_mut() {
+            self.head += std::mem::take(gap);
+        }
+    }
+
+    unsafe fn get_unchecked(&self) -> ResourceID {
+        self.packets.front().unwrap().1
+    }
+
+    fn is_full(&self) -> bool {
+        self.packets.len() as u32 == self.length
+    }
+
+    fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    fn is_ready_to_consume(&self, tick: u32) -> bool {
+        !self.packets.is_empty() && tick - self.head >= self.length
+    }
+
+    fn length(&self) -> u32 {
+        self.length
+    }
+
+    fn next_arrival(&self) -> Option<u32> {
+        (!self.packets.is_empty()).then(|| self.head + self.length)
+    }
+
+    fn for_each_packet(&self, f: &mut dyn FnMut(u32, ResourceID)) {
+        self.packets().for_each(|(tick, resource)| f(tick, resource));
+    }
+
+    fn resolve(&self, factory_tick: u32) -> Box<[Option<ResourceID>]> {
+        resolve_slots(self.length, factory_tick, self.packets())
+    }
+
+    fn resolve_into(&self, factory_tick: u32, alpha: f32, out: &mut Vec<PacketPosition>) {
+        resolve_positions(self.length, factory_tick, alpha, self.packets(), out);
+    }
+
+}
@@ -0,0 +1,55 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+use crate::factory::{PipeContents, PipeSimple};
+
+fn resource(id: u16) -> ResourceID {
+    ResourceID::try_from_inner(id).unwrap()
+}
+
+#[test]
+fn sparse_trunk() {
+    let mut pipe = PipeGap::new(1000);
+    for tick in [5, 90, 400] { unsafe{ pipe.enqueue_unchecked(tick, resource(1)); } }
+    assert_eq!(pipe.entries(), 3);
+    assert_eq!(pipe.next_arrival(), Some(1005));
+    assert!(!pipe.is_ready_to_consume(1004));
+
+    let slots = pipe.resolve(500);
+    assert_eq!(slots.iter().enumerate().filter_map(|(i, v)| v.map(|_| i)).collect::<Vec<_>>(), vec![100, 410, 495]);
+
+    unsafe{ pipe.consume_unchecked(); }
+    assert_eq!(pipe.next_arrival(), Some(1090));
+}
+
+#[test]
+fn matches_simple() {
+    let (mut gap, mut simple) = (PipeGap::new(4), PipeSimple::new(4));
+    let (mut gap_positions, mut simple_positions) = (Vec::new(), Vec::new());
+    for tick in 0..40u32 {
+        if tick % 3 != 0 && !simple.is_full() {
+            let value = resource((tick % 5 + 1) as u16);
+            unsafe{ gap.enqueue_unchecked(tick, value); }
+            unsafe{ simple.enqueue_unchecked(tick, value); }
+        }
+        if tick % 7 < 2 && simple.is_ready_to_consume(tick) {
+            assert_eq!(unsafe{ gap.get_unchecked() }, unsafe{ simple.get_unchecked() });
+            unsafe{ gap.consume_unchecked(); }
+            unsafe{ simple.consume_unchecked(); }
+        }
+
+        assert_eq!((gap.is_full(), gap.is_empty()), (simple.is_full(), simple.is_empty()));
+        assert_eq!(gap.is_ready_to_consume(tick), simple.is_ready_to_consume(tick));
+        assert_eq!(gap.next_arrival(), simple.next_arrival());
+        assert_eq!(gap.resolve(tick), simple.resolve(tick));
+        gap.resolve_into(tick, 0.5, &mut gap_positions);
+        simple.resolve_into(tick, 0.5, &mut simple_positions);
+        assert_eq!(gap_positions, simple_positions);
+        assert_eq!(PipeContents::from_pipe(&gap), PipeContents::from_pipe(&simple));
+    }
+
+    let contents = PipeContents::from_pipe(&simple);
+    assert_eq!(PipeContents::from_pipe(&contents.to_pipe::<PipeGap>()), contents);
+}
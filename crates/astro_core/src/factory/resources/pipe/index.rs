@@ -0,0 +1,94 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use bevy::{prelude::{Entity, Query, ResMut, RemovedComponents, Changed, Or}, utils::HashMap};
+
+use super::{PortSend, PortRecv, PortID};
+
+/// Lookup from machine ports to the connections attached to them.
+///
+/// Entries may briefly outlive their connection, they're resynced at the end
+/// of each frame, so callers that need certainty should check the connection's
+/// components.
+#[derive(Default)]
+pub struct ConnectionIndex {
+    senders:     HashMap<(Entity, PortID), Vec<Entity>>,
+    receivers:   HashMap<(Entity, PortID), Vec<Entity>>,
+    connections: HashMap<Entity, (Option<PortSend>, Option<PortRecv>)>,
+}
+
+impl ConnectionIndex {
+
+    /// Connections that send into the given port.
+    pub fn senders(&self, entity: Entity, port: PortID) -> &[Entity] {
+        self.senders.get(&(entity, port)).map_or(&[], |v| v.as_slice())
+    }
+
+    /// Connections that receive from the given port.
+    pub fn receivers(&self, entity: Entity, port: PortID) -> &[Entity] {
+        self.receivers.get(&(entity, port)).map_or(&[], |v| v.as_slice())
+    }
+
+    pub fn get(&self, connection: Entity) -> Option<(Option<PortSend>, Option<PortRecv>)> {
+        self.connections.get(&connection).copied()
+    }
+
+    /// All connections attached to any port of the given entity.
+    pub fn attached(&self, entity: Entity) -> impl Iterator<Item = Entity> + '_ {
+        PortID::ALL.into_iter().flat_map(move |port| {
+            self.senders(entity, port).iter().chain(self.receivers(entity, port).iter()).copied()
+        })
+    }
+
+    pub fn sync(&mut self, connection: Entity, send: Option<PortSend>, recv: Option<PortRecv>) {
+        self.remove(connection);
+        if send.is_none() && recv.is_none() { return; }
+
+        if let Some(PortSend(e, p)) = send {
+            self.senders.entry((e, p)).or_default().push(connection);
+        }
+
+        if let Some(PortRecv(e, p)) = recv {
+            self.receivers.entry((e, p)).or_default().push(connection);
+        }
+
+        self.connections.insert(connection, (send, recv));
+    }
+
+    pub fn remove(&mut self, connection: Entity) {
+        if let Some((send, recv)) = self.connections.remove(&connection) {
+            if let Some(PortSend(e, p)) = send { remove_from(&mut self.senders,   (e, p), connection); }
+            if let Some(PortRecv(e, p)) = recv { remove_from(&mut self.receivers, (e, p), connection); }
+        }
+    }
+
+}
+
+fn remove_from(map: &mut HashMap<(Entity, PortID), Vec<Entity>>, key: (Entity, PortID), connection: Entity) {
+    if let Some(list) = map.get_mut(&key) {
+        list.retain(|&v| v != connection);
+        if list.is_empty() { map.remove(&key); }
+    }
+}
+
+pub fn update_connection_index(
+    mut index:    ResMut<ConnectionIndex>,
+    changed:      Query<(Entity, Option<&PortSend>, Option<&PortRecv>), Or<(Changed<PortSend>, Changed<PortRecv>)>>,
+    current:      Query<(Option<&PortSend>, Option<&PortRecv>)>,
+    removed_send: RemovedComponents<PortSend>,
+    removed_recv: RemovedComponents<PortRecv>,
+) {
+    for (entity, send, recv) in changed.iter() {
+        index.sync(entity, send.copied(), recv.copied());
+    }
+
+    for entity in removed_send.iter().chain(removed_recv.iter()) {
+        match current.get(entity) {
+            Ok((send, recv)) => index.sync(entity, send.copied(), recv.copied()),
+            Err(_)           => index.remove(entity),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::prelude::{App, World};
+
+use super::*;
+use crate::factory::{ConnectionBuilder, FactoryPlugins, PipeSimple, Ports};
+
+#[test]
+fn sync_and_remove() {
+    let mut world = World::new();
+    let (a, b)    = (world.spawn().id(), world.spawn().id());
+    let (c0, c1)  = (world.spawn().id(), world.spawn().id());
+
+    let mut index = ConnectionIndex::default();
+    index.sync(c0, Some(PortSend(b, PortID::A)), Some(PortRecv(a, PortID::B)));
+    index.sync(c1, Some(PortSend(b, PortID::A)), None);
+    assert_eq!(index.senders(b, PortID::A),   &[c0, c1]);
+    assert_eq!(index.receivers(a, PortID::B), &[c0]);
+    assert_eq!(index.get(c1), Some((Some(PortSend(b, PortID::A)), None)));
+    assert_eq!(index.attached(b).collect::<Vec<_>>(), vec![c0, c1]);
+
+    index.sync(c0, Some(PortSend(a, PortID::C)), None);
+    assert_eq!(index.senders(b, PortID::A), &[c1]);
+    assert!(index.receivers(a, PortID::B).is_empty());
+    assert_eq!(index.attached(a).collect::<Vec<_>>(), vec![c0]);
+
+    index.sync(c1, None, None);
+    index.remove(c0);
+    assert_eq!(index.get(c0), None);
+    assert_eq!(index.get(c1), None);
+    assert_eq!(index.attached(a).chain(index.attached(b)).count(), 0);
+}
+
+#[test]
+fn follows_components() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+    let a    = app.world.spawn().insert(Ports::default()).id();
+    let b    = app.world.spawn().insert(Ports::default()).id();
+    let pipe = ConnectionBuilder::new(4).recv_from(a, PortID::B).send_to(b, PortID::A).build::<PipeSimple>(&mut app.world).unwrap();
+    let index = |app: &App| app.world.get_resource::<ConnectionIndex>().unwrap().get(pipe);
+    app.update();
+    assert_eq!(index(&app), Some((Some(PortSend(b, PortID::A)), Some(PortRecv(a, PortID::B)))));
+
+    app.world.entity_mut(pipe).insert(PortSend(a, PortID::C));
+    app.update();
+    assert_eq!(index(&app), Some((Some(PortSend(a, PortID::C)), Some(PortRecv(a, PortID::B)))));
+    assert!(app.world.get_resource::<ConnectionIndex>().unwrap().senders(b, PortID::A).is_empty());
+
+    app.world.entity_mut(pipe).remove::<PortRecv>();
+    app.update();
+    assert_eq!(index(&app), Some((Some(PortSend(a, PortID::C)), None)));
+
+    app.world.despawn(pipe);
+    app.update();
+    assert_eq!(index(&app), None);
+}
@@ -0,0 +1,211 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use std::marker::PhantomData;
+
+use bevy::{prelude::{Entity, Component, Query, Res, ResMut, Commands, EventWriter, RemovedComponents, With, World}, utils::HashMap};
+
+use super::{ConnectionIndex, DetachedConnection, Pipe, ResourceID, PortFilter, PortSend, PortRecv, PortID, Ports, take_connection};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEnd {
+    Send,
+    Recv,
+}
+
+/// Emitted when a connection references an entity that no longer has `Ports`.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionBroken {
+    pub connection: Entity,
+    pub end:        ConnectionEnd,
+    pub target:     Entity,
+    pub port:       PortID,
+    pub spilled:    u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpillPolicy {
+    /// Packets left in a connection with no destination are destroyed.
+    Discard,
+    /// Packets left in a connection with no destination are added to `SpilledResources`.
+    Store,
+}
+
+pub struct ConnectionLifecycle {
+    pub spill:           SpillPolicy,
+    pub despawn_orphans: bool,
+}
+
+impl Default for ConnectionLifecycle {
+    fn default() -> Self {
+        Self { spill: SpillPolicy::Store, despawn_orphans: true }
+    }
+}
+
+/// Container for resources recovered from broken connections.
+#[derive(Default)]
+pub struct SpilledResources(HashMap<ResourceID, u64>);
+
+impl SpilledResources {
+
+    pub fn get(&self, resource: ResourceID) -> u64 {
+        self.0.get(&resource).copied().unwrap_or(0)
+    }
+
+    pub fn take(&mut self, resource: ResourceID) -> u64 {
+        self.0.remove(&resource).unwrap_or(0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ResourceID, u64)> + '_ {
+        self.0.iter().map(|(&k, &v)| (k, v))
+    }
+
+    pub(super) fn add(&mut self, resource: ResourceID) {
+        *self.0.entry(resource).or_insert(0) += 1;
+    }
+
+    /// Spills a packet according to the policy.
+    pub fn spill(&mut self, policy: SpillPolicy, resource: ResourceID) {
+        if policy == SpillPolicy::Store { self.add(resource); }
+    }
+
+}
+
+/// Spills packets taken out of connections according to the `ConnectionLifecycle`.
+pub fn spill_packets(world: &mut World, packets: impl IntoIterator<Item = ResourceID>) {
+    let policy      = world.get_resource::<ConnectionLifecycle>().map_or(SpillPolicy::Store, |v| v.spill);
+    let mut spilled = world.get_resource_or_insert_with(SpilledResources::default);
+    packets.into_iter().for_each(|v| spilled.spill(policy, v));
+}
+
+/// Spills whatever's left in a connection, as `connection_lifecycle` would.
+pub fn spill_connection(world: &mut World, connection: Entity) {
+    if let Some((_, contents)) = take_connection(world, connection) {
+        spill_packets(world, contents.packets.into_iter().map(|v| v.1));
+    }
+}
+
+/// Whether a connection attached to the machines has nowhere to deliver once
+/// they're gone, so `connection_lifecycle` would spill it.
+pub fn is_stranded(world: &World, connection: Entity, machines: &[Entity]) -> bool {
+    let orphans = world.get_resource::<ConnectionLifecycle>().map_or(true, |v| v.despawn_orphans);
+    world.get::<PortSend>(connection).map_or(orphans, |PortSend(e, _)| machines.contains(e))
+}
+
+/// Machines that lost their `Ports` or `PortFilter` during the previous frame,
+/// for systems that ran before the removal and so never saw it. Replaced at
+/// the very end of each frame.
+#[derive(Default)]
+pub struct LateRemovals(Vec<Entity>);
+
+impl LateRemovals {
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+/// Records the frame's removals once nothing else can run after them.
+pub fn track_late_removals(world: &mut World) {
+    let removed = world.removed::<Ports>().chain(world.removed::<PortFilter>()).collect::<Vec<_>>();
+    world.get_resource_mut::<LateRemovals>().unwrap().0 = removed;
+}
+
+/// Connections attached to machines that lost their `Ports` this frame or
+/// late in the last one. These may never try to reach the missing machine
+/// themselves, such as an empty pipe that only sends, so they're never
+/// reported as dangling.
+pub fn detached_connections(index: &ConnectionIndex, removed: &RemovedComponents<Ports>, late: &LateRemovals) -> Vec<Entity> {
+    removed.iter().chain(late.iter()).flat_map(|machine| index.attached(machine)).collect()
+}
+
+/// Connections that failed to resolve one of their ports during the last update.
+pub struct DanglingConnections<T>(Vec<Entity>, PhantomData<fn() -> T>);
+
+impl<T> Default for DanglingConnections<T> {
+    fn default() -> Self {
+        Self(Vec::new(), PhantomData)
+    }
+}
+
+impl<T> DanglingConnections<T> {
+    pub fn push(&mut self, connection: Entity) {
+        self.0.push(connection);
+    }
+}
+
+pub fn connection_lifecycle<T: Pipe + Component>(
+    mut commands:              Commands,
+    lifecycle:                 Res<ConnectionLifecycle>,
+    mut dangling:              ResMut<DanglingConnections<T>>,
+    (mut spilled, mut events): (ResMut<SpilledResources>, EventWriter<ConnectionBroken>),
+    mut connections:           Query<(&mut T, Option<&PortSend>, Option<&PortRecv>)>,
+    ports:                     Query<(), With<Ports>>,
+    (index, removed, late):    (Res<ConnectionIndex>, RemovedComponents<Ports>, Res<LateRemovals>),
+) {
+    let mut candidates = std::mem::take(&mut dangling.0);
+    candidates.extend(detached_connections(&index, &removed, &late));
+    if candidates.is_empty() { return; }
+
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    for entity in candidates {
+        let (mut connection, send, recv) = if let Ok(v) = connections.get_mut(entity) { v } else { continue; };
+        break_connection(&mut commands, &lifecycle, &mut events, entity, (send, recv), &ports, || spill(&mut *connection, lifecycle.spill, &mut spilled));
+    }
+}
+
+/// Removes the ends of a connection whose machines lost their `Ports`,
+/// reporting each, and despawns it once orphaned or marks it detached.
+/// `spill` empties the connection, returning how many packets it held, and
+/// is only called if those packets can no longer be delivered.
+pub(super) fn break_connection(
+    commands:     &mut Commands,
+    lifecycle:    &ConnectionLifecycle,
+    events:       &mut EventWriter<ConnectionBroken>,
+    entity:       Entity,
+    (send, recv): (Option<&PortSend>, Option<&PortRecv>),
+    ports:        &Query<(), With<Ports>>,
+    spill:        impl FnOnce() -> u32,
+) {
+    let send_broken = send.filter(|PortSend(target, _)| ports.get(*target).is_err());
+    let recv_broken = recv.filter(|PortRecv(target, _)| ports.get(*target).is_err());
+
+    let detached = (send.is_none() || send_broken.is_some())
+                && (recv.is_none() || recv_broken.is_some());
+    let orphaned = detached && lifecycle.despawn_orphans;
+
+    let spilled_count = if send_broken.is_some() || orphaned { spill() } else { 0 };
+
+    let mut entity_commands = commands.entity(entity);
+
+    if let Some(&PortSend(target, port)) = send_broken {
+        entity_commands.remove::<PortSend>();
+        events.send(ConnectionBroken{ connection: entity, end: ConnectionEnd::Send, target, port, spilled: spilled_count });
+    }
+
+    if let Some(&PortRecv(target, port)) = recv_broken {
+        entity_commands.remove::<PortRecv>();
+        events.send(ConnectionBroken{ connection: entity, end: ConnectionEnd::Recv, target, port, spilled: 0 });
+    }
+
+    if orphaned {
+        entity_commands.despawn();
+    } else if detached {
+        entity_commands.insert(DetachedConnection);
+    }
+}
+
+fn spill<T: Pipe>(connection: &mut T, policy: SpillPolicy, spilled: &mut SpilledResources) -> u32 {
+    let mut count = 0;
+    while !connection.is_empty() {
+        let resource = unsafe{ connection.get_unchecked() };
+        unsafe{ connection.consume_unchecked(); }
+        spilled.spill(policy, resource);
+        count += 1;
+    }
+    count
+}
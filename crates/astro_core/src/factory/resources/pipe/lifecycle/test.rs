@@ -0,0 +1,90 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::{ecs::event::Events, prelude::{App, CoreStage}};
+
+use super::*;
+use crate::factory::{ConnectionBuilder, FactoryPlugins, PipeSimple, ResourceUUID, MACHINE_SOURCE, MACHINE_SINK, spawn_machine};
+
+fn speed() -> ResourceID {
+    ResourceID::intern(ResourceUUID::new("SPEED"))
+}
+
+fn build_app() -> (App, Entity, Entity, Entity) {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+
+    let source = spawn_machine(&mut app.world, MACHINE_SOURCE, Some(speed())).unwrap();
+    let sink   = spawn_machine(&mut app.world, MACHINE_SINK, None).unwrap();
+    let pipe   = ConnectionBuilder::new(4).recv_from(source, PortID::B).send_to(sink, PortID::A).build::<PipeSimple>(&mut app.world).unwrap();
+    (app, source, sink, pipe)
+}
+
+fn broken(app: &App) -> Vec<(Entity, ConnectionEnd, Entity, PortID, u32)> {
+    let events = app.world.get_resource::<Events<ConnectionBroken>>().unwrap();
+    events.get_reader().iter(events).map(|v| (v.connection, v.end, v.target, v.port, v.spilled)).collect()
+}
+
+fn spilled(app: &App) -> u64 {
+    app.world.get_resource::<SpilledResources>().unwrap().get(speed())
+}
+
+#[test]
+fn broken_send_spills() {
+    let (mut app, source, sink, pipe) = build_app();
+    for _ in 0..3 { app.update(); }
+    let held = app.world.get::<PipeSimple>(pipe).unwrap().len();
+    assert!(held > 0);
+
+    app.world.despawn(sink);
+    app.update();
+    assert_eq!(broken(&app), vec![(pipe, ConnectionEnd::Send, sink, PortID::A, held)]);
+    assert_eq!(spilled(&app), u64::from(held));
+    assert_eq!(app.world.get::<PortSend>(pipe), None);
+    assert_eq!(app.world.get::<PortRecv>(pipe), Some(&PortRecv(source, PortID::B)));
+
+    app.world.insert_resource(ConnectionLifecycle{ spill: SpillPolicy::Discard, despawn_orphans: true });
+    app.world.despawn(source);
+    app.update();
+    assert!(app.world.get_entity(pipe).is_none());
+    assert_eq!(spilled(&app), u64::from(held));
+}
+
+#[test]
+fn orphans_kept() {
+    let (mut app, source, sink, pipe) = build_app();
+    app.world.insert_resource(ConnectionLifecycle{ spill: SpillPolicy::Store, despawn_orphans: false });
+    for _ in 0..3 { app.update(); }
+
+    app.world.despawn(source);
+    app.world.despawn(sink);
+    app.update();
+    let ends = broken(&app).into_iter().map(|(_, end, target, ..)| (end, target)).collect::<Vec<_>>();
+    assert_eq!(ends, vec![(ConnectionEnd::Send, sink), (ConnectionEnd::Recv, source)]);
+    assert!(app.world.get_entity(pipe).is_some());
+    assert!(spilled(&app) > 0);
+}
+
+#[derive(Component)]
+struct DespawnLate;
+
+fn despawn_late(mut commands: Commands, query: Query<Entity, With<DespawnLate>>) {
+    query.iter().for_each(|e| commands.entity(e).despawn());
+}
+
+#[test]
+fn empty_send_only() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins).add_system_to_stage(CoreStage::PostUpdate, despawn_late);
+    let sink = spawn_machine(&mut app.world, MACHINE_SINK, None).unwrap();
+    let pipe = ConnectionBuilder::new(2).send_to(sink, PortID::A).build::<PipeSimple>(&mut app.world).unwrap();
+    for _ in 0..3 { app.update(); }
+
+    app.world.entity_mut(sink).insert(DespawnLate);
+    app.update();
+    assert!(app.world.get_entity(sink).is_none());
+    app.update();
+    assert!(app.world.get_entity(pipe).is_none());
+    assert_eq!(broken(&app), vec![(pipe, ConnectionEnd::Send, sink, PortID::A, 0)]);
+}
@@ -2,16 +2,43 @@
 ** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
 \*=====================================================================*/
 
-use bevy::{ecs::system::EntityCommands, prelude::{Entity, Component, Without, Query, Res, Mut}};
+use bevy::prelude::{Entity, Component, Commands, CoreStage, Without, Query, Res, ResMut, Mut, ParallelSystemDescriptorCoercion};
 
-use crate::factory::{FactoryStageInternal, FactoryTick};
+use crate::factory::{ArrivalSchedule, Dormant, FactoryStageInternal, FactorySystem, FactoryTick, sleep};
 
-use super::{ResourceID, PortSend, PortRecv, PortID, Ports};
+use super::{ResourceID, FlowMonitor, FlowStatus, PortSend, PortRecv, PortID, Ports, PortFilter};
 
 mod simple;
 pub use simple::*;
 
+mod gap;
+pub use gap::*;
+
+mod lifecycle;
+pub use lifecycle::*;
+
+mod index;
+pub use index::*;
+
+mod builder;
+pub use builder::*;
+
+mod registry;
+pub use registry::*;
+
+mod pool;
+pub use pool::*;
+
+mod position;
+pub use position::*;
+
 pub trait Pipe {
+    /// Creates an empty pipe with the given length in slots.
+    fn with_length(length: u32) -> Self where Self: Sized;
+
+    /// Stable name identifying the pipe type in saved data.
+    fn name() -> &'static str where Self: Sized;
+
     /// Enqueues the given resource with the given tick.
     /// 
     /// # Safety 
@@ -34,89 +61,180 @@ pub trait Pipe {
     fn is_empty(&self) -> bool;
     fn is_ready_to_consume(&self, tick_factory: u32) -> bool;
 
-    fn resolve(&self, factory_tick: u32) -> Box<[Option<ResourceID>]>;
-}
+    fn length(&self) -> u32;
 
-pub fn spawn_pipe(entity: &mut EntityCommands, length: u32, send: Option<(Entity, PortID)>, recv: Option<(Entity, PortID)>) {
-    
-    entity.insert(PipeSimple::new(length));
-
-    if let Some((e, p)) = send {
-        entity.insert(PortSend(e, p));
+    /// Tick the head packet is ready to consume on, if there is one.
+    fn next_arrival(&self) -> Option<u32> {
+        let mut head = None;
+        self.for_each_packet(&mut |tick, _| { head.get_or_insert(tick); });
+        head.map(|tick| tick + self.length())
     }
 
-    if let Some((e, p)) = recv {
-        entity.insert(PortRecv(e, p));
-    }
+    /// Visits each packet from head to tail along with the tick it was enqueued on.
+    fn for_each_packet(&self, f: &mut dyn FnMut(u32, ResourceID));
+
+    fn resolve(&self, factory_tick: u32) -> Box<[Option<ResourceID>]>;
+
+    /// Fills `out` with each packet from head to tail and its position
+    /// `alpha` of the way to the next tick, for drawing between ticks.
+    /// Clears `out` first, so a buffer kept across frames doesn't allocate.
+    fn resolve_into(&self, factory_tick: u32, alpha: f32, out: &mut Vec<PacketPosition>);
 }
 
 pub fn register_connection_stage<T: Pipe + Component +>(app: &mut bevy::prelude::App) {
-    app.schedule.add_system_to_stage(FactoryStageInternal::Machine, connection_send_recv::<T>);
-    app.schedule.add_system_to_stage(FactoryStageInternal::Machine, connection_recv::<T>);
-    app.schedule.add_system_to_stage(FactoryStageInternal::Machine, connection_send::<T>);
+    app.init_resource::<DanglingConnections<T>>();
+    app.world.get_resource_or_insert_with(PipeRegistry::default).register::<T>();
+    app.schedule.add_system_to_stage(FactoryStageInternal::Tick,    connection_lifecycle::<T>);
+    app.schedule.add_system_to_stage(FactoryStageInternal::Machine, connection_send_recv::<T>.label(FactorySystem::ConnectionSendRecv));
+    app.schedule.add_system_to_stage(FactoryStageInternal::Machine, connection_recv::<T>.label(FactorySystem::ConnectionRecv).after(FactorySystem::ConnectionSendRecv));
+    app.schedule.add_system_to_stage(FactoryStageInternal::Machine, connection_send::<T>.label(FactorySystem::ConnectionSend).after(FactorySystem::ConnectionRecv));
+}
+
+pub fn register_pooled_connection_stage(app: &mut bevy::prelude::App) {
+    app.init_resource::<PipePool>();
+    app.world.get_resource_or_insert_with(PipeRegistry::default).register_descriptor(POOLED_PIPE);
+    app.schedule.add_system_to_stage(FactoryStageInternal::Tick,    pooled_lifecycle);
+    app.schedule.add_system_to_stage(FactoryStageInternal::Tick,    release_pooled_pipes.after(FactorySystem::UpdateTick));
+    app.schedule.add_system_to_stage(FactoryStageInternal::Machine, pooled_connections.label(FactorySystem::ConnectionPooled).after(FactorySystem::ConnectionSend));
+    app.schedule.add_system_to_stage(CoreStage::Last,               release_pooled_pipes);
 }
 
 pub fn connection_send_recv<T: Pipe + Component>(
+    mut commands: Commands,
     tick: Res<FactoryTick>,
-    mut connections: Query<(&mut T, &PortRecv, &PortSend)>,
-    mut ports: Query<&mut Ports>
+    mut schedule: ResMut<ArrivalSchedule>,
+    mut dangling: ResMut<DanglingConnections<T>>,
+    mut connections: Query<(Entity, &mut T, &PortRecv, &PortSend, Option<&mut FlowMonitor>), Without<Dormant>>,
+    (mut ports, filters): (Query<&mut Ports>, Query<&PortFilter>),
 ) {
     let tick = tick.0;
-    for (mut connection, ports_recv, ports_send) in connections.iter_mut() {
-        do_connection_send(tick, &mut connection, ports_send, &mut ports);
-        do_connection_recv(tick, &mut connection, ports_recv, &mut ports);
+    for (entity, mut connection, ports_recv, ports_send, monitor) in connections.iter_mut() {
+        let send = do_connection_send(tick, &mut connection, ports_send, &mut ports, &filters);
+        let recv = do_connection_recv(tick, &mut connection, ports_recv, &mut ports);
+        let status = update_status(entity, &*connection, send, recv, monitor, &mut dangling);
+        if is_settled(send, recv) { settle(&mut commands, &mut schedule, tick, entity, &*connection, status); }
     }
 }
 
 pub fn connection_recv<T: Pipe + Component>(
+    mut commands: Commands,
     tick: Res<FactoryTick>,
-    mut connections: Query<(&mut T, &PortRecv), Without<PortSend>>,
+    mut schedule: ResMut<ArrivalSchedule>,
+    mut dangling: ResMut<DanglingConnections<T>>,
+    mut connections: Query<(Entity, &mut T, &PortRecv, Option<&mut FlowMonitor>), (Without<PortSend>, Without<Dormant>)>,
     mut ports: Query<&mut Ports>
 ) {
     let tick = tick.0;
-    for (mut connection, ports_recv) in connections.iter_mut() {
-        do_connection_recv(tick, &mut connection, ports_recv, &mut ports)
+    for (entity, mut connection, ports_recv, monitor) in connections.iter_mut() {
+        let recv = do_connection_recv(tick, &mut connection, ports_recv, &mut ports);
+        // Without a send end nothing ever leaves.
+        let status = update_status(entity, &*connection, Transfer::Starved, recv, monitor, &mut dangling);
+        if is_settled(Transfer::Starved, recv) { settle(&mut commands, &mut schedule, tick, entity, &*connection, status); }
     }
 }
 
 pub fn connection_send<T: Pipe + Component>(
+    mut commands: Commands,
     tick: Res<FactoryTick>,
-    mut connections: Query<(&mut T, &PortSend), Without<PortRecv>>,
-    mut ports: Query<&mut Ports>
+    mut schedule: ResMut<ArrivalSchedule>,
+    mut dangling: ResMut<DanglingConnections<T>>,
+    mut connections: Query<(Entity, &mut T, &PortSend, Option<&mut FlowMonitor>), (Without<PortRecv>, Without<Dormant>)>,
+    (mut ports, filters): (Query<&mut Ports>, Query<&PortFilter>),
 ) {
     let tick = tick.0;
-    for (mut connection, ports_send) in connections.iter_mut() {
-        do_connection_send(tick, &mut connection, ports_send, &mut ports)
+    for (entity, mut connection, ports_send, monitor) in connections.iter_mut() {
+        let send = do_connection_send(tick, &mut connection, ports_send, &mut ports, &filters);
+        let status = update_status(entity, &*connection, send, Transfer::Starved, monitor, &mut dangling);
+        if is_settled(send, Transfer::Starved) { settle(&mut commands, &mut schedule, tick, entity, &*connection, status); }
     }
 }
 
+/// Outcome of one end of a connection for a single tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transfer {
+    Moved,
+    Idle,
+    Starved,
+    Blocked,
+    Mismatch,
+    Dangling,
+}
+
+fn update_status<T: Pipe>(
+    entity: Entity,
+    connection: &T,
+    send: Transfer,
+    recv: Transfer,
+    monitor: Option<Mut<FlowMonitor>>,
+    dangling: &mut DanglingConnections<T>,
+) -> FlowStatus {
+    let status = flow_status(send, recv, connection.is_empty());
+    if status == FlowStatus::DanglingTarget { dangling.push(entity); }
+    if let Some(mut monitor) = monitor { monitor.record(status); }
+    status
+}
+
+fn flow_status(send: Transfer, recv: Transfer, empty: bool) -> FlowStatus {
+    match (send, recv) {
+        (Transfer::Dangling, _) | (_, Transfer::Dangling) => FlowStatus::DanglingTarget,
+        (Transfer::Mismatch, _)                           => FlowStatus::ResourceMismatch,
+        (Transfer::Blocked,  _) | (_, Transfer::Blocked ) => FlowStatus::OutputBlocked,
+        (_, Transfer::Starved) if empty                   => FlowStatus::InputStarved,
+        _                                                 => FlowStatus::Running,
+    }
+}
+
+/// Neither end can move anything until one of the ports it touches changes
+/// or the head packet arrives.
+fn is_settled(send: Transfer, recv: Transfer) -> bool {
+    let send = !matches!(send, Transfer::Moved | Transfer::Dangling);
+    let recv = !matches!(recv, Transfer::Moved | Transfer::Mismatch | Transfer::Dangling);
+    send && recv
+}
+
+/// Sleeps a settled connection, waking it when its head packet arrives.
+fn settle<T: Pipe>(commands: &mut Commands, schedule: &mut ArrivalSchedule, tick: u32, entity: Entity, connection: &T, status: FlowStatus) {
+    sleep(commands, entity, tick, status);
+    if let Some(arrival) = connection.next_arrival().filter(|&v| v > tick) { schedule.schedule(arrival, entity); }
+}
+
+/// The port is only resolved when the connection has room, so dangling
+/// targets are detected lazily.
 fn do_connection_recv<T: Pipe>(
     tick: u32,
     connection: &mut Mut<T>,
     ports_recv: &PortRecv,
-    ports: &mut Query<&mut Ports>
-) {
-    if connection.is_full() { return; }
-    if let Ok(mut ports) = ports.get_mut(ports_recv.0) {
-        if let Some((resource, count)) = ports.get(ports_recv.1).get() {
-            ports.get_mut(ports_recv.1).set(resource, count-1);
-            unsafe{ connection.enqueue_unchecked(tick, resource); }
-        }
+    ports: &mut Query<&mut Ports>,
+) -> Transfer {
+    if connection.is_full() { return Transfer::Blocked; }
+    let mut ports = if let Ok(ports) = ports.get_mut(ports_recv.0) { ports } else { return Transfer::Dangling; };
+    if let Some((resource, count)) = ports.get(ports_recv.1).get() {
+        ports.get_mut(ports_recv.1).set(resource, count-1);
+        unsafe{ connection.enqueue_unchecked(tick, resource); }
+        Transfer::Moved
+    } else {
+        Transfer::Starved
     }
 }
 
+/// The port is only resolved when the head packet has arrived, so dangling
+/// targets are detected lazily. Packets a `PortFilter` rejects count as a
+/// mismatch.
 fn do_connection_send<T: Pipe>(
     tick: u32,
     connection: &mut Mut<T>,
     ports_send: &PortSend,
-    ports: &mut Query<&mut Ports>
-) {
-    if !connection.is_ready_to_consume(tick) {  return; }
-    if let Ok(mut ports) = ports.get_mut(ports_send.0) {
-        let resource_head = unsafe{ connection.get_unchecked() };
-        let (resource, count) = ports.get(ports_send.1).get_or(resource_head);
-        if resource != resource_head { return; }
-        ports.get_mut(ports_send.1).set(resource, count+1);
-        unsafe{ connection.consume_unchecked() };
-    }
+    ports: &mut Query<&mut Ports>,
+    filters: &Query<&PortFilter>,
+) -> Transfer {
+    if !connection.is_ready_to_consume(tick) {  return Transfer::Idle; }
+    let mut ports = if let Ok(ports) = ports.get_mut(ports_send.0) { ports } else { return Transfer::Dangling; };
+    let resource_head = unsafe{ connection.get_unchecked() };
+    let (resource, count) = ports.get(ports_send.1).get_or(resource_head);
+    if resource != resource_head { return Transfer::Mismatch; }
+    if filters.get(ports_send.0).map_or(false, |v| !v.accepts(ports_send.1, resource)) { return Transfer::Mismatch; }
+    if count == u16::MAX { return Transfer::Blocked; }
+    ports.get_mut(ports_send.1).set(resource, count+1);
+    unsafe{ connection.consume_unchecked() };
+    Transfer::Moved
 }
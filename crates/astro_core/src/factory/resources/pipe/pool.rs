@@ -0,0 +1,301 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use bevy::{prelude::{Entity, Component, Commands, EventWriter, Query, RemovedComponents, Res, ResMut, With, World}, utils::HashMap};
+
+use crate::factory::FactoryTick;
+
+use super::{ConnectionBroken, ConnectionIndex, ConnectionLifecycle, FlowMonitor, LateRemovals, PacketPosition, PipeContents, PipeDescriptor, PortFilter, PortRecv, PortSend, Ports, ResourceID, SpillPolicy, SpilledResources, Transfer, break_connection, detached_connections, flow_status, resolve_positions, resolve_slots};
+
+/// Index of a connection's ring in the `PipePool`.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PipeHandle(pub u32);
+
+/// Connection buffers kept as parallel arrays instead of a component per
+/// connection, so packets share one arena. Each pipe is a ring of
+/// `length` slots in a shared packet arena, and freed rings are reused by
+/// pipes of the same length.
+///
+/// Pooled connections are polled every tick rather than put to sleep. Their
+/// ends and monitors are still looked up on the connection entity, so a tick
+/// doesn't walk memory in order.
+#[derive(Default)]
+pub struct PipePool {
+    entity:    Vec<Option<Entity>>,
+    offset:    Vec<u32>,
+    capacity:  Vec<u32>,
+    head:      Vec<u32>,
+    len:       Vec<u32>,
+    ticks:     Vec<u32>,
+    resources: Vec<Option<ResourceID>>,
+    changed:   Vec<bool>,
+    free:      HashMap<u32, Vec<u32>>,
+    handles:   HashMap<Entity, PipeHandle>,
+    dangling:  Vec<PipeHandle>,
+}
+
+impl PipePool {
+
+    /// Reserves an empty ring of `length` slots.
+    pub fn allocate(&mut self, length: u32) -> PipeHandle {
+        if let Some(index) = self.free.get_mut(&length).and_then(|v| v.pop()) {
+            self.changed[index as usize] = true;
+            return PipeHandle(index);
+        }
+
+        let index = self.entity.len() as u32;
+        self.entity.push(None);
+        self.offset.push(self.ticks.len() as u32);
+        self.capacity.push(length);
+        self.head.push(0);
+        self.len.push(0);
+        self.changed.push(true);
+        self.ticks.resize(self.ticks.len() + length as usize, 0);
+        self.resources.resize(self.resources.len() + length as usize, None);
+        PipeHandle(index)
+    }
+
+    /// Attaches an allocated ring to its connection.
+    pub fn bind(&mut self, handle: PipeHandle, entity: Entity) {
+        self.entity[handle.0 as usize] = Some(entity);
+        self.handles.insert(entity, handle);
+    }
+
+    /// Replaces the packets in a ring, any that don't fit are dropped.
+    pub fn fill(&mut self, handle: PipeHandle, contents: &PipeContents) {
+        let i = handle.0 as usize;
+        self.head[i]    = 0;
+        self.len[i]     = 0;
+        self.changed[i] = true;
+        for &(tick, resource) in contents.packets.iter().take(self.capacity[i] as usize) {
+            self.push(i, tick, resource);
+        }
+    }
+
+    /// Frees the ring of a connection, spilling anything left in it. Returns
+    /// how many packets spilled, `None` if the connection wasn't pooled.
+    pub fn release(&mut self, entity: Entity, policy: SpillPolicy, spilled: &mut SpilledResources) -> Option<u32> {
+        let PipeHandle(index) = self.handles.remove(&entity)?;
+        let i = index as usize;
+        let count = self.spill(i, policy, spilled);
+        self.entity[i] = None;
+        self.head[i]   = 0;
+        self.free.entry(self.capacity[i]).or_default().push(index);
+        Some(count)
+    }
+
+    pub fn handle(&self, entity: Entity) -> Option<PipeHandle> {
+        self.handles.get(&entity).copied()
+    }
+
+    /// Number of bound pipes.
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    pub fn length(&self, handle: PipeHandle) -> u32 {
+        self.capacity[handle.0 as usize]
+    }
+
+    /// Visits each packet from head to tail along with the tick it was enqueued on.
+    pub fn packets(&self, handle: PipeHandle) -> impl Iterator<Item = (u32, ResourceID)> + '_ {
+        let i = handle.0 as usize;
+        (0..self.len[i]).filter_map(move |n| self.get(i, n))
+    }
+
+    /// Whether a ring's packets changed since the last call, clearing the flag.
+    pub fn take_changed(&mut self, handle: PipeHandle) -> bool {
+        std::mem::take(&mut self.changed[handle.0 as usize])
+    }
+
+    /// Tick the head packet is ready to consume on, if there is one.
+    pub fn next_arrival(&self, handle: PipeHandle) -> Option<u32> {
+        let i = handle.0 as usize;
+        self.get(i, 0).map(|(tick, _)| tick + self.capacity[i])
+    }
+
+    pub fn contents(&self, handle: PipeHandle) -> PipeContents {
+        PipeContents{ length: self.length(handle), packets: self.packets(handle).collect() }
+    }
+
+    /// Matches `Pipe::resolve`.
+    pub fn resolve(&self, handle: PipeHandle, factory_tick: u32) -> Box<[Option<ResourceID>]> {
+        resolve_slots(self.length(handle), factory_tick, self.packets(handle))
+    }
+
+    /// Matches `Pipe::resolve_into`.
+    pub fn resolve_into(&self, handle: PipeHandle, factory_tick: u32, alpha: f32, out: &mut Vec<PacketPosition>) {
+        resolve_positions(self.length(handle), factory_tick, alpha, self.packets(handle), out);
+    }
+
+    fn slot(&self, i: usize, n: u32) -> usize {
+        (self.offset[i] + (self.head[i] + n) % self.capacity[i]) as usize
+    }
+
+    fn get(&self, i: usize, n: u32) -> Option<(u32, ResourceID)> {
+        if n >= self.len[i] { return None; }
+        let slot = self.slot(i, n);
+        self.resources[slot].map(|resource| (self.ticks[slot], resource))
+    }
+
+    fn push(&mut self, i: usize, tick: u32, resource: ResourceID) {
+        let slot = self.slot(i, self.len[i]);
+        self.ticks[slot]     = tick;
+        self.resources[slot] = Some(resource);
+        self.len[i]     += 1;
+        self.changed[i]  = true;
+    }
+
+    fn pop(&mut self, i: usize) -> Option<ResourceID> {
+        let (_, resource) = self.get(i, 0)?;
+        self.head[i]     = (self.head[i] + 1) % self.capacity[i];
+        self.len[i]     -= 1;
+        self.changed[i]  = true;
+        Some(resource)
+    }
+
+    fn spill(&mut self, i: usize, policy: SpillPolicy, spilled: &mut SpilledResources) -> u32 {
+        let mut count = 0;
+        while let Some(resource) = self.pop(i) {
+            spilled.spill(policy, resource);
+            count += 1;
+        }
+        count
+    }
+
+    /// Mirrors `do_connection_recv`.
+    fn recv_packet(&mut self, i: usize, tick: u32, PortRecv(target, port): PortRecv, ports: &mut Query<&mut Ports>) -> Transfer {
+        if self.len[i] == self.capacity[i] { return Transfer::Blocked; }
+        let mut ports = if let Ok(ports) = ports.get_mut(target) { ports } else { return Transfer::Dangling; };
+        if let Some((resource, count)) = ports.get(port).get() {
+            ports.get_mut(port).set(resource, count-1);
+            self.push(i, tick, resource);
+            Transfer::Moved
+        } else {
+            Transfer::Starved
+        }
+    }
+
+    /// Mirrors `do_connection_send`.
+    fn send_packet(&mut self, i: usize, tick: u32, PortSend(target, port): PortSend, ports: &mut Query<&mut Ports>, filters: &Query<&PortFilter>) -> Transfer {
+        let resource_head = match self.get(i, 0) {
+            Some((enqueued, resource)) if tick - enqueued >= self.capacity[i] => resource,
+            _                                                                  => return Transfer::Idle,
+        };
+        let mut ports = if let Ok(ports) = ports.get_mut(target) { ports } else { return Transfer::Dangling; };
+        let (resource, count) = ports.get(port).get_or(resource_head);
+        if resource != resource_head { return Transfer::Mismatch; }
+        if filters.get(target).map_or(false, |v| !v.accepts(port, resource)) { return Transfer::Mismatch; }
+        if count == u16::MAX { return Transfer::Blocked; }
+        ports.get_mut(port).set(resource, count+1);
+        self.pop(i);
+        Transfer::Moved
+    }
+
+}
+
+/// Type-erased access to pooled connections, registered by
+/// `register_pooled_connection_stage` so snapshots and history capture them.
+pub const POOLED_PIPE: PipeDescriptor = PipeDescriptor{
+    name:    "pooled",
+    capture: |world, entity| {
+        let pool = world.get_resource::<PipePool>()?;
+        pool.handle(entity).map(|v| pool.contents(v))
+    },
+    visit:   |world, entity, f| {
+        let pool   = world.get_resource::<PipePool>()?;
+        let handle = pool.handle(entity)?;
+        pool.packets(handle).for_each(|(tick, resource)| f(tick, resource));
+        Some(pool.length(handle))
+    },
+    insert:  insert_pooled,
+};
+
+/// Fills the connection's ring, allocating one if it doesn't have one yet.
+fn insert_pooled(world: &mut World, entity: Entity, contents: &PipeContents) {
+    if let Some(&handle) = world.get::<PipeHandle>(entity) {
+        world.get_resource_or_insert_with(PipePool::default).fill(handle, contents);
+        return;
+    }
+    let mut pool = world.get_resource_or_insert_with(PipePool::default);
+    let handle = pool.allocate(contents.length);
+    pool.bind(handle, entity);
+    pool.fill(handle, contents);
+    world.entity_mut(entity).insert(handle);
+}
+
+/// Moves packets through every pooled connection in handle order.
+pub fn pooled_connections(
+    tick:         Res<FactoryTick>,
+    mut pool:     ResMut<PipePool>,
+    mut ports:    Query<&mut Ports>,
+    filters:      Query<&PortFilter>,
+    ends:         Query<(Option<&PortSend>, Option<&PortRecv>)>,
+    mut monitors: Query<&mut FlowMonitor>,
+) {
+    let tick = tick.0;
+    let pool = &mut *pool;
+    for i in 0..pool.entity.len() {
+        let entity = if let Some(v) = pool.entity[i] { v } else { continue; };
+        let (send, recv) = ends.get(entity).unwrap_or((None, None));
+        let send = match send.copied() {
+            Some(end) => pool.send_packet(i, tick, end, &mut ports, &filters),
+            None      => Transfer::Starved,
+        };
+        let recv = match recv.copied() {
+            Some(end) => pool.recv_packet(i, tick, end, &mut ports),
+            None      => Transfer::Starved,
+        };
+
+        let status = flow_status(send, recv, pool.len[i] == 0);
+        if send == Transfer::Dangling || recv == Transfer::Dangling { pool.dangling.push(PipeHandle(i as u32)); }
+        if let Ok(mut monitor) = monitors.get_mut(entity) { monitor.record(status); }
+    }
+}
+
+/// Runs the `connection_lifecycle` checks on pooled connections.
+pub fn pooled_lifecycle(
+    mut commands:              Commands,
+    lifecycle:                 Res<ConnectionLifecycle>,
+    mut pool:                  ResMut<PipePool>,
+    (mut spilled, mut events): (ResMut<SpilledResources>, EventWriter<ConnectionBroken>),
+    ends:                      Query<(Option<&PortSend>, Option<&PortRecv>)>,
+    ports:                     Query<(), With<Ports>>,
+    (index, removed, late):    (Res<ConnectionIndex>, RemovedComponents<Ports>, Res<LateRemovals>),
+) {
+    let mut candidates = std::mem::take(&mut pool.dangling);
+    let detached       = detached_connections(&index, &removed, &late);
+    candidates.extend(detached.into_iter().filter_map(|v| pool.handle(v)));
+    if candidates.is_empty() { return; }
+
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    for PipeHandle(index) in candidates {
+        let i = index as usize;
+        let entity = if let Some(v) = pool.entity[i] { v } else { continue; };
+        let connection = if let Ok(v) = ends.get(entity) { v } else { continue; };
+        break_connection(&mut commands, &lifecycle, &mut events, entity, connection, &ports, || pool.spill(i, lifecycle.spill, &mut spilled));
+    }
+}
+
+/// Frees the rings of despawned connections, spilling what they held. Runs
+/// after commands and again at the end of the frame, so removals anywhere in
+/// a frame are seen.
+pub fn release_pooled_pipes(
+    lifecycle:   Res<ConnectionLifecycle>,
+    mut pool:    ResMut<PipePool>,
+    mut spilled: ResMut<SpilledResources>,
+    removed:     RemovedComponents<PipeHandle>,
+) {
+    for entity in removed.iter() {
+        pool.release(entity, lifecycle.spill, &mut spilled);
+    }
+}
@@ -0,0 +1,141 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::prelude::App;
+
+use super::*;
+use crate::factory::{ConnectionBuilder, FactoryCommand, FactoryPlugins, FactorySnapshot, FlowStatus, Pipe, PipeSimple, PortID, ResourceUUID, MACHINE_SOURCE, MACHINE_SINK, capture_connection, execute_command, visit_connection};
+
+fn speed() -> ResourceID {
+    ResourceID::intern(ResourceUUID::new("SPEED"))
+}
+
+fn build_app(pooled: bool) -> (App, Entity, Entity, Entity) {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+
+    let source  = execute_command(&mut app.world, FactoryCommand::PlaceMachine{ kind: MACHINE_SOURCE, recipe: Some(speed()) }).unwrap().unwrap();
+    let sink    = execute_command(&mut app.world, FactoryCommand::PlaceMachine{ kind: MACHINE_SINK,   recipe: None }).unwrap().unwrap();
+    let builder = ConnectionBuilder::new(3).recv_from(source, PortID::B).send_to(sink, PortID::A);
+    let pipe    = if pooled { builder.build_pooled(&mut app.world) } else { builder.build::<PipeSimple>(&mut app.world) };
+    (app, source, sink, pipe.unwrap())
+}
+
+#[test]
+fn rings() {
+    let (a, b) = (Entity::from_raw(1), Entity::from_raw(2));
+    let mut pool = PipePool::default();
+    let (ring_a, ring_b) = (pool.allocate(4), pool.allocate(2));
+    pool.bind(ring_a, a);
+    pool.bind(ring_b, b);
+    assert_eq!((pool.len(), pool.handle(b)), (2, Some(ring_b)));
+
+    let i = ring_b.0 as usize;
+    pool.push(i, 1, speed());
+    pool.push(i, 2, speed());
+    assert_eq!(pool.pop(i), Some(speed()));
+    pool.push(i, 3, speed());
+    assert_eq!(pool.packets(ring_b).collect::<Vec<_>>(), vec![(2, speed()), (3, speed())]);
+    assert_eq!(pool.next_arrival(ring_b), Some(4));
+    assert_eq!(pool.ticks.len(), 6);
+
+    let mut spilled = SpilledResources::default();
+    assert_eq!(pool.release(a, SpillPolicy::Store, &mut spilled), Some(0));
+    assert_eq!(pool.release(a, SpillPolicy::Store, &mut spilled), None);
+    assert_eq!(pool.release(b, SpillPolicy::Store, &mut spilled), Some(2));
+    assert_eq!(spilled.get(speed()), 2);
+    assert_eq!(pool.allocate(2), ring_b);
+    assert_eq!(pool.allocate(4), ring_a);
+    assert_eq!(pool.packets(ring_a).count(), 0);
+
+    pool.fill(ring_b, &PipeContents{ length: 2, packets: vec![(5, speed()), (6, speed()), (7, speed())] });
+    assert_eq!(pool.packets(ring_b).collect::<Vec<_>>(), vec![(5, speed()), (6, speed())]);
+}
+
+#[test]
+fn matches_simple() {
+    let (mut simple, _, simple_sink, simple_pipe) = build_app(false);
+    let (mut pooled, _, pooled_sink, pooled_pipe) = build_app(true);
+    let handle = pooled.world.get::<PipeHandle>(pooled_pipe).copied().unwrap();
+
+    for _ in 0..12 {
+        simple.update();
+        pooled.update();
+
+        let tick = simple.world.get_resource::<FactoryTick>().unwrap().0;
+        let pool = pooled.world.get_resource::<PipePool>().unwrap();
+        assert_eq!(simple.world.get::<PipeSimple>(simple_pipe).unwrap().resolve(tick), pool.resolve(handle, tick));
+
+        let sink = |app: &App, sink: Entity| {
+            let monitor = app.world.get::<FlowMonitor>(sink).unwrap();
+            (app.world.get::<Ports>(sink).unwrap().get(PortID::A).count(), monitor.status(), monitor.ticks_in(FlowStatus::Running))
+        };
+        assert_eq!(sink(&simple, simple_sink), sink(&pooled, pooled_sink));
+    }
+}
+
+#[test]
+fn dangling_ends() {
+    let (mut app, source, sink, pipe) = build_app(true);
+    for _ in 0..4 { app.update(); }
+
+    app.world.despawn(sink);
+    app.update();
+    app.update();
+    assert!(app.world.get::<PortSend>(pipe).is_none());
+    assert_eq!(app.world.get_resource::<SpilledResources>().unwrap().get(speed()), 3);
+
+    app.world.despawn(source);
+    app.update();
+    app.update();
+    assert!(app.world.get_entity(pipe).is_none());
+    assert_eq!(app.world.get_resource::<SpilledResources>().unwrap().get(speed()), 4);
+    assert!(app.world.get_resource::<PipePool>().unwrap().is_empty());
+}
+
+#[test]
+fn removal_spills() {
+    let (mut app, _, _, pipe) = build_app(true);
+    for _ in 0..4 { app.update(); }
+
+    app.world.despawn(pipe);
+    app.update();
+    assert_eq!(app.world.get_resource::<SpilledResources>().unwrap().get(speed()), 3);
+    assert!(app.world.get_resource::<PipePool>().unwrap().is_empty());
+}
+
+#[test]
+fn snapshot_round_trip() {
+    let (mut app, _, _, pipe) = build_app(true);
+    for _ in 0..4 { app.update(); }
+    let handle   = app.world.get::<PipeHandle>(pipe).copied().unwrap();
+    let contents = app.world.get_resource::<PipePool>().unwrap().contents(handle);
+    assert_eq!(contents.packets.len(), 3);
+
+    let snapshot = FactorySnapshot::capture(&mut app.world).unwrap();
+    assert_eq!(snapshot.connections.len(), 1);
+    assert_eq!(snapshot.connections[0].pipe, POOLED_PIPE.name);
+
+    let mut restored = App::new();
+    restored.add_plugins(FactoryPlugins);
+    let entities = snapshot.to_string().parse::<FactorySnapshot>().unwrap().restore(&mut restored.world).unwrap();
+    let handle   = restored.world.get::<PipeHandle>(entities.connections[0]).copied().unwrap();
+    assert_eq!(restored.world.get_resource::<PipePool>().unwrap().contents(handle), contents);
+    assert_eq!(FactorySnapshot::capture(&mut restored.world).unwrap(), snapshot);
+}
+
+#[test]
+fn visit_matches_capture() {
+    for pooled in [false, true] {
+        let (mut app, _, _, pipe) = build_app(pooled);
+        for _ in 0..4 { app.update(); }
+
+        let mut packets = Vec::new();
+        let length = visit_connection(&app.world, pipe, &mut |tick, resource| packets.push((tick, resource)));
+        let (_, contents) = capture_connection(&app.world, pipe).unwrap();
+        assert!(!packets.is_empty());
+        assert_eq!(length, Some(contents.length));
+        assert_eq!(packets, contents.packets);
+    }
+}
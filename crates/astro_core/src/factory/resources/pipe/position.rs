@@ -0,0 +1,49 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use super::ResourceID;
+
+/// A packet and where it's drawn along its pipe, see `Pipe::resolve_into`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PacketPosition {
+    pub resource: ResourceID,
+    /// Slots travelled from the recv end, from 0 up to `length - 1`.
+    pub position: f32,
+}
+
+/// Slot the `n`th packet from the head, enqueued on `tick`, occupies on
+/// `factory_tick`. Packets queue up behind the head, so each sits at the
+/// slot it's travelled to or the one behind the packet ahead, whichever is
+/// less.
+pub fn packet_slot(factory_tick: u32, length: u32, n: u32, tick: u32) -> u32 {
+    factory_tick.saturating_sub(tick).min(length.saturating_sub(n + 1))
+}
+
+/// As `packet_slot` when `alpha` of the way from `factory_tick` to the next,
+/// packets stopped behind the one ahead don't move.
+pub fn packet_position(factory_tick: u32, alpha: f32, length: u32, n: u32, tick: u32) -> f32 {
+    let travelled = factory_tick.saturating_sub(tick) as f32 + alpha.clamp(0.0, 1.0);
+    travelled.min(length.saturating_sub(n + 1) as f32)
+}
+
+/// Slot each packet occupies, given head to tail with the tick it was
+/// enqueued on. Backs every `resolve`.
+pub fn resolve_slots(length: u32, factory_tick: u32, packets: impl IntoIterator<Item = (u32, ResourceID)>) -> Box<[Option<ResourceID>]> {
+    let mut result = vec![None; length as usize].into_boxed_slice();
+    for (n, (tick, resource)) in packets.into_iter().enumerate() {
+        if let Some(slot) = result.get_mut(packet_slot(factory_tick, length, n as u32, tick) as usize) { *slot = Some(resource); }
+    }
+    result
+}
+
+/// Fills `out` with each packet and its position, clearing it first. Backs
+/// every `resolve_into`.
+pub fn resolve_positions(length: u32, factory_tick: u32, alpha: f32, packets: impl IntoIterator<Item = (u32, ResourceID)>, out: &mut Vec<PacketPosition>) {
+    out.clear();
+    out.extend(packets.into_iter().enumerate().map(|(n, (tick, resource))| {
+        PacketPosition{ resource, position: packet_position(factory_tick, alpha, length, n as u32, tick) }
+    }));
+}
@@ -0,0 +1,51 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+use crate::factory::{Pipe, PipeContents, PipeSimple};
+
+fn resource(id: u16) -> ResourceID {
+    ResourceID::try_from_inner(id).unwrap()
+}
+
+fn positions(out: &[PacketPosition]) -> Vec<f32> {
+    out.iter().map(|v| v.position).collect()
+}
+
+#[test]
+fn interpolates() {
+    let mut pipe = PipeSimple::new(4);
+    unsafe{ pipe.enqueue_unchecked(1, resource(1)); }
+    unsafe{ pipe.enqueue_unchecked(2, resource(2)); }
+
+    let mut out = Vec::new();
+    pipe.resolve_into(2, 0.5, &mut out);
+    let capacity = out.capacity();
+    assert_eq!(out, vec![PacketPosition{ resource: resource(1), position: 1.5 }, PacketPosition{ resource: resource(2), position: 0.5 }]);
+
+    pipe.resolve_into(4, 0.25, &mut out);
+    assert_eq!(positions(&out), vec![3.0, 2.0]);
+    assert_eq!(out.capacity(), capacity);
+
+    unsafe{ pipe.consume_unchecked(); }
+    pipe.resolve_into(5, 0.5, &mut out);
+    assert_eq!(positions(&out), vec![3.0]);
+}
+
+#[test]
+fn matches_resolve() {
+    let mut pipe = PipeSimple::new(5);
+    for tick in [0, 1, 3, 4, 6] { unsafe{ pipe.enqueue_unchecked(tick, resource(1)); } }
+
+    let mut out = Vec::new();
+    for tick in 6..12 {
+        pipe.resolve_into(tick, 0.0, &mut out);
+        let slots = pipe.resolve(tick);
+        assert_eq!(out.iter().map(|v| v.position as usize).collect::<Vec<_>>(), slots.iter().enumerate().rev().filter_map(|(i, v)| v.map(|_| i)).collect::<Vec<_>>());
+
+        let mut contents = Vec::new();
+        PipeContents::from_pipe(&pipe).resolve_into(tick, 0.0, &mut contents);
+        assert_eq!(contents, out);
+    }
+}
@@ -0,0 +1,115 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::prelude::{Entity, Component, World};
+
+use super::{PacketPosition, Pipe, ResourceID, resolve_positions, resolve_slots};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PipeContents {
+    pub length:  u32,
+    pub packets: Vec<(u32, ResourceID)>,
+}
+
+impl PipeContents {
+
+    pub fn from_pipe(pipe: &dyn Pipe) -> Self {
+        let mut packets = Vec::new();
+        pipe.for_each_packet(&mut |tick, resource| packets.push((tick, resource)));
+        Self{ length: pipe.length(), packets }
+    }
+
+    /// Creates a pipe holding the given packets, any that don't fit are dropped.
+    pub fn to_pipe<T: Pipe>(&self) -> T {
+        let mut pipe = T::with_length(self.length);
+        for &(tick, resource) in self.packets.iter() {
+            if pipe.is_full() { break; }
+            unsafe{ pipe.enqueue_unchecked(tick, resource); }
+        }
+        pipe
+    }
+
+    /// Matches `Pipe::resolve`.
+    pub fn resolve(&self, factory_tick: u32) -> Box<[Option<ResourceID>]> {
+        resolve_slots(self.length, factory_tick, self.packets.iter().copied())
+    }
+
+    /// Matches `Pipe::resolve_into`.
+    pub fn resolve_into(&self, factory_tick: u32, alpha: f32, out: &mut Vec<PacketPosition>) {
+        resolve_positions(self.length, factory_tick, alpha, self.packets.iter().copied(), out);
+    }
+
+}
+
+/// Type-erased access to a registered pipe type.
+#[derive(Clone, Copy)]
+pub struct PipeDescriptor {
+    pub name:    &'static str,
+    pub capture: fn(&World, Entity) -> Option<PipeContents>,
+    /// Visits each packet without copying them out, returning the length.
+    pub visit:   fn(&World, Entity, &mut dyn FnMut(u32, ResourceID)) -> Option<u32>,
+    /// Gives the entity a pipe holding the contents, replacing its packets
+    /// if it already has one.
+    pub insert:  fn(&mut World, Entity, &PipeContents),
+}
+
+#[derive(Default)]
+pub struct PipeRegistry(Vec<PipeDescriptor>);
+
+impl PipeRegistry {
+
+    pub fn register<T: Pipe + Component>(&mut self) {
+        self.register_descriptor(PipeDescriptor{
+            name:    T::name(),
+            capture: |world, entity| world.get::<T>(entity).map(|v| PipeContents::from_pipe(v)),
+            visit:   |world, entity, f| world.get::<T>(entity).map(|v| { v.for_each_packet(f); v.length() }),
+            insert:  |world, entity, contents| { world.entity_mut(entity).insert(contents.to_pipe::<T>()); },
+        });
+    }
+
+    /// Registers a pipe type that isn't a single component, such as `POOLED_PIPE`.
+    pub fn register_descriptor(&mut self, descriptor: PipeDescriptor) {
+        if self.get(descriptor.name).is_some() { return; }
+        self.0.push(descriptor);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PipeDescriptor> {
+        self.0.iter().find(|v| v.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PipeDescriptor> {
+        self.0.iter()
+    }
+
+    /// Finds the registered pipe on the entity and captures its contents.
+    pub fn capture(&self, world: &World, entity: Entity) -> Option<(&'static str, PipeContents)> {
+        self.0.iter().find_map(|v| (v.capture)(world, entity).map(|c| (v.name, c)))
+    }
+
+    /// Finds the registered pipe on the entity and visits its packets from
+    /// head to tail, returning its length.
+    pub fn visit(&self, world: &World, entity: Entity, f: &mut dyn FnMut(u32, ResourceID)) -> Option<u32> {
+        self.0.iter().find_map(|v| (v.visit)(world, entity, &mut *f))
+    }
+
+}
+
+/// Captures a connection's contents and the name of its pipe type, whether
+/// it's a registered pipe component or a ring in the `PipePool`.
+pub fn capture_connection(world: &World, entity: Entity) -> Option<(&'static str, PipeContents)> {
+    world.get_resource::<PipeRegistry>()?.capture(world, entity)
+}
+
+/// Like `capture_connection` without allocating, for code that runs every tick.
+pub fn visit_connection(world: &World, entity: Entity, f: &mut dyn FnMut(u32, ResourceID)) -> Option<u32> {
+    world.get_resource::<PipeRegistry>()?.visit(world, entity, f)
+}
+
+/// Empties a connection, returning what it held. Used before despawning a
+/// connection whose packets are kept elsewhere, so they aren't spilled.
+pub fn take_connection(world: &mut World, entity: Entity) -> Option<(&'static str, PipeContents)> {
+    let (descriptor, contents) = world.get_resource::<PipeRegistry>()?.iter().find_map(|v| Some((*v, (v.capture)(world, entity)?)))?;
+    (descriptor.insert)(world, entity, &PipeContents{ length: contents.length, packets: Vec::new() });
+    Some((descriptor.name, contents))
+}
@@ -4,7 +4,7 @@
 
 use bevy::prelude::Component;
 
-use super::{ResourceID, Pipe};
+use super::{PacketPosition, ResourceID, Pipe, packet_slot, resolve_positions, resolve_slots};
 
 #[derive(Component)]
 pub struct PipeSimple(PacketBuffer);
@@ -17,14 +17,25 @@ impl PipeSimple {
 
     pub fn get_packet_position(&self, factory_tick: u32, i: u32) -> usize {
         if i >= self.0.len() { panic!("Attempt to index out of bounds") }
-        let capacity = self.0.capacity() as u32;
-        let distance_from_start = factory_tick - self.0.get(i).unwrap().0;
-        distance_from_start.min((capacity - i - 1) as u32).min(capacity-1) as usize
+        packet_slot(factory_tick, self.0.capacity(), i, self.0.get(i).unwrap().0) as usize
+    }
+
+    /// Each packet from head to tail with the tick it was enqueued on.
+    pub fn packets(&self) -> impl Iterator<Item = (u32, ResourceID)> + '_ {
+        (0..self.0.len()).filter_map(|i| self.0.get(i))
     }
 }
 
 impl Pipe for PipeSimple {
 
+    fn with_length(length: u32) -> Self {
+        Self::new(length)
+    }
+
+    fn name() -> &'static str {
+        "simple"
+    }
+
     unsafe fn enqueue_unchecked(&mut self, tick: u32, resource: ResourceID) {
         self.0.push(tick, resource);
     }
@@ -49,13 +60,24 @@ impl Pipe for PipeSimple {
         !self.0.is_empty() && tick - self.0.peek_front().unwrap().0 >= self.0.capacity() as u32
     }
 
+    fn length(&self) -> u32 {
+        self.0.capacity()
+    }
+
+    fn next_arrival(&self) -> Option<u32> {
+        self.0.peek_front().map(|(tick, _)| tick + self.0.capacity())
+    }
+
+    fn for_each_packet(&self, f: &mut dyn FnMut(u32, ResourceID)) {
+        self.packets().for_each(|(tick, resource)| f(tick, resource));
+    }
+
     fn resolve(&self, factory_tick: u32) -> Box<[Option<ResourceID>]> {
-        let mut result = vec![None; self.0.capacity() as usize].into_boxed_slice();
-        for i in 0..self.0.len() {
-            let idx = self.get_packet_position(factory_tick, i);
-            result[idx] = self.0.get(i).map(|v| v.1);
-        }
-        result
+        resolve_slots(self.0.capacity(), factory_tick, self.packets())
+    }
+
+    fn resolve_into(&self, factory_tick: u32, alpha: f32, out: &mut Vec<PacketPosition>) {
+        resolve_positions(self.0.capacity(), factory_tick, alpha, self.packets(), out);
     }
 
 }
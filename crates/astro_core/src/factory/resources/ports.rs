@@ -9,7 +9,7 @@ use bevy::prelude::{Component, Entity};
 use super::ResourceID;
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PortID {
     A = 0,
     B = 1,
@@ -17,12 +17,56 @@ pub enum PortID {
     D = 3
 }
 
-#[derive(Component, Clone, Copy, PartialEq, Eq)]
+impl PortID {
+    pub const ALL: [PortID; 4] = [PortID::A, PortID::B, PortID::C, PortID::D];
+}
+
+impl std::str::FromStr for PortID {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "A" | "a" => Ok(PortID::A),
+            "B" | "b" => Ok(PortID::B),
+            "C" | "c" => Ok(PortID::C),
+            "D" | "d" => Ok(PortID::D),
+            _ => Err("Invalid port"),
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PortRecv(pub Entity, pub PortID);
 
-#[derive(Component, Clone, Copy, PartialEq, Eq)]
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PortSend(pub Entity, pub PortID);
 
+/// Resources connections may deliver into each port of a machine, ports
+/// without a filter accept anything.
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PortFilter([Option<ResourceID>; 4]);
+
+impl PortFilter {
+
+    pub fn get(&self, port: PortID) -> Option<ResourceID> {
+        self.0[port as usize]
+    }
+
+    pub fn set(&mut self, port: PortID, resource: Option<ResourceID>) {
+        self.0[port as usize] = resource;
+    }
+
+    pub fn accepts(&self, port: PortID, resource: ResourceID) -> bool {
+        self.get(port).map_or(true, |v| v == resource)
+    }
+
+    /// Whether every port accepts anything.
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(Option::is_none)
+    }
+
+}
+
 #[derive(Component, Default)]
 pub struct Ports([ResourceStore; 4]);
 
@@ -2,10 +2,10 @@
 ** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
 \*=====================================================================*/
 
-use std::{sync::atomic::{AtomicU16, Ordering}, num::NonZeroU16};
+use std::{sync::RwLock, num::NonZeroU16};
 
 use compact_str::{CompactStr128, newtype_compactstr};
-use once_cell::sync::OnceCell;
+use once_cell::sync::{OnceCell, Lazy};
 
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -28,6 +28,29 @@ impl ResourceID {
     pub unsafe fn from_inner_unchecked(value: ResourceIDInnerType) -> Self {
         Self(NonZeroU16::new_unchecked(value))
     }
+
+    /// Looks up the ID of an initialized resource type by its UUID.
+    pub fn from_uuid(uuid: ResourceUUID) -> Option<Self> {
+        let names = RESOURCE_UUIDS.read().unwrap();
+        let idx   = names.iter().position(|&v| v == uuid)?;
+        Self::try_from_inner((idx + 1) as ResourceIDInnerType)
+    }
+
+    /// Returns the ID for the given UUID, allocating one if it hasn't been
+    /// seen before.
+    pub fn intern(uuid: ResourceUUID) -> Self {
+        if let Some(id) = Self::from_uuid(uuid) { return id; }
+        let mut names = RESOURCE_UUIDS.write().unwrap();
+        let idx = names.iter().position(|&v| v == uuid).unwrap_or_else(|| {
+            names.push(uuid);
+            names.len() - 1
+        });
+        Self::try_from_inner((idx + 1).try_into().expect("Resource UUIDs exhausted")).unwrap()
+    }
+
+    pub fn uuid(&self) -> Option<ResourceUUID> {
+        RESOURCE_UUIDS.read().unwrap().get(self.into_inner() as usize - 1).copied()
+    }
 }
 
 newtype_compactstr!(pub, ResourceUUID, CompactStr128);
@@ -46,9 +69,7 @@ impl ResourceType {
     }
 
     pub fn id(&self) -> ResourceID {
-        *self.id.get_or_init(|| ResourceID(unsafe{
-            NonZeroU16::new_unchecked(RESOURCE_UUID.fetch_add(1, Ordering::AcqRel).checked_add(1).expect("Resource UUIDs exhausted"))
-        }))
+        *self.id.get_or_init(|| ResourceID::intern(self.uuid))
     }
 
     pub fn uuid(&self) -> ResourceUUID {
@@ -56,4 +77,5 @@ impl ResourceType {
     }
 }
 
-static RESOURCE_UUID: AtomicU16 = AtomicU16::new(0);
+/// UUIDs of initialized resource types, indexed by ID-1.
+static RESOURCE_UUIDS: Lazy<RwLock<Vec<ResourceUUID>>> = Lazy::new(|| RwLock::new(Vec::new()));
@@ -0,0 +1,120 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use std::borrow::Borrow;
+
+use bevy::prelude::{Component, Entity};
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FlowStatus {
+    Running          = 0,
+    OutputBlocked    = 1,
+    InputStarved     = 2,
+    ResourceMismatch = 3,
+    DanglingTarget   = 4,
+}
+
+impl FlowStatus {
+    pub const COUNT: usize = 5;
+
+    pub fn is_stalled(self) -> bool {
+        self != FlowStatus::Running
+    }
+}
+
+impl Default for FlowStatus {
+    fn default() -> Self {
+        Self::Running
+    }
+}
+
+/// Tracks why a connection or machine did or didn't make progress. Connections
+/// are updated by the connection systems, machines must record their own status.
+#[derive(Component, Debug, Default, Clone)]
+pub struct FlowMonitor {
+    status:      FlowStatus,
+    consecutive: u32,
+    ticks:       [u32; FlowStatus::COUNT],
+}
+
+impl FlowMonitor {
+
+    #[inline] pub fn record(&mut self, status: FlowStatus) {
+        if status == self.status {
+            self.consecutive = self.consecutive.saturating_add(1);
+        } else {
+            self.status      = status;
+            self.consecutive = 1;
+        }
+        let ticks = &mut self.ticks[status as usize];
+        *ticks = ticks.saturating_add(1);
+    }
+
+    /// Records the same status for several ticks at once.
+    pub fn record_for(&mut self, status: FlowStatus, ticks: u32) {
+        if ticks == 0 { return; }
+        if status == self.status {
+            self.consecutive = self.consecutive.saturating_add(ticks);
+        } else {
+            self.status      = status;
+            self.consecutive = ticks;
+        }
+        let total = &mut self.ticks[status as usize];
+        *total = total.saturating_add(ticks);
+    }
+
+    pub fn status(&self) -> FlowStatus {
+        self.status
+    }
+
+    /// Number of ticks the current status has been held for.
+    pub fn consecutive_ticks(&self) -> u32 {
+        self.consecutive
+    }
+
+    pub fn ticks_in(&self, status: FlowStatus) -> u32 {
+        self.ticks[status as usize]
+    }
+
+    pub fn stalled_ticks(&self) -> u32 {
+        self.ticks[1..].iter().fold(0, |acc, &v| acc.saturating_add(v))
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bottleneck {
+    pub entity:            Entity,
+    pub status:            FlowStatus,
+    pub stalled_ticks:     u32,
+    pub consecutive_ticks: u32,
+}
+
+/// Returns up to `count` monitors, ordered from most to least stalled ticks.
+/// Monitors of dormant entities should be passed through `Dormant::caught_up`.
+pub fn worst_bottlenecks<M: Borrow<FlowMonitor>>(monitors: impl IntoIterator<Item = (Entity, M)>, count: usize) -> Vec<Bottleneck> {
+    let mut result: Vec<Bottleneck> = monitors.into_iter()
+        .filter(|(_, m)| m.borrow().stalled_ticks() > 0)
+        .map(|(entity, m)| {
+            let m = m.borrow();
+            Bottleneck{ entity, status: m.status(), stalled_ticks: m.stalled_ticks(), consecutive_ticks: m.consecutive_ticks() }
+        })
+        .collect();
+
+    let order = |a: &Bottleneck, b: &Bottleneck| b.stalled_ticks.cmp(&a.stalled_ticks).then(a.entity.cmp(&b.entity));
+    if count < result.len() {
+        if count == 0 { return Vec::new(); }
+        result.select_nth_unstable_by(count - 1, order);
+        result.truncate(count);
+    }
+    result.sort_unstable_by(order);
+    result
+}
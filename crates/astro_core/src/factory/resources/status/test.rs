@@ -0,0 +1,76 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+
+fn monitor(records: &[(FlowStatus, u32)]) -> FlowMonitor {
+    let mut result = FlowMonitor::default();
+    for &(status, ticks) in records { result.record_for(status, ticks); }
+    result
+}
+
+#[test]
+fn transitions() {
+    let mut monitor = FlowMonitor::default();
+    assert_eq!((monitor.status(), monitor.consecutive_ticks()), (FlowStatus::Running, 0));
+
+    monitor.record(FlowStatus::Running);
+    monitor.record(FlowStatus::Running);
+    assert_eq!((monitor.status(), monitor.consecutive_ticks()), (FlowStatus::Running, 2));
+
+    monitor.record(FlowStatus::OutputBlocked);
+    assert_eq!((monitor.status(), monitor.consecutive_ticks()), (FlowStatus::OutputBlocked, 1));
+    monitor.record(FlowStatus::InputStarved);
+    monitor.record(FlowStatus::InputStarved);
+    monitor.record(FlowStatus::InputStarved);
+    assert_eq!((monitor.status(), monitor.consecutive_ticks()), (FlowStatus::InputStarved, 3));
+
+    monitor.record(FlowStatus::Running);
+    assert_eq!((monitor.status(), monitor.consecutive_ticks()), (FlowStatus::Running, 1));
+    assert_eq!(monitor.ticks_in(FlowStatus::Running),       3);
+    assert_eq!(monitor.ticks_in(FlowStatus::OutputBlocked), 1);
+    assert_eq!(monitor.ticks_in(FlowStatus::InputStarved),  3);
+    assert_eq!(monitor.stalled_ticks(), 4);
+
+    monitor.reset();
+    assert_eq!((monitor.status(), monitor.consecutive_ticks(), monitor.stalled_ticks()), (FlowStatus::Running, 0, 0));
+}
+
+#[test]
+fn consecutive_ticks() {
+    let mut monitor = monitor(&[(FlowStatus::DanglingTarget, 5), (FlowStatus::DanglingTarget, 0)]);
+    assert_eq!(monitor.consecutive_ticks(), 5);
+
+    // Recording for several ticks matches recording each tick.
+    monitor.record(FlowStatus::DanglingTarget);
+    monitor.record_for(FlowStatus::DanglingTarget, 4);
+    assert_eq!(monitor.consecutive_ticks(), 10);
+    monitor.record_for(FlowStatus::ResourceMismatch, 2);
+    assert_eq!((monitor.status(), monitor.consecutive_ticks()), (FlowStatus::ResourceMismatch, 2));
+    assert_eq!(monitor.stalled_ticks(), 12);
+
+    monitor.record_for(FlowStatus::ResourceMismatch, u32::MAX);
+    assert_eq!(monitor.consecutive_ticks(), u32::MAX);
+    assert_eq!(monitor.stalled_ticks(),     u32::MAX);
+}
+
+#[test]
+fn bottleneck_order() {
+    let monitors = vec![
+        (Entity::from_raw(4), monitor(&[(FlowStatus::OutputBlocked, 3)])),
+        (Entity::from_raw(1), monitor(&[(FlowStatus::Running, 50)])),
+        (Entity::from_raw(3), monitor(&[(FlowStatus::InputStarved, 7), (FlowStatus::Running, 2)])),
+        (Entity::from_raw(2), monitor(&[(FlowStatus::OutputBlocked, 3)])),
+        (Entity::from_raw(5), monitor(&[(FlowStatus::DanglingTarget, 9)])),
+    ];
+    let worst = |count| worst_bottlenecks(monitors.iter().map(|(e, m)| (*e, m)), count).into_iter().map(|v| v.entity.id()).collect::<Vec<_>>();
+
+    // Most stalled first, ties broken by entity, running monitors left out.
+    assert_eq!(worst(10), vec![5, 3, 2, 4]);
+    assert_eq!(worst(3),  vec![5, 3, 2]);
+    assert_eq!(worst(0),  Vec::<u32>::new());
+
+    let first = worst_bottlenecks(monitors.iter().map(|(e, m)| (*e, m)), 2);
+    assert_eq!(first[1], Bottleneck{ entity: Entity::from_raw(3), status: FlowStatus::Running, stalled_ticks: 7, consecutive_ticks: 2 });
+}
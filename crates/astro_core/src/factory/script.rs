@@ -0,0 +1,253 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use bevy::{prelude::*, utils::HashMap};
+use rhai::{AST, Engine, INT, Scope, packages::{CorePackage, Package}};
+
+use super::{FactoryStage, FactorySystem, FactoryTick, FlowMonitor, FlowStatus, Machine, MachineUUID, PortID, Ports, ResourceFlow, ResourceID, register_machine, resource_name};
+
+/// Operations a machine script may run each tick before it's stopped.
+pub const SCRIPT_OPERATIONS_PER_TICK: u64 = 10_000;
+
+/// Resources a script may produce from its recipe each tick, the rate a
+/// source machine produces at.
+pub const SCRIPT_PRODUCED_PER_TICK: u16 = 1;
+
+/// Marks a machine run by the script registered for its kind.
+#[derive(Component, Default)]
+pub struct ScriptedMachine;
+
+/// Emitted when a machine script fails or runs out of operations, its
+/// changes to the machine's ports that tick are discarded.
+#[derive(Debug, Clone)]
+pub struct ScriptFailed {
+    pub machine: Entity,
+    pub error:   String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptError {
+    Compile{ kind: MachineUUID, message: String },
+}
+
+impl std::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Compile{ kind, message } => write!(f, "script for {} doesn't compile: {}", kind, message),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Copy of a machine's ports a script works on, written back if it
+/// changes anything.
+#[derive(Debug, Clone)]
+pub struct ScriptPorts {
+    ports:    [Option<(ResourceID, u16)>; 4],
+    recipe:   Option<ResourceID>,
+    produced: u16,
+    consumed: Vec<(ResourceID, u16)>,
+    changed:  bool,
+}
+
+impl ScriptPorts {
+
+    pub fn new(ports: &Ports, recipe: Option<ResourceID>) -> Self {
+        Self{ ports: PortID::ALL.map(|v| ports.get(v).get()), recipe, produced: 0, consumed: Vec::new(), changed: false }
+    }
+
+    pub fn apply(&self, ports: &mut Ports) {
+        for port in PortID::ALL {
+            match self.ports[port as usize] {
+                Some((resource, count)) => ports.get_mut(port).set(resource, count),
+                None                    => ports.get_mut(port).clear(),
+            }
+        }
+    }
+
+    pub fn count(&self, port: PortID) -> u16 {
+        self.ports[port as usize].map_or(0, |(_, count)| count)
+    }
+
+    /// Moves up to `count` from one port to another, if the other is empty
+    /// or holds the same resource. Returns how many moved.
+    pub fn transfer(&mut self, from: PortID, to: PortID, count: u16) -> u16 {
+        let (resource, available) = if let Some(v) = self.ports[from as usize] { v } else { return 0; };
+        let held = match self.ports[to as usize] {
+            Some((other, _)) if other != resource => return 0,
+            Some((_, held))                       => held,
+            None                                  => 0,
+        };
+        let moved = count.min(available).min(u16::MAX - held);
+        if moved == 0 || from == to { return 0; }
+        self.set(from, resource, available - moved);
+        self.set(to,   resource, held + moved);
+        moved
+    }
+
+    /// Adds up to `count` of the recipe resource to a port, no more than
+    /// `SCRIPT_PRODUCED_PER_TICK` in total. Returns how many were added.
+    pub fn produce(&mut self, port: PortID, count: u16) -> u16 {
+        let recipe = if let Some(v) = self.recipe { v } else { return 0; };
+        let held = match self.ports[port as usize] {
+            Some((other, _)) if other != recipe => return 0,
+            Some((_, held))                     => held,
+            None                                => 0,
+        };
+        let added = count.min(u16::MAX - held).min(SCRIPT_PRODUCED_PER_TICK - self.produced);
+        if added > 0 { self.set(port, recipe, held + added); }
+        self.produced += added;
+        added
+    }
+
+    /// Destroys up to `count` from a port. Returns how many were destroyed.
+    pub fn consume(&mut self, port: PortID, count: u16) -> u16 {
+        let (resource, held) = if let Some(v) = self.ports[port as usize] { v } else { return 0; };
+        let removed = count.min(held);
+        if removed == 0 { return 0; }
+        self.set(port, resource, held - removed);
+        match self.consumed.iter_mut().find(|(v, _)| *v == resource) {
+            Some((_, count)) => *count = count.saturating_add(removed),
+            None             => self.consumed.push((resource, removed)),
+        }
+        removed
+    }
+
+    /// Records what the script produced and consumed.
+    pub fn record(&self, flow: &mut ResourceFlow) {
+        if let Some(recipe) = self.recipe { flow.record_produced(recipe, self.produced as u32); }
+        for &(resource, count) in self.consumed.iter() { flow.record_consumed(resource, count as u32); }
+    }
+
+    fn set(&mut self, port: PortID, resource: ResourceID, count: u16) {
+        self.ports[port as usize] = if count == 0 { None } else { Some((resource, count)) };
+        self.changed = true;
+    }
+
+}
+
+/// Counts given to scripts are clamped to what a port can hold.
+fn script_count(count: INT) -> u16 {
+    count.clamp(0, u16::MAX as INT) as u16
+}
+
+/// Compiled machine scripts by kind, and the sandboxed engine they run in.
+///
+/// A script runs once a tick for each machine of its kind, with `ports`,
+/// `tick` and the port constants `A` to `D` in scope:
+///
+/// ```text
+/// if ports.count(A) > 0 && tick % 4 == 0 {
+///     ports.transfer(A, B, 1);
+/// }
+/// ```
+///
+/// `ports` also has `resource(port)`, `recipe`, `produce(port, count)` and
+/// `consume(port, count)`. Machines whose script changed nothing record
+/// `InputStarved`, but aren't put to sleep since a script may act on the
+/// tick alone.
+///
+/// Only the core language is available, with no file access, clock, or
+/// output from `print` and `debug`.
+pub struct MachineScripts {
+    engine:  Engine,
+    scripts: HashMap<MachineUUID, AST>,
+}
+
+impl Default for MachineScripts {
+    fn default() -> Self {
+        let mut engine = Engine::new_raw();
+        engine.register_global_module(CorePackage::new().as_shared_module());
+        engine
+            .set_max_operations(SCRIPT_OPERATIONS_PER_TICK)
+            .set_max_call_levels(16)
+            .set_max_expr_depths(64, 32)
+            .set_max_string_size(1024)
+            .set_max_array_size(1024)
+            .set_max_map_size(1024)
+            .disable_symbol("eval");
+
+        engine
+            .register_type_with_name::<PortID>("Port")
+            .register_type_with_name::<ScriptPorts>("Ports")
+            .register_fn("count",    |ports: &mut ScriptPorts, port: PortID| ports.count(port) as INT)
+            .register_fn("resource", |ports: &mut ScriptPorts, port: PortID| ports.ports[port as usize].map_or_else(String::new, |(v, _)| resource_name(v)))
+            .register_fn("transfer", |ports: &mut ScriptPorts, from: PortID, to: PortID, count: INT| ports.transfer(from, to, script_count(count)) as INT)
+            .register_fn("produce",  |ports: &mut ScriptPorts, port: PortID, count: INT| ports.produce(port, script_count(count)) as INT)
+            .register_fn("consume",  |ports: &mut ScriptPorts, port: PortID, count: INT| ports.consume(port, script_count(count)) as INT)
+            .register_get("recipe",  |ports: &mut ScriptPorts| ports.recipe.map_or_else(String::new, resource_name));
+
+        Self{ engine, scripts: HashMap::default() }
+    }
+}
+
+impl MachineScripts {
+
+    pub fn compile(&mut self, kind: MachineUUID, source: &str) -> Result<(), ScriptError> {
+        let ast = self.engine.compile(source).map_err(|e| ScriptError::Compile{ kind, message: e.to_string() })?;
+        self.scripts.insert(kind, ast);
+        Ok(())
+    }
+
+    pub fn get(&self, kind: MachineUUID) -> Option<&AST> {
+        self.scripts.get(&kind)
+    }
+
+    /// Runs a script against a copy of a machine's ports.
+    pub fn run(&self, scope: &mut Scope, ast: &AST, ports: ScriptPorts, tick: u32) -> Result<ScriptPorts, String> {
+        scope.clear();
+        for (name, port) in ["A", "B", "C", "D"].into_iter().zip(PortID::ALL) { scope.push_constant(name, port); }
+        scope.push_constant("tick", tick as INT);
+        scope.push("ports", ports);
+        self.engine.run_ast_with_scope(scope, ast).map_err(|e| e.to_string())?;
+        scope.get_value::<ScriptPorts>("ports").ok_or_else(|| "ports was replaced".to_string())
+    }
+
+}
+
+/// Compiles a script and registers a machine kind run by it.
+pub fn register_script(app: &mut App, kind: MachineUUID, source: &str) -> Result<(), ScriptError> {
+    app.world.get_resource_or_insert_with(MachineScripts::default).compile(kind, source)?;
+    register_machine(app, kind, |e| { e.insert(ScriptedMachine); });
+    Ok(())
+}
+
+pub fn update_scripted_machines(
+    tick:       Res<FactoryTick>,
+    scripts:    Res<MachineScripts>,
+    mut events: EventWriter<ScriptFailed>,
+    mut flow:   ResMut<ResourceFlow>,
+    mut scope:  Local<Scope<'static>>,
+    mut q:      Query<(Entity, &Machine, &mut Ports, &mut FlowMonitor), With<ScriptedMachine>>,
+) {
+    for (entity, machine, mut ports, mut monitor) in q.iter_mut() {
+        let ast = if let Some(v) = scripts.get(machine.kind) { v } else { continue; };
+        let status = match scripts.run(&mut scope, ast, ScriptPorts::new(&ports, machine.recipe), tick.0) {
+            Ok(result) if result.changed => {
+                result.apply(&mut ports);
+                result.record(&mut flow);
+                FlowStatus::Running
+            },
+            Ok(_) => FlowStatus::InputStarved,
+            Err(error) => {
+                events.send(ScriptFailed{ machine: entity, error });
+                FlowStatus::InputStarved
+            },
+        };
+        monitor.record(status);
+    }
+}
+
+pub struct FactoryScriptPlugin;
+
+impl Plugin for FactoryScriptPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MachineScripts>();
+        app.add_event::<ScriptFailed>();
+        app.add_system_to_stage(FactoryStage::Machine, update_scripted_machines.label(FactorySystem::MachineScript).after(FactorySystem::MachineSink));
+    }
+}
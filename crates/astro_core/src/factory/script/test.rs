@@ -0,0 +1,71 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::ecs::event::Events;
+
+use super::*;
+use crate::factory::{FactoryCommand, FactoryPlugins, ResourceUUID, execute_command};
+
+const MACHINE_MOVER: MachineUUID = MachineUUID::new("MOVER");
+
+fn speed() -> ResourceID {
+    ResourceID::intern(ResourceUUID::new("SPEED"))
+}
+
+fn build_app(source: &str, recipe: Option<ResourceID>) -> (App, Entity) {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+    register_script(&mut app, MACHINE_MOVER, source).unwrap();
+    let machine = execute_command(&mut app.world, FactoryCommand::PlaceMachine{ kind: MACHINE_MOVER, recipe }).unwrap().unwrap();
+    (app, machine)
+}
+
+fn counts(app: &App, machine: Entity) -> [u16; 4] {
+    let ports = app.world.get::<Ports>(machine).unwrap();
+    PortID::ALL.map(|v| ports.get(v).count())
+}
+
+#[test]
+fn moves_between_ports() {
+    let (mut app, machine) = build_app("if ports.resource(A) == \"SPEED\" { ports.transfer(A, B, 2); }", None);
+    app.world.get_mut::<Ports>(machine).unwrap().get_mut(PortID::A).set(speed(), 5);
+
+    app.update();
+    assert_eq!(counts(&app, machine), [3, 2, 0, 0]);
+    app.update();
+    app.update();
+    assert_eq!(counts(&app, machine), [0, 5, 0, 0]);
+
+    app.update();
+    let monitor = app.world.get::<FlowMonitor>(machine).unwrap();
+    assert_eq!((monitor.status(), monitor.ticks_in(FlowStatus::Running)), (FlowStatus::InputStarved, 3));
+}
+
+#[test]
+fn produces_on_tick() {
+    let (mut app, machine) = build_app("if tick % 2 == 0 { ports.produce(B, 1); } ports.consume(C, 100);", Some(speed()));
+    app.world.get_mut::<Ports>(machine).unwrap().get_mut(PortID::C).set(speed(), 7);
+    for _ in 0..4 { app.update(); }
+    assert_eq!(counts(&app, machine), [0, 2, 0, 0]);
+
+    let (mut app, machine) = build_app("ports.produce(B, 5); ports.produce(C, 5);", Some(speed()));
+    for _ in 0..3 { app.update(); }
+    assert_eq!(counts(&app, machine), [0, 3, 0, 0]);
+}
+
+#[test]
+fn operation_budget() {
+    let (mut app, machine) = build_app("ports.consume(A, 1); loop {}", None);
+    app.world.get_mut::<Ports>(machine).unwrap().get_mut(PortID::A).set(speed(), 5);
+    app.update();
+    assert_eq!(counts(&app, machine), [5, 0, 0, 0]);
+
+    let events = app.world.get_resource::<Events<ScriptFailed>>().unwrap();
+    let failed = events.get_reader().iter(events).map(|v| v.machine).collect::<Vec<_>>();
+    assert_eq!(failed, vec![machine]);
+
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+    assert_eq!(register_script(&mut app, MACHINE_MOVER, "let").map_err(|ScriptError::Compile{ kind, .. }| kind), Err(MACHINE_MOVER));
+}
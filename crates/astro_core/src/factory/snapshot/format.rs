@@ -0,0 +1,209 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+//! Line based text format, one entity per line:
+//!
+//! ```text
+//! astro 1
+//! tick 120
+//! machine SOURCE SPEED - SPEED:1 - -
+//! machine SINK - - - - -
+//! filter 1:A SPEED
+//! place 1 4,0 East 1,1 -1,0 1,0 0,1 0,-1
+//! connection simple 16 1:A 0:B SPEED@110 SPEED@118
+//! path 0 1,0 2,0 3,0
+//! shared 0
+//! ```
+//!
+//! Filters and placements refer to machines by index, paths and shared
+//! markers to connections.
+
+use std::str::FromStr;
+
+use bevy::prelude::IVec2;
+
+use crate::factory::{FormatError, Optional, format_lines, parse_header, parse_number, parse_tile};
+
+use super::{FactorySnapshot, MachineSnapshot, ConnectionSnapshot, SnapshotError, Footprint, MachineUUID, ResourceUUID, PortID, Rotation};
+
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+impl std::fmt::Display for FactorySnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "astro {}", SNAPSHOT_VERSION)?;
+        writeln!(f, "tick {}", self.tick)?;
+
+        for machine in self.machines.iter() {
+            write!(f, "machine {} {}", Optional(machine.kind), Optional(machine.recipe))?;
+            for port in machine.ports.iter() {
+                match port {
+                    Some((resource, count)) => write!(f, " {}:{}", resource, count)?,
+                    None                    => write!(f, " -")?,
+                }
+            }
+            writeln!(f)?;
+        }
+
+        for (idx, machine) in self.machines.iter().enumerate() {
+            for (port, filter) in PortID::ALL.into_iter().zip(machine.filters.iter()) {
+                if let Some(resource) = filter { writeln!(f, "filter {}:{:?} {}", idx, port, resource)?; }
+            }
+            if let Some((position, rotation, footprint)) = machine.placement {
+                write!(f, "place {} {} {:?} {}", idx, Tile(position), rotation, Tile(footprint.size))?;
+                for &port in footprint.ports.iter() { write!(f, " {}", Tile(port))?; }
+                writeln!(f)?;
+            }
+        }
+
+        for connection in self.connections.iter() {
+            write!(f, "connection {} {} {} {}", connection.pipe, connection.length, Endpoint(connection.send), Endpoint(connection.recv))?;
+            for (tick, resource) in connection.packets.iter() {
+                write!(f, " {}@{}", resource, tick)?;
+            }
+            writeln!(f)?;
+        }
+
+        for (idx, connection) in self.connections.iter().enumerate() {
+            if let Some(path) = connection.path.as_ref() {
+                write!(f, "path {}", idx)?;
+                for &tile in path.iter() { write!(f, " {}", Tile(tile))?; }
+                writeln!(f)?;
+            }
+            if connection.shared { writeln!(f, "shared {}", idx)?; }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for FactorySnapshot {
+    type Err = SnapshotError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut lines = format_lines(text);
+        parse_header(&mut lines, "astro", SNAPSHOT_VERSION)?;
+
+        let mut result = Self::default();
+        for (line, text) in lines {
+            let mut tokens = text.split_whitespace();
+            match tokens.next() {
+                Some("tick")       => result.tick = parse_number(line, tokens.next())?,
+                Some("machine")    => result.machines.push(parse_machine(line, &mut tokens)?),
+                Some("filter")     => parse_filter(line, &mut tokens, &mut result.machines)?,
+                Some("place")      => parse_placement(line, &mut tokens, &mut result.machines)?,
+                Some("connection") => result.connections.push(parse_connection(line, &mut tokens)?),
+                Some("path")       => parse_path(line, &mut tokens, &mut result.connections)?,
+                Some("shared")     => parse_shared(line, &mut tokens, &mut result.connections)?,
+                _ => return Err(FormatError::Parse{ line, message: "Unknown entry" }.into()),
+            }
+            if tokens.next().is_some() { return Err(FormatError::Parse{ line, message: "Unexpected token" }.into()); }
+        }
+
+        Ok(result)
+    }
+}
+
+struct Tile(IVec2);
+
+impl std::fmt::Display for Tile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{}", self.0.x, self.0.y)
+    }
+}
+
+struct Endpoint(Option<(u32, PortID)>);
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some((idx, port)) => write!(f, "{}:{:?}", idx, port),
+            None              => f.write_str("-"),
+        }
+    }
+}
+
+fn parse_machine<'a>(line: usize, tokens: &mut impl Iterator<Item = &'a str>) -> Result<MachineSnapshot, SnapshotError> {
+    let mut result = MachineSnapshot{
+        kind:    parse_optional(line, tokens.next(), |v| MachineUUID::try_new(v).ok())?,
+        recipe:  parse_optional(line, tokens.next(), |v| ResourceUUID::try_new(v).ok())?,
+        ports:   [None; 4],
+        filters: [None; 4],
+        ..Default::default()
+    };
+
+    for port in result.ports.iter_mut() {
+        *port = parse_optional(line, tokens.next(), |v| {
+            let (resource, count) = v.split_once(':')?;
+            Some((ResourceUUID::try_new(resource).ok()?, count.parse().ok()?))
+        })?;
+    }
+
+    Ok(result)
+}
+
+/// Filters refer to machines by index, so must follow them.
+fn parse_filter<'a>(line: usize, tokens: &mut impl Iterator<Item = &'a str>, machines: &mut [MachineSnapshot]) -> Result<(), SnapshotError> {
+    let (idx, port) = parse_optional(line, tokens.next(), parse_endpoint)?.ok_or(FormatError::Parse{ line, message: "Missing field" })?;
+    let resource    = parse_optional(line, tokens.next(), |v| ResourceUUID::try_new(v).ok())?;
+    let machine     = machines.get_mut(idx as usize).ok_or(SnapshotError::InvalidMachine(idx))?;
+    machine.filters[port as usize] = resource;
+    Ok(())
+}
+
+/// Placements refer to machines by index, so must follow them.
+fn parse_placement<'a>(line: usize, tokens: &mut impl Iterator<Item = &'a str>, machines: &mut [MachineSnapshot]) -> Result<(), SnapshotError> {
+    let idx      = parse_number::<u32>(line, tokens.next())?;
+    let position = parse_optional(line, tokens.next(), parse_tile)?.ok_or(FormatError::Parse{ line, message: "Missing field" })?;
+    let rotation = parse_optional(line, tokens.next(), |v| v.parse::<Rotation>().ok())?.ok_or(FormatError::Parse{ line, message: "Missing field" })?;
+    let mut tile = || parse_optional(line, tokens.next(), parse_tile)?.ok_or(FormatError::Parse{ line, message: "Missing field" });
+    let size     = tile()?;
+    let ports    = [tile()?, tile()?, tile()?, tile()?];
+
+    let machine = machines.get_mut(idx as usize).ok_or(SnapshotError::InvalidMachine(idx))?;
+    machine.placement = Some((position, rotation, Footprint{ size, ports }));
+    Ok(())
+}
+
+/// Paths refer to connections by index, so must follow them.
+fn parse_path<'a>(line: usize, tokens: &mut impl Iterator<Item = &'a str>, connections: &mut [ConnectionSnapshot]) -> Result<(), SnapshotError> {
+    let idx  = parse_number::<u32>(line, tokens.next())?;
+    let path = tokens.map(|v| parse_tile(v).ok_or(FormatError::Parse{ line, message: "Invalid tile" })).collect::<Result<Vec<_>, _>>()?;
+    connections.get_mut(idx as usize).ok_or(SnapshotError::InvalidConnection(idx))?.path = Some(path);
+    Ok(())
+}
+
+/// Shared markers refer to connections by index, so must follow them.
+fn parse_shared<'a>(line: usize, tokens: &mut impl Iterator<Item = &'a str>, connections: &mut [ConnectionSnapshot]) -> Result<(), SnapshotError> {
+    let idx = parse_number::<u32>(line, tokens.next())?;
+    connections.get_mut(idx as usize).ok_or(SnapshotError::InvalidConnection(idx))?.shared = true;
+    Ok(())
+}
+
+fn parse_connection<'a>(line: usize, tokens: &mut impl Iterator<Item = &'a str>) -> Result<ConnectionSnapshot, SnapshotError> {
+    let pipe   = tokens.next().ok_or(FormatError::Parse{ line, message: "Missing pipe type" })?.to_string();
+    let length = parse_number(line, tokens.next())?;
+    let send   = parse_optional(line, tokens.next(), parse_endpoint)?;
+    let recv   = parse_optional(line, tokens.next(), parse_endpoint)?;
+
+    let packets = tokens.map(|v| {
+        let (resource, tick) = v.split_once('@').ok_or(FormatError::Parse{ line, message: "Invalid packet" })?;
+        let resource = ResourceUUID::try_new(resource).map_err(|message| FormatError::Parse{ line, message })?;
+        Ok((parse_number(line, Some(tick))?, resource))
+    }).collect::<Result<_, SnapshotError>>()?;
+
+    Ok(ConnectionSnapshot{ pipe, length, send, recv, packets, path: None, shared: false })
+}
+
+fn parse_endpoint(value: &str) -> Option<(u32, PortID)> {
+    let (idx, port) = value.split_once(':')?;
+    Some((idx.parse().ok()?, port.parse().ok()?))
+}
+
+fn parse_optional<T>(line: usize, token: Option<&str>, parse: impl FnOnce(&str) -> Option<T>) -> Result<Option<T>, FormatError> {
+    match token {
+        None      => Err(FormatError::Parse{ line, message: "Missing field" }),
+        Some("-") => Ok(None),
+        Some(v)   => parse(v).map(Some).ok_or(FormatError::Parse{ line, message: "Invalid field" }),
+    }
+}
@@ -0,0 +1,279 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use bevy::{prelude::{Entity, IVec2, Or, World, With}, utils::HashMap};
+
+use super::{
+    DetachedConnection, FactoryTick, Footprint, FormatError, GridPosition, Machine, MachineUUID, MachineError, PipePath, PipeRegistry, PipeContents, ConnectionBuilder, SharedConnection,
+    ConnectionError, Ports, PortID, PortFilter, PortSend, PortRecv, ResourceID, ResourceUUID, Rotation, FlowMonitor, capture_connection,
+    GridError, index_placement, spawn_machine
+};
+
+mod format;
+pub use format::*;
+
+/// Serializable state of the factory, entities are referred to by their
+/// index into `machines` and resources by their UUID.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FactorySnapshot {
+    pub tick:        u32,
+    pub machines:    Vec<MachineSnapshot>,
+    pub connections: Vec<ConnectionSnapshot>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MachineSnapshot {
+    pub kind:    Option<MachineUUID>,
+    pub recipe:  Option<ResourceUUID>,
+    pub ports:   [Option<(ResourceUUID, u16)>; 4],
+    pub filters: [Option<ResourceUUID>; 4],
+    /// Where the machine sits on the grid, if it was placed on it.
+    pub placement: Option<(IVec2, Rotation, Footprint)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionSnapshot {
+    pub pipe:    String,
+    pub length:  u32,
+    pub send:    Option<(u32, PortID)>,
+    pub recv:    Option<(u32, PortID)>,
+    pub packets: Vec<(u32, ResourceUUID)>,
+    /// Tiles the connection runs along, if it was placed on the grid.
+    pub path:    Option<Vec<IVec2>>,
+    /// Whether the connection may share its ports, see `SharedConnection`.
+    pub shared:  bool,
+}
+
+/// Entities created by restoring a snapshot, in snapshot order.
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotEntities {
+    pub machines:    Vec<Entity>,
+    pub connections: Vec<Entity>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    Format(FormatError),
+    UnnamedResource(ResourceID),
+    UnknownPipe(String),
+    InvalidMachine(u32),
+    InvalidConnection(u32),
+    Machine(MachineError),
+    Connection(ConnectionError),
+    Grid(GridError),
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Format(e)              => e.fmt(f),
+            Self::UnnamedResource(v)     => write!(f, "resource {:?} has no UUID", v),
+            Self::UnknownPipe(v)         => write!(f, "pipe type {} is not registered", v),
+            Self::InvalidMachine(v)      => write!(f, "machine index {} is out of range", v),
+            Self::InvalidConnection(v)   => write!(f, "connection index {} is out of range", v),
+            Self::Machine(e)             => e.fmt(f),
+            Self::Connection(e)          => e.fmt(f),
+            Self::Grid(e)                => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<FormatError> for SnapshotError {
+    fn from(e: FormatError) -> Self {
+        Self::Format(e)
+    }
+}
+
+impl From<MachineError> for SnapshotError {
+    fn from(e: MachineError) -> Self {
+        Self::Machine(e)
+    }
+}
+
+impl From<ConnectionError> for SnapshotError {
+    fn from(e: ConnectionError) -> Self {
+        Self::Connection(e)
+    }
+}
+
+impl From<GridError> for SnapshotError {
+    fn from(e: GridError) -> Self {
+        Self::Grid(e)
+    }
+}
+
+impl FactorySnapshot {
+
+    /// Captures every entity with `Ports` and every connection.
+    pub fn capture(world: &mut World) -> Result<Self, SnapshotError> {
+        Ok(Self::capture_entities(world)?.0)
+    }
+
+    /// Like `capture`, also returning the captured entities in snapshot order.
+    /// Connections of unregistered pipe types are skipped, those that lost
+    /// both of their machines are kept.
+    pub fn capture_entities(world: &mut World) -> Result<(Self, SnapshotEntities), SnapshotError> {
+        let mut machines: Vec<Entity> = world.query_filtered::<Entity, With<Ports>>().iter(world).collect();
+        let mut connections: Vec<Entity> = world.query_filtered::<Entity, Or<(With<PortSend>, With<PortRecv>, With<DetachedConnection>)>>().iter(world).collect();
+        machines.sort_unstable();
+        connections.sort_unstable();
+
+        connections.retain(|&e| capture_connection(world, e).is_some());
+
+        let snapshot = Self::capture_with(world, &machines, &connections)?;
+        Ok((snapshot, SnapshotEntities{ machines, connections }))
+    }
+
+    /// Captures the given entities, connection ports referring to machines
+    /// outside of the set are left unconnected.
+    pub fn capture_with(world: &World, machines: &[Entity], connections: &[Entity]) -> Result<Self, SnapshotError> {
+        let machines = machines.iter().copied().filter(|&e| world.get::<Ports>(e).is_some()).collect::<Vec<_>>();
+        let indices  = machines.iter().enumerate().map(|(i, &e)| (e, i as u32)).collect::<HashMap<_, _>>();
+        Self::capture_indexed(world, &machines, connections, |e| indices.get(&e).copied())
+    }
+
+    /// Like `capture_with`, with connection ports referring to machines by
+    /// `index`, which may include machines outside of the set.
+    pub fn capture_indexed(world: &World, machines: &[Entity], connections: &[Entity], index: impl Fn(Entity) -> Option<u32>) -> Result<Self, SnapshotError> {
+        let tick     = world.get_resource::<FactoryTick>().map_or(0, |v| v.0);
+        let machines = machines.iter().filter_map(|&e| Some((e, world.get::<Ports>(e)?))).collect::<Vec<_>>();
+
+        let machines = machines.into_iter().map(|(e, ports)| {
+            let machine = world.get::<Machine>(e);
+            let mut filters = [None; 4];
+            if let Some(filter) = world.get::<PortFilter>(e) {
+                for (port, slot) in PortID::ALL.into_iter().zip(filters.iter_mut()) {
+                    *slot = filter.get(port).map(resource_uuid).transpose()?;
+                }
+            }
+            Ok(MachineSnapshot{
+                kind:      machine.map(|v| v.kind),
+                recipe:    machine.and_then(|v| v.recipe).map(resource_uuid).transpose()?,
+                ports:     capture_ports(ports)?,
+                filters,
+                placement: match (world.get::<GridPosition>(e), world.get::<Rotation>(e), world.get::<Footprint>(e)) {
+                    (Some(position), Some(&rotation), Some(&footprint)) => Some((position.0, rotation, footprint)),
+                    _ => None,
+                },
+            })
+        }).collect::<Result<Vec<_>, SnapshotError>>()?;
+
+        let connections = connections.iter().filter_map(|&e| {
+            let (pipe, contents) = capture_connection(world, e)?;
+            Some((e, pipe, contents))
+        }).map(|(e, pipe, contents)| {
+            Ok(ConnectionSnapshot{
+                pipe:    pipe.to_string(),
+                length:  contents.length,
+                send:    world.get::<PortSend>(e).and_then(|&PortSend(t, p)| Some((index(t)?, p))),
+                recv:    world.get::<PortRecv>(e).and_then(|&PortRecv(t, p)| Some((index(t)?, p))),
+                packets: contents.packets.iter().map(|&(tick, r)| Ok((tick, resource_uuid(r)?))).collect::<Result<_, SnapshotError>>()?,
+                path:    world.get::<PipePath>(e).map(|v| v.0.clone()),
+                shared:  world.get::<SharedConnection>(e).is_some(),
+            })
+        }).collect::<Result<Vec<_>, SnapshotError>>()?;
+
+        Ok(Self{ tick, machines, connections })
+    }
+
+    /// Spawns the snapshot's entities and sets the factory tick to match.
+    pub fn restore(&self, world: &mut World) -> Result<SnapshotEntities, SnapshotError> {
+        let result = self.restore_after(world, &[])?;
+        world.insert_resource(FactoryTick(self.tick));
+        Ok(result)
+    }
+
+    /// Spawns the snapshot's entities alongside `existing` ones, connection
+    /// ports index into `existing` followed by the snapshot's machines. The
+    /// factory tick is left alone.
+    pub fn restore_after(&self, world: &mut World, existing: &[Entity]) -> Result<SnapshotEntities, SnapshotError> {
+        let mut result = SnapshotEntities::default();
+
+        for machine in self.machines.iter() {
+            let recipe = machine.recipe.map(ResourceID::intern);
+            let entity = match machine.kind {
+                Some(kind) => spawn_machine(world, kind, recipe)?,
+                None       => world.spawn().insert_bundle((Ports::default(), FlowMonitor::default())).id(),
+            };
+
+            let mut ports = world.get_mut::<Ports>(entity).unwrap();
+            for (port, contents) in PortID::ALL.into_iter().zip(machine.ports.iter()) {
+                if let Some((resource, count)) = contents {
+                    ports.get_mut(port).set(ResourceID::intern(*resource), *count);
+                }
+            }
+
+            if let Some(filter) = machine.port_filter() {
+                world.entity_mut(entity).insert(filter);
+            }
+            if let Some((position, rotation, footprint)) = machine.placement {
+                world.entity_mut(entity).insert_bundle((GridPosition(position), rotation, footprint));
+                index_placement(world, entity)?;
+            }
+
+            result.machines.push(entity);
+        }
+
+        for connection in self.connections.iter() {
+            let pipe = world.get_resource::<PipeRegistry>()
+                .and_then(|v| v.get(&connection.pipe).copied())
+                .ok_or_else(|| SnapshotError::UnknownPipe(connection.pipe.clone()))?;
+
+            let machine     = |idx: u32| existing.iter().chain(result.machines.iter()).nth(idx as usize).copied().ok_or(SnapshotError::InvalidMachine(idx));
+            let mut builder = ConnectionBuilder::new(connection.length).exclusive(!connection.shared);
+            if let Some((idx, port)) = connection.send { builder = builder.send_to(machine(idx)?, port); }
+            if let Some((idx, port)) = connection.recv { builder = builder.recv_from(machine(idx)?, port); }
+
+            let contents = PipeContents{
+                length:  connection.length,
+                packets: connection.packets.iter().map(|&(tick, r)| (tick, ResourceID::intern(r))).collect(),
+            };
+
+            let entity = match (connection.send, connection.recv) {
+                (None, None) => builder.build_detached_with(world, &pipe, &contents)?,
+                _            => builder.build_with(world, &pipe, &contents)?,
+            };
+            if let Some(path) = connection.path.as_ref() {
+                world.entity_mut(entity).insert(PipePath(path.clone()));
+                index_placement(world, entity)?;
+            }
+            result.connections.push(entity);
+        }
+
+        Ok(result)
+    }
+
+}
+
+impl MachineSnapshot {
+
+    /// The machine's filters as a component, `None` if no port is filtered.
+    pub fn port_filter(&self) -> Option<PortFilter> {
+        if self.filters.iter().all(Option::is_none) { return None; }
+        let mut result = PortFilter::default();
+        for (port, resource) in PortID::ALL.into_iter().zip(self.filters.iter()) {
+            result.set(port, resource.map(ResourceID::intern));
+        }
+        Some(result)
+    }
+
+}
+
+pub(crate) fn capture_ports(ports: &Ports) -> Result<[Option<(ResourceUUID, u16)>; 4], SnapshotError> {
+    let mut result = [None; 4];
+    for (port, slot) in PortID::ALL.into_iter().zip(result.iter_mut()) {
+        if let Some((resource, count)) = ports.get(port).get() {
+            *slot = Some((resource_uuid(resource)?, count));
+        }
+    }
+    Ok(result)
+}
+
+fn resource_uuid(resource: ResourceID) -> Result<ResourceUUID, SnapshotError> {
+    resource.uuid().ok_or(SnapshotError::UnnamedResource(resource))
+}
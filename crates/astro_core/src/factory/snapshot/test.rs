@@ -0,0 +1,115 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::prelude::{App, IVec2};
+
+use super::*;
+use crate::factory::{ConnectionLifecycle, FactoryPlugins, PipeSimple, MACHINE_SOURCE, MACHINE_SINK, MAX_PIPE_LENGTH};
+
+fn example() -> FactorySnapshot {
+    let speed = ResourceUUID::new("SPEED");
+    FactorySnapshot{
+        tick: 120,
+        machines: vec![
+            MachineSnapshot{ kind: Some(MachineUUID::new("SOURCE")), recipe: Some(speed), ports: [None, Some((speed, 1)), None, None], filters: [None; 4], placement: None },
+            MachineSnapshot{ kind: None, recipe: None, ports: [None; 4], filters: [Some(speed), None, None, None], placement: Some((IVec2::new(4, -2), Rotation::East, Footprint::UNIT)) },
+        ],
+        connections: vec![
+            ConnectionSnapshot{ pipe: "simple".to_string(), length: 16, send: Some((1, PortID::A)), recv: Some((0, PortID::B)), packets: vec![(110, speed), (118, speed)], path: None, shared: true },
+            ConnectionSnapshot{ pipe: "simple".to_string(), length: 4,  send: None, recv: None, packets: vec![], path: Some(vec![IVec2::new(0, 0), IVec2::new(0, -1)]), shared: false },
+        ],
+    }
+}
+
+#[test]
+fn format() {
+    assert_eq!(example().to_string(), "\
+astro 1
+tick 120
+machine SOURCE SPEED - SPEED:1 - -
+machine - - - - - -
+filter 1:A SPEED
+place 1 4,-2 East 1,1 -1,0 1,0 0,1 0,-1
+connection simple 16 1:A 0:B SPEED@110 SPEED@118
+connection simple 4 - -
+shared 0
+path 1 0,0 0,-1
+");
+}
+
+#[test]
+fn round_trip() {
+    let snapshot = example();
+    assert_eq!(snapshot.to_string().parse::<FactorySnapshot>(), Ok(snapshot));
+}
+
+#[test]
+fn parse_errors() {
+    assert_eq!("".parse::<FactorySnapshot>(),                     Err(SnapshotError::Format(FormatError::Parse{ line: 1, message: "Missing header" })));
+    assert_eq!("astro 2".parse::<FactorySnapshot>(),              Err(SnapshotError::Format(FormatError::UnsupportedVersion{ format: "astro", version: 2 })));
+    assert_eq!("astro 1\nmachine - -".parse::<FactorySnapshot>(), Err(SnapshotError::Format(FormatError::Parse{ line: 2, message: "Missing field" })));
+    assert_eq!("astro 1\n\n# comment\nbogus".parse::<FactorySnapshot>(), Err(SnapshotError::Format(FormatError::Parse{ line: 4, message: "Unknown entry" })));
+    assert_eq!("astro 1\nconnection simple 4 0:E -".parse::<FactorySnapshot>(), Err(SnapshotError::Format(FormatError::Parse{ line: 2, message: "Invalid field" })));
+    assert_eq!("astro 1\nfilter 0:A SPEED".parse::<FactorySnapshot>(), Err(SnapshotError::InvalidMachine(0)));
+    assert_eq!("astro 1\npath 0 1,0".parse::<FactorySnapshot>(), Err(SnapshotError::InvalidConnection(0)));
+    assert_eq!("astro 1\nshared 0".parse::<FactorySnapshot>(), Err(SnapshotError::InvalidConnection(0)));
+    assert_eq!("astro 1\nmachine - - - - - -\nplace 0 1;0 North".parse::<FactorySnapshot>(), Err(SnapshotError::Format(FormatError::Parse{ line: 3, message: "Invalid field" })));
+}
+
+#[test]
+fn world_round_trip() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+
+    let world  = &mut app.world;
+    let speed  = ResourceID::intern(ResourceUUID::new("SPEED"));
+    let source = spawn_machine(world, MACHINE_SOURCE, Some(speed)).unwrap();
+    let sink   = spawn_machine(world, MACHINE_SINK, None).unwrap();
+    let mut filter = PortFilter::default();
+    filter.set(PortID::A, Some(speed));
+    world.entity_mut(sink).insert(filter);
+    ConnectionBuilder::new(8).send_to(sink, PortID::A).recv_from(source, PortID::B).build::<PipeSimple>(world).unwrap();
+
+    // Kept with neither end after both of its machines are removed.
+    world.get_resource_mut::<ConnectionLifecycle>().unwrap().despawn_orphans = false;
+    let removed_source = spawn_machine(world, MACHINE_SOURCE, Some(speed)).unwrap();
+    let removed_sink   = spawn_machine(world, MACHINE_SINK, None).unwrap();
+    ConnectionBuilder::new(4).send_to(removed_sink, PortID::C).recv_from(removed_source, PortID::D).build::<PipeSimple>(world).unwrap();
+    world.despawn(removed_source);
+    world.despawn(removed_sink);
+    for _ in 0..4 { app.update(); }
+
+    let snapshot = FactorySnapshot::capture(&mut app.world).unwrap();
+    assert_eq!(snapshot.machines.len(), 2);
+    assert_eq!(snapshot.connections.len(), 2);
+    assert!(snapshot.connections.iter().any(|v| v.send.is_none() && v.recv.is_none() && v.length == 4));
+    assert!(snapshot.connections.iter().any(|v| !v.packets.is_empty()));
+
+    let mut restored = App::new();
+    restored.add_plugins(FactoryPlugins);
+    snapshot.to_string().parse::<FactorySnapshot>().unwrap().restore(&mut restored.world).unwrap();
+    assert_eq!(FactorySnapshot::capture(&mut restored.world), Ok(snapshot));
+
+    // Both worlds carry on identically from the snapshot.
+    app.update();
+    restored.update();
+    assert_eq!(FactorySnapshot::capture(&mut restored.world), FactorySnapshot::capture(&mut app.world));
+}
+
+#[test]
+fn detached_lengths() {
+    for (length, error) in [(0, ConnectionError::ZeroLength), (MAX_PIPE_LENGTH + 1, ConnectionError::TooLong(MAX_PIPE_LENGTH + 1))] {
+        let mut app = App::new();
+        app.add_plugins(FactoryPlugins);
+
+        let snapshot = FactorySnapshot{
+            tick: 0,
+            machines: vec![],
+            connections: vec![
+                ConnectionSnapshot{ pipe: "simple".to_string(), length, send: None, recv: None, packets: vec![], path: None, shared: false },
+            ],
+        };
+        assert_eq!(snapshot.restore(&mut app.world).err(), Some(SnapshotError::Connection(error)));
+    }
+}
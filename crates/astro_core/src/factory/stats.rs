@@ -0,0 +1,174 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use bevy::prelude::{Plugin, ResMut, ParallelSystemDescriptorCoercion};
+
+use super::{FactoryStageInternal, FactorySystem, ResourceFlow, ResourceID};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsWindow {
+    Seconds10,
+    Minute,
+    Hour,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FlowTotal {
+    pub produced: u64,
+    pub consumed: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FlowRate {
+    pub produced: f32,
+    pub consumed: f32,
+}
+
+/// Rolling production and consumption totals per resource.
+pub struct FactoryStats {
+    ticks_per_second: u32,
+    windows:          [RollingWindow; 3],
+}
+
+impl Default for FactoryStats {
+    fn default() -> Self {
+        Self::new(60)
+    }
+}
+
+impl FactoryStats {
+
+    pub fn new(ticks_per_second: u32) -> Self {
+        Self{
+            ticks_per_second,
+            windows: [
+                RollingWindow::new(ticks_per_second,    10),
+                RollingWindow::new(ticks_per_second,    60),
+                RollingWindow::new(ticks_per_second*60, 60),
+            ]
+        }
+    }
+
+    pub fn ticks_per_second(&self) -> u32 {
+        self.ticks_per_second
+    }
+
+    /// Accumulates a single tick of flow.
+    pub fn push(&mut self, flow: &ResourceFlow) {
+        self.windows.iter_mut().for_each(|w| w.push(flow));
+    }
+
+    pub fn total(&self, resource: ResourceID, window: StatsWindow) -> FlowTotal {
+        self.window(window).total(resource)
+    }
+
+    /// Average flow per second over the window, or over the ticks recorded
+    /// so far if the window hasn't filled yet.
+    pub fn rate(&self, resource: ResourceID, window: StatsWindow) -> FlowRate {
+        let window = self.window(window);
+        let ticks  = window.covered_ticks();
+        if ticks == 0 { return FlowRate::default(); }
+
+        let total   = window.total(resource);
+        let seconds = ticks as f32 / self.ticks_per_second as f32;
+        FlowRate{
+            produced: total.produced as f32 / seconds,
+            consumed: total.consumed as f32 / seconds,
+        }
+    }
+
+    pub fn rate_per_minute(&self, resource: ResourceID, window: StatsWindow) -> FlowRate {
+        let FlowRate{ produced, consumed } = self.rate(resource, window);
+        FlowRate{ produced: produced*60.0, consumed: consumed*60.0 }
+    }
+
+    /// Iterates resources with any flow in the window.
+    pub fn iter(&self, window: StatsWindow) -> impl Iterator<Item = (ResourceID, FlowTotal)> + '_ {
+        self.window(window).totals.iter().enumerate().filter_map(|(i, &total)| {
+            if total == FlowTotal::default() { return None; }
+            Some((ResourceID::try_from_inner(i as u16)?, total))
+        })
+    }
+
+    fn window(&self, window: StatsWindow) -> &RollingWindow {
+        &self.windows[window as usize]
+    }
+
+}
+
+struct RollingWindow {
+    bucket_ticks: u32,
+    buckets:      Box<[Vec<FlowTotal>]>,
+    head:         usize,
+    head_ticks:   u32,
+    complete:     usize,
+    totals:       Vec<FlowTotal>,
+}
+
+impl RollingWindow {
+
+    fn new(bucket_ticks: u32, bucket_count: usize) -> Self {
+        Self{
+            bucket_ticks,
+            buckets:    vec![Vec::new(); bucket_count + 1].into_boxed_slice(),
+            head:       0,
+            head_ticks: 0,
+            complete:   0,
+            totals:     Vec::new(),
+        }
+    }
+
+    fn push(&mut self, flow: &ResourceFlow) {
+        for (resource, produced, consumed) in flow.iter() {
+            let idx = resource.into_inner() as usize;
+            for counts in [&mut self.buckets[self.head], &mut self.totals] {
+                if idx >= counts.len() { counts.resize(idx + 1, FlowTotal::default()); }
+                counts[idx].produced += produced as u64;
+                counts[idx].consumed += consumed as u64;
+            }
+        }
+
+        self.head_ticks += 1;
+        if self.head_ticks < self.bucket_ticks { return; }
+
+        self.head       = (self.head + 1) % self.buckets.len();
+        self.head_ticks = 0;
+        self.complete   = (self.complete + 1).min(self.buckets.len() - 1);
+
+        let expired = &mut self.buckets[self.head];
+        for (total, expired) in self.totals.iter_mut().zip(expired.iter_mut()) {
+            total.produced -= expired.produced;
+            total.consumed -= expired.consumed;
+            *expired = FlowTotal::default();
+        }
+    }
+
+    fn total(&self, resource: ResourceID) -> FlowTotal {
+        self.totals.get(resource.into_inner() as usize).copied().unwrap_or_default()
+    }
+
+    fn covered_ticks(&self) -> u32 {
+        self.complete as u32 * self.bucket_ticks + self.head_ticks
+    }
+
+}
+
+pub fn update_factory_stats(
+    mut flow:  ResMut<ResourceFlow>,
+    mut stats: ResMut<FactoryStats>,
+) {
+    stats.push(&flow);
+    flow.clear();
+}
+
+pub struct FactoryStatsPlugin;
+
+impl Plugin for FactoryStatsPlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.init_resource::<FactoryStats>();
+        app.schedule.add_system_to_stage(FactoryStageInternal::Tick, update_factory_stats.before(FactorySystem::UpdateTick));
+    }
+}
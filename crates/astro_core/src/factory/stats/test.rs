@@ -0,0 +1,77 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use bevy::prelude::App;
+
+use super::*;
+use crate::factory::{ConnectionBuilder, FactoryPlugins, PipeSimple, PortID, ResourceUUID, MACHINE_SOURCE, MACHINE_SINK, MACHINE_PASSTHROUGH, spawn_machine};
+
+fn resource(id: u16) -> ResourceID {
+    ResourceID::try_from_inner(id).unwrap()
+}
+
+fn push_ticks(stats: &mut FactoryStats, ticks: u32, produced: u32, consumed: u32) {
+    let mut flow = ResourceFlow::default();
+    for _ in 0..ticks {
+        flow.record_produced(resource(1), produced);
+        flow.record_consumed(resource(1), consumed);
+        stats.push(&flow);
+        flow.clear();
+    }
+}
+
+#[test]
+fn empty() {
+    let stats = FactoryStats::new(10);
+    assert_eq!(stats.total(resource(1), StatsWindow::Minute), FlowTotal::default());
+    assert_eq!(stats.rate(resource(1), StatsWindow::Minute),  FlowRate::default());
+    assert_eq!(stats.iter(StatsWindow::Hour).count(), 0);
+}
+
+#[test]
+fn partial_window() {
+    let mut stats = FactoryStats::new(10);
+    push_ticks(&mut stats, 25, 2, 1);
+
+    assert_eq!(stats.total(resource(1), StatsWindow::Seconds10), FlowTotal{ produced: 50, consumed: 25 });
+    assert_eq!(stats.rate(resource(1), StatsWindow::Seconds10),  FlowRate{ produced: 20.0, consumed: 10.0 });
+    assert_eq!(stats.rate_per_minute(resource(1), StatsWindow::Hour), FlowRate{ produced: 1200.0, consumed: 600.0 });
+    assert_eq!(stats.total(resource(2), StatsWindow::Seconds10), FlowTotal::default());
+}
+
+#[test]
+fn window_expiry() {
+    let mut stats = FactoryStats::new(10);
+    push_ticks(&mut stats, 200, 1, 0);
+    push_ticks(&mut stats, 200, 0, 0);
+
+    // 10s window holds between 10s and 11s of history, all of it idle.
+    assert_eq!(stats.total(resource(1), StatsWindow::Seconds10), FlowTotal::default());
+    assert_eq!(stats.total(resource(1), StatsWindow::Minute),     FlowTotal{ produced: 200, consumed: 0 });
+    assert_eq!(stats.iter(StatsWindow::Seconds10).count(), 0);
+    assert_eq!(stats.iter(StatsWindow::Minute).collect::<Vec<_>>(), vec![(resource(1), FlowTotal{ produced: 200, consumed: 0 })]);
+}
+
+/// Items passing through connections and machines along the way are counted
+/// once, where they're made and where they're destroyed.
+#[test]
+fn chain_counts_once() {
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+
+    let speed  = ResourceID::intern(ResourceUUID::new("SPEED"));
+    let source = spawn_machine(&mut app.world, MACHINE_SOURCE, Some(speed)).unwrap();
+    let middle = spawn_machine(&mut app.world, MACHINE_PASSTHROUGH, None).unwrap();
+    let sink   = spawn_machine(&mut app.world, MACHINE_SINK, None).unwrap();
+    for (from, to) in [(source, middle), (middle, sink)] {
+        ConnectionBuilder::new(2).recv_from(from, PortID::B).send_to(to, PortID::A).build::<PipeSimple>(&mut app.world).unwrap();
+    }
+
+    for _ in 0..20 { app.update(); }
+    for _ in 0..10 {
+        app.update();
+        let flow = app.world.get_resource::<ResourceFlow>().unwrap();
+        assert_eq!((flow.produced(speed), flow.consumed(speed)), (1, 1));
+    }
+}
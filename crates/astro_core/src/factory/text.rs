@@ -0,0 +1,160 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use std::{collections::VecDeque, fmt::Write};
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
+
+use super::{ConnectionIndex, Dormant, FactoryTick, FlowMonitor, Machine, PortID, PortRecv, PortSend, Ports, ResourceID, capture_connection};
+
+/// Machines drawn by a `TextView` made with `new`.
+pub const TEXT_VIEW_LIMIT: usize = 32;
+
+/// Draws the machines and connections reachable from `roots` as text, one
+/// line each, with connections listed under the machine they feed:
+///
+/// ```text
+/// tick 2
+/// #1 SINK A:- B:- C:- D:- InputStarved asleep
+///   #2 #0.B -[SS.]-> #1.A Running
+/// #0 SOURCE A:- B:- C:- D:- Running
+/// ```
+///
+/// Ports show the first letter of their resource and its count, pipes show
+/// a slot per tick of travel with packets entering on the left.
+///
+/// Inserted as a resource, the `FactoryTextPlugin` prints it every tick.
+#[derive(Debug, Clone)]
+pub struct TextView {
+    pub roots: Vec<Entity>,
+    /// Machines drawn at most, the walk stops once it's reached.
+    pub limit: usize,
+    /// Clear the terminal before each print.
+    pub clear: bool,
+}
+
+impl TextView {
+
+    pub fn new(roots: Vec<Entity>) -> Self {
+        Self{ roots, limit: TEXT_VIEW_LIMIT, clear: false }
+    }
+
+    /// Machines and connections reachable from the roots, breadth first.
+    pub fn collect(&self, world: &World) -> (Vec<Entity>, Vec<Entity>) {
+        let index = world.get_resource::<ConnectionIndex>();
+        let (mut machines, mut connections) = (Vec::new(), Vec::new());
+        let mut seen  = HashSet::default();
+        let mut queue = self.roots.iter().copied().collect::<VecDeque<_>>();
+
+        while let Some(entity) = queue.pop_front() {
+            if seen.contains(&entity) { continue; }
+            if world.get::<Ports>(entity).is_some() {
+                if machines.len() >= self.limit { continue; }
+                seen.insert(entity);
+                machines.push(entity);
+                if let Some(index) = index { queue.extend(index.attached(entity)); }
+            } else if let Some((send, recv)) = connection_ends(world, entity) {
+                seen.insert(entity);
+                connections.push(entity);
+                queue.extend(send.map(|PortSend(target, _)| target));
+                queue.extend(recv.map(|PortRecv(target, _)| target));
+            }
+        }
+        (machines, connections)
+    }
+
+    pub fn render(&self, world: &World) -> String {
+        let tick = world.get_resource::<FactoryTick>().map_or(0, |v| v.0);
+        let (machines, connections) = self.collect(world);
+
+        // Connections go under the machine they feed, or the one feeding
+        // them if that isn't drawn, and after every machine if neither is.
+        let drawn      = machines.iter().copied().collect::<HashSet<_>>();
+        let mut fed_by = HashMap::<Entity, Vec<Entity>>::default();
+        let mut loose  = Vec::new();
+        for connection in connections {
+            let (send, recv) = connection_ends(world, connection).unwrap_or_default();
+            let owner = send.map(|PortSend(target, _)| target).filter(|v| drawn.contains(v))
+                .or_else(|| recv.map(|PortRecv(target, _)| target).filter(|v| drawn.contains(v)));
+            match owner {
+                Some(machine) => fed_by.entry(machine).or_default().push(connection),
+                None          => loose.push(connection),
+            }
+        }
+
+        let mut text = String::new();
+        writeln!(text, "tick {}", tick).unwrap();
+        for machine in machines.iter() {
+            write_machine(&mut text, world, *machine);
+            for connection in fed_by.get(machine).into_iter().flatten() {
+                text.push_str("  ");
+                write_connection(&mut text, world, tick, *connection);
+            }
+        }
+        for connection in loose {
+            write_connection(&mut text, world, tick, connection);
+        }
+        text
+    }
+
+}
+
+/// Character drawn for a resource.
+pub fn resource_glyph(resource: ResourceID) -> char {
+    resource.uuid().and_then(|v| v.to_string().chars().next()).unwrap_or('?')
+}
+
+fn connection_ends(world: &World, entity: Entity) -> Option<(Option<PortSend>, Option<PortRecv>)> {
+    let send = world.get::<PortSend>(entity).copied();
+    let recv = world.get::<PortRecv>(entity).copied();
+    if send.is_none() && recv.is_none() { None } else { Some((send, recv)) }
+}
+
+fn write_machine(text: &mut String, world: &World, entity: Entity) {
+    let kind = world.get::<Machine>(entity).map_or_else(|| "machine".to_string(), |v| v.kind.to_string());
+    write!(text, "#{} {}", entity.id(), kind).unwrap();
+    if let Some(ports) = world.get::<Ports>(entity) {
+        for port in PortID::ALL {
+            match ports.get(port).get() {
+                Some((resource, count)) => write!(text, " {:?}:{}{}", port, resource_glyph(resource), count),
+                None                    => write!(text, " {:?}:-", port),
+            }.unwrap();
+        }
+    }
+    write_status(text, world, entity);
+}
+
+fn write_connection(text: &mut String, world: &World, tick: u32, entity: Entity) {
+    let (send, recv) = connection_ends(world, entity).unwrap_or_default();
+    let slots = capture_connection(world, entity).map_or_else(|| "?".to_string(), |(_, v)| {
+        v.resolve(tick).iter().map(|v| v.map_or('.', resource_glyph)).collect()
+    });
+    let from = recv.map_or_else(|| "-".to_string(), |PortRecv(target, port)| format!("#{}.{:?}", target.id(), port));
+    let to   = send.map_or_else(|| "-".to_string(), |PortSend(target, port)| format!("#{}.{:?}", target.id(), port));
+    write!(text, "#{} {} -[{}]-> {}", entity.id(), from, slots, to).unwrap();
+    write_status(text, world, entity);
+}
+
+fn write_status(text: &mut String, world: &World, entity: Entity) {
+    if let Some(monitor) = world.get::<FlowMonitor>(entity) { write!(text, " {:?}", monitor.status()).unwrap(); }
+    if world.get::<Dormant>(entity).is_some() { text.push_str(" asleep"); }
+    text.push('\n');
+}
+
+pub struct FactoryTextPlugin;
+
+impl Plugin for FactoryTextPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_to_stage(CoreStage::Last, print_text_view.exclusive_system().at_end());
+    }
+}
+
+/// Prints the `TextView` resource, if there is one.
+pub fn print_text_view(world: &mut World) {
+    let view = if let Some(v) = world.get_resource::<TextView>() { v } else { return; };
+    let text = view.render(world);
+    if view.clear { print!("\x1b[2J\x1b[H"); }
+    print!("{}", text);
+}
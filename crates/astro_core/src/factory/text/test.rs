@@ -0,0 +1,45 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+use crate::factory::{ConnectionBuilder, FactoryCommand, FactoryPlugins, PipeSimple, ResourceUUID, MACHINE_SOURCE, MACHINE_SINK, execute_command};
+
+fn build_app(pooled: bool) -> (App, Entity, Entity, Entity) {
+    let speed = ResourceID::intern(ResourceUUID::new("SPEED"));
+    let mut app = App::new();
+    app.add_plugins(FactoryPlugins);
+
+    let source  = execute_command(&mut app.world, FactoryCommand::PlaceMachine{ kind: MACHINE_SOURCE, recipe: Some(speed) }).unwrap().unwrap();
+    let sink    = execute_command(&mut app.world, FactoryCommand::PlaceMachine{ kind: MACHINE_SINK,   recipe: None }).unwrap().unwrap();
+    let builder = ConnectionBuilder::new(3).recv_from(source, PortID::B).send_to(sink, PortID::A);
+    let pipe    = if pooled { builder.build_pooled(&mut app.world) } else { builder.build::<PipeSimple>(&mut app.world) };
+    (app, source, sink, pipe.unwrap())
+}
+
+#[test]
+fn draws_subgraph() {
+    for pooled in [false, true] {
+        let (mut app, source, sink, pipe) = build_app(pooled);
+        app.update();
+        app.update();
+
+        let text  = TextView::new(vec![sink]).render(&app.world);
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "tick 2");
+        assert!(lines[1].starts_with(&format!("#{} SINK A:- B:- C:- D:-", sink.id())));
+        assert_eq!(lines[2], format!("  #{} #{}.B -[SS.]-> #{}.A Running", pipe.id(), source.id(), sink.id()));
+        assert_eq!(lines[3], format!("#{} SOURCE A:- B:- C:- D:- Running", source.id()));
+    }
+}
+
+#[test]
+fn stops_at_limit() {
+    let (mut app, _, sink, pipe) = build_app(false);
+    app.update();
+
+    let view = TextView{ limit: 1, ..TextView::new(vec![sink]) };
+    assert_eq!(view.collect(&app.world), (vec![sink], vec![pipe]));
+    assert_eq!(view.render(&app.world).lines().count(), 3);
+}
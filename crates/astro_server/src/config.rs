@@ -0,0 +1,62 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+usage: astro_server <world-file> [--tps <ticks>] [--autosave <ticks>] [--listen <addr>]
+       astro_server --replay <replay-file>";
+
+/// Fastest tick rate accepted, tick counts derived from it stay well within `u32`.
+pub const MAX_TICKS_PER_SECOND: u32 = 1000;
+
+pub struct ServerConfig {
+    pub world_path:       PathBuf,
+    pub ticks_per_second: u32,
+    /// Ticks between autosaves, 0 disables autosaving.
+    pub autosave_ticks:   u32,
+    /// Address to accept replication clients on.
+    pub listen:           Option<String>,
+}
+
+impl ServerConfig {
+
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut world_path       = None;
+        let mut ticks_per_second = 60;
+        let mut autosave_ticks   = None;
+        let mut listen           = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--tps"      => ticks_per_second = parse_value(&arg, args.next())?,
+                "--autosave" => autosave_ticks   = Some(parse_value(&arg, args.next())?),
+                "--listen"   => listen           = Some(args.next().ok_or_else(|| format!("Missing value for {}", arg))?),
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ if world_path.is_none()  => world_path = Some(PathBuf::from(arg)),
+                _ => return Err(format!("Unexpected argument {}", arg)),
+            }
+        }
+
+        if ticks_per_second == 0 { return Err("--tps must be greater than 0".to_string()); }
+        if ticks_per_second > MAX_TICKS_PER_SECOND { return Err(format!("--tps must be at most {}", MAX_TICKS_PER_SECOND)); }
+
+        Ok(Self{
+            world_path: world_path.ok_or_else(|| "Missing world file".to_string())?,
+            ticks_per_second,
+            autosave_ticks: autosave_ticks.unwrap_or(ticks_per_second.saturating_mul(60*5)),
+            listen,
+        })
+    }
+
+}
+
+fn parse_value(arg: &str, value: Option<String>) -> Result<u32, String> {
+    value
+        .ok_or_else(|| format!("Missing value for {}", arg))?
+        .parse()
+        .map_err(|_| format!("Invalid value for {}", arg))
+}
@@ -0,0 +1,21 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+
+fn parse(args: &[&str]) -> Result<ServerConfig, String> {
+    ServerConfig::from_args(args.iter().map(|v| v.to_string()))
+}
+
+#[test]
+fn tick_rate() {
+    let config = parse(&["world.astro", "--tps", "20"]).unwrap();
+    assert_eq!((config.ticks_per_second, config.autosave_ticks), (20, 20*60*5));
+
+    let config = parse(&["world.astro", "--tps", &MAX_TICKS_PER_SECOND.to_string()]).unwrap();
+    assert_eq!(config.autosave_ticks, MAX_TICKS_PER_SECOND*60*5);
+
+    assert!(parse(&["world.astro", "--tps", "0"]).is_err());
+    assert!(parse(&["world.astro", "--tps", &u32::MAX.to_string()]).is_err());
+}
@@ -0,0 +1,277 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+#[cfg(test)] mod test;
+
+use std::{io::BufRead, path::PathBuf, sync::{Mutex, mpsc::{Receiver, channel}}};
+use bevy::{prelude::*, app::AppExit, ecs::event::Events, utils::HashMap};
+
+use astro::factory::{
+    Blueprint, CommandId, DetachedConnection, Dormant, FactoryCommand, FactoryCommandQueue, FactoryCommandResult, FactoryHistory, FactoryStats, FactoryTick, FlowMonitor, MachineUUID,
+    PortID, PortRecv, PortSend, Ports, ReplayRecorder, ResourceID, ResourceUUID, StatsWindow, TextView, inspect, parse_tile, worst_bottlenecks
+};
+
+use crate::{config::ServerConfig, persist::save_world};
+
+const HELP: &str = "\
+commands:
+  help                                    show this message
+  status                                  show tick and entity counts
+  stats                                   show production per minute over the last minute
+  bottlenecks [count]                     list the most stalled machines and connections
+  inspect <id>                            show a machine's ports or a pipe's packets
+  spawn <kind> [recipe]                   spawn a machine, printing its id
+  connect <from> <port> <to> <port> <len> connect two machines with a pipe
+  disconnect <id> <port>                  remove every connection attached to a port
+  filter <id> <port> <resource|->         only accept a resource into a port
+  recipe <id> <recipe|->                  change a machine's recipe
+  despawn <id>                            despawn a machine or connection
+  undo                                    revert the last build command
+  redo                                    reapply the last undone build command
+  blueprint copy <id...>                  print a blueprint of machines and the pipes between them
+  blueprint paste <blueprint> [x,y]       build a copy of a blueprint, moving placed machines by x,y
+  record start [interval]                 start recording a replay, checksumming every interval ticks
+  record save <path>                      stop recording and save the replay
+  watch <id...>                           redraw everything connected to the given entities each tick
+  watch off                               stop redrawing
+  save [path]                             save the world
+  quit                                    save the world and exit";
+
+/// Lines read from stdin by a background thread.
+pub struct Console(Mutex<Receiver<String>>);
+
+/// Queued build commands, with what to print for the entity they spawn.
+#[derive(Default)]
+pub struct ConsoleRequests(HashMap<CommandId, &'static str>);
+
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                match line {
+                    Ok(line) if sender.send(line).is_ok() => {},
+                    _ => break,
+                }
+            }
+        });
+
+        app
+            .insert_resource(Console(Mutex::new(receiver)))
+            .init_resource::<ConsoleRequests>()
+            .init_resource::<FactoryHistory>()
+            .add_system_to_stage(CoreStage::First, process_console.exclusive_system())
+            .add_system_to_stage(CoreStage::Last, report_command_results);
+    }
+}
+
+pub fn process_console(world: &mut World) {
+    let lines: Vec<String> = world.get_resource::<Console>().unwrap().0.lock().unwrap().try_iter().collect();
+    for line in lines {
+        if let Err(e) = run_command(world, &line) {
+            println!("error: {}", e);
+        }
+    }
+}
+
+pub fn report_command_results(mut requests: ResMut<ConsoleRequests>, mut results: EventReader<FactoryCommandResult>) {
+    for applied in results.iter() {
+        let spawned = match requests.0.remove(&applied.id) {
+            Some(v) => v,
+            None    => continue,
+        };
+        match applied.result {
+            Ok(Some(entity)) => println!("{} {}", spawned, entity.id()),
+            Ok(None)         => {},
+            Err(e)           => println!("error: {}", e),
+        }
+    }
+}
+
+fn run_command(world: &mut World, line: &str) -> Result<(), String> {
+    let args: Vec<&str> = line.split_whitespace().collect();
+    match args.as_slice() {
+        []                 => Ok(()),
+        ["help"]           => { println!("{}", HELP); Ok(()) },
+        ["status"]         => status(world),
+        ["stats"]          => stats(world),
+        ["bottlenecks"]    => bottlenecks(world, 10),
+        ["bottlenecks", n] => bottlenecks(world, n.parse().map_err(|_| "Invalid count")?),
+        ["inspect", id]    => inspect_entity(world, id),
+        ["spawn", kind]    => spawn(world, kind, None),
+        ["spawn", kind, r] => spawn(world, kind, Some(r)),
+        ["connect", from, from_port, to, to_port, length] => connect(world, from, from_port, to, to_port, length),
+        ["disconnect", id, port]      => disconnect(world, id, port),
+        ["filter", id, port, r]       => filter(world, id, port, r),
+        ["recipe", id, r]  => recipe(world, id, r),
+        ["despawn", id]    => despawn(world, id),
+        ["undo"]           => { queue(world, FactoryCommand::Undo, ""); Ok(()) },
+        ["redo"]           => { queue(world, FactoryCommand::Redo, ""); Ok(()) },
+        ["blueprint", "copy", ids @ ..] if !ids.is_empty() => blueprint_copy(world, ids),
+        ["blueprint", "paste", text]  => blueprint_paste(world, text, "0,0"),
+        ["blueprint", "paste", text, offset] => blueprint_paste(world, text, offset),
+        ["record", "start"]           => record_start(world, 60),
+        ["record", "start", interval] => record_start(world, interval.parse().map_err(|_| "Invalid interval")?),
+        ["record", "save", path]      => record_save(world, path),
+        ["watch", "off"]   => { world.remove_resource::<TextView>(); Ok(()) },
+        ["watch", ids @ ..] if !ids.is_empty() => watch(world, ids),
+        ["save"]           => save(world, None),
+        ["save", path]     => save(world, Some(PathBuf::from(path))),
+        ["quit" | "exit"]  => quit(world),
+        _ => Err("Unknown command, try help".to_string()),
+    }
+}
+
+fn status(world: &mut World) -> Result<(), String> {
+    let tick        = world.get_resource::<FactoryTick>().unwrap().0;
+    let machines    = world.query_filtered::<(), With<Ports>>().iter(world).count();
+    let connections = world.query_filtered::<(), Or<(With<PortSend>, With<PortRecv>, With<DetachedConnection>)>>().iter(world).count();
+    println!("tick {} | {} machines | {} connections", tick, machines, connections);
+    Ok(())
+}
+
+fn stats(world: &mut World) -> Result<(), String> {
+    let stats = world.get_resource::<FactoryStats>().unwrap();
+    for (resource, _) in stats.iter(StatsWindow::Minute) {
+        let rate = stats.rate_per_minute(resource, StatsWindow::Minute);
+        let name = resource.uuid().map_or_else(|| format!("{:?}", resource), |v| v.to_string());
+        println!("{: <24} {: >10.1}/min produced {: >10.1}/min consumed", name, rate.produced, rate.consumed);
+    }
+    Ok(())
+}
+
+fn bottlenecks(world: &mut World, count: usize) -> Result<(), String> {
+    let tick      = world.get_resource::<FactoryTick>().unwrap().0;
+    let mut query = world.query::<(Entity, &FlowMonitor, Option<&Dormant>)>();
+    let monitors  = query.iter(world).map(|(entity, monitor, dormant)| (entity, dormant.map_or_else(|| monitor.clone(), |v| v.caught_up(monitor, tick))));
+    for bottleneck in worst_bottlenecks(monitors, count) {
+        println!("{: >8} {:?} stalled {} ticks ({} consecutive)", bottleneck.entity.id(), bottleneck.status, bottleneck.stalled_ticks, bottleneck.consecutive_ticks);
+    }
+    Ok(())
+}
+
+fn inspect_entity(world: &mut World, id: &str) -> Result<(), String> {
+    let entity = find_entity(world, id)?;
+    let inspection = inspect(world, entity).ok_or_else(|| format!("{} isn't a machine or connection", id))?;
+    println!("{}", inspection);
+    Ok(())
+}
+
+fn spawn(world: &mut World, kind: &str, recipe: Option<&str>) -> Result<(), String> {
+    let kind   = MachineUUID::try_new(kind)?;
+    let recipe = recipe.map(ResourceUUID::try_new).transpose()?.map(ResourceID::intern);
+    queue(world, FactoryCommand::PlaceMachine{ kind, recipe }, "spawned");
+    Ok(())
+}
+
+fn connect(world: &mut World, from: &str, from_port: &str, to: &str, to_port: &str, length: &str) -> Result<(), String> {
+    let from   = find_entity(world, from)?;
+    let to     = find_entity(world, to)?;
+    let length = length.parse().map_err(|_| "Invalid length")?;
+    queue(world, FactoryCommand::Connect{
+        from,
+        from_port: from_port.parse::<PortID>()?,
+        to,
+        to_port:   to_port.parse::<PortID>()?,
+        length,
+    }, "connected");
+    Ok(())
+}
+
+fn disconnect(world: &mut World, id: &str, port: &str) -> Result<(), String> {
+    let machine = find_entity(world, id)?;
+    queue(world, FactoryCommand::Disconnect{ machine, port: port.parse()? }, "");
+    Ok(())
+}
+
+fn filter(world: &mut World, id: &str, port: &str, resource: &str) -> Result<(), String> {
+    let machine = find_entity(world, id)?;
+    queue(world, FactoryCommand::ConfigurePortFilter{ machine, port: port.parse()?, filter: parse_resource(resource)? }, "");
+    Ok(())
+}
+
+fn recipe(world: &mut World, id: &str, recipe: &str) -> Result<(), String> {
+    let machine = find_entity(world, id)?;
+    queue(world, FactoryCommand::SetRecipe{ machine, recipe: parse_resource(recipe)? }, "");
+    Ok(())
+}
+
+fn despawn(world: &mut World, id: &str) -> Result<(), String> {
+    let entity = find_entity(world, id)?;
+    queue(world, FactoryCommand::Remove{ entity }, "");
+    Ok(())
+}
+
+fn blueprint_copy(world: &mut World, ids: &[&str]) -> Result<(), String> {
+    let machines  = ids.iter().map(|id| find_entity(world, id)).collect::<Result<Vec<_>, _>>()?;
+    let blueprint = Blueprint::capture(world, &machines).map_err(|e| e.to_string())?;
+    println!("{}", blueprint);
+    Ok(())
+}
+
+fn blueprint_paste(world: &mut World, text: &str, offset: &str) -> Result<(), String> {
+    let blueprint = text.parse::<Blueprint>().map_err(|e| e.to_string())?;
+    let offset    = parse_tile(offset).ok_or("Invalid offset")?;
+    queue(world, FactoryCommand::Paste{ blueprint, offset }, "pasted");
+    Ok(())
+}
+
+fn record_start(world: &mut World, interval: u32) -> Result<(), String> {
+    if world.contains_resource::<ReplayRecorder>() { return Err("Already recording".to_string()); }
+    let recorder = ReplayRecorder::start(world, interval).map_err(|e| e.to_string())?;
+    world.insert_resource(recorder);
+    println!("recording");
+    Ok(())
+}
+
+fn record_save(world: &mut World, path: &str) -> Result<(), String> {
+    let recorder = world.remove_resource::<ReplayRecorder>().ok_or("Not recording")?;
+    let replay   = recorder.finish().map_err(|e| e.to_string())?;
+    std::fs::write(path, replay.to_string()).map_err(|e| e.to_string())?;
+    println!("saved {} commands to {}", replay.commands.len(), path);
+    Ok(())
+}
+
+fn watch(world: &mut World, ids: &[&str]) -> Result<(), String> {
+    let roots = ids.iter().map(|id| find_entity(world, id)).collect::<Result<Vec<_>, _>>()?;
+    world.insert_resource(TextView{ clear: true, ..TextView::new(roots) });
+    Ok(())
+}
+
+/// Queues a command for the next tick, its result is printed once applied
+/// with `spawned` naming the entity it spawns.
+fn queue(world: &mut World, command: FactoryCommand, spawned: &'static str) {
+    let id = world.get_resource_mut::<FactoryCommandQueue>().unwrap().push(command);
+    world.get_resource_mut::<ConsoleRequests>().unwrap().0.insert(id, spawned);
+}
+
+fn parse_resource(value: &str) -> Result<Option<ResourceID>, String> {
+    match value {
+        "-" => Ok(None),
+        v   => Ok(Some(ResourceID::intern(ResourceUUID::try_new(v)?))),
+    }
+}
+
+fn save(world: &mut World, path: Option<PathBuf>) -> Result<(), String> {
+    let path = path.unwrap_or_else(|| world.get_resource::<ServerConfig>().unwrap().world_path.clone());
+    save_world(world, &path)?;
+    println!("saved to {}", path.display());
+    Ok(())
+}
+
+fn quit(world: &mut World) -> Result<(), String> {
+    save(world, None)?;
+    world.get_resource_mut::<Events<AppExit>>().unwrap().send(AppExit);
+    Ok(())
+}
+
+/// Finds a live entity by the id printed by other commands.
+fn find_entity(world: &World, id: &str) -> Result<Entity, String> {
+    let id: u32 = id.parse().map_err(|_| format!("Invalid id {}", id))?;
+    world.entities().resolve_from_id(id)
+        .filter(|&e| world.get_entity(e).is_some())
+        .ok_or_else(|| format!("No entity {}", id))
+}
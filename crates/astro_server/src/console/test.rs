@@ -0,0 +1,55 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use super::*;
+use astro::factory::{FactoryPlugins, Machine, PortSend, MACHINE_SOURCE, MACHINE_SINK};
+
+fn build_app() -> App {
+    let mut app = App::new();
+    app
+        .add_plugins(FactoryPlugins)
+        .init_resource::<ConsoleRequests>()
+        .init_resource::<FactoryHistory>();
+    app
+}
+
+fn machine(app: &mut App, kind: MachineUUID) -> Entity {
+    app.world.query::<(Entity, &Machine)>().iter(&app.world).find(|(_, v)| v.kind == kind).unwrap().0
+}
+
+#[test]
+fn find_entities() {
+    let mut app = build_app();
+    let entity  = app.world.spawn().id();
+    assert_eq!(find_entity(&app.world, &entity.id().to_string()), Ok(entity));
+    assert!(find_entity(&app.world, "x").is_err());
+
+    app.world.despawn(entity);
+    assert!(find_entity(&app.world, &entity.id().to_string()).is_err());
+    assert!(find_entity(&app.world, &(entity.id() + 1000).to_string()).is_err());
+
+    // A reused id finds the new entity rather than the despawned one.
+    let reused = app.world.spawn().id();
+    assert_eq!(reused.id(), entity.id());
+    assert_eq!(find_entity(&app.world, &entity.id().to_string()), Ok(reused));
+}
+
+#[test]
+fn commands_are_queued() {
+    let mut app = build_app();
+    run_command(&mut app.world, "spawn SOURCE SPEED").unwrap();
+    run_command(&mut app.world, "spawn SINK").unwrap();
+    assert_eq!(app.world.get_resource::<ConsoleRequests>().unwrap().0.len(), 2);
+    app.update();
+
+    let source = machine(&mut app, MACHINE_SOURCE).id();
+    let sink   = machine(&mut app, MACHINE_SINK).id();
+    run_command(&mut app.world, &format!("connect {} B {} A 8", source, sink)).unwrap();
+    app.update();
+    assert_eq!(app.world.query::<&PortSend>().iter(&app.world).count(), 1);
+
+    assert!(run_command(&mut app.world, "connect 9999 B 9998 A 8").is_err());
+    assert!(run_command(&mut app.world, "bogus").is_err());
+    assert!(run_command(&mut app.world, "spawn not-a-kind").is_err());
+}
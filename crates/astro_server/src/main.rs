@@ -0,0 +1,63 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+mod config;
+mod console;
+mod persist;
+mod replay;
+
+use std::{path::Path, time::Duration};
+use bevy::{prelude::*, MinimalPlugins, app::ScheduleRunnerSettings};
+
+use astro::factory::{FactoryPlugins, FactoryStats, FactoryTextPlugin, ReplayPlugin, net::{ReplicationServer, ReplicationServerPlugin}};
+
+use config::ServerConfig;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [flag, path] = args.as_slice() {
+        if flag == "--replay" {
+            if let Err(e) = replay::verify(Path::new(path)) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+    }
+
+    let config = match ServerConfig::from_args(args.into_iter()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", config::USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    let replication = config.listen.as_ref().map(|addr| match ReplicationServer::bind(addr) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Failed to listen on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    });
+
+    let mut app = App::new();
+    app
+        .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(1.0/config.ticks_per_second as f64)))
+        .insert_resource(FactoryStats::new(config.ticks_per_second))
+        .insert_resource(config)
+        .add_plugins(MinimalPlugins)
+        .add_plugins(FactoryPlugins)
+        .add_plugin(ReplayPlugin)
+        .add_plugin(FactoryTextPlugin)
+        .add_plugin(persist::PersistPlugin)
+        .add_plugin(console::ConsolePlugin);
+
+    if let Some(server) = replication {
+        app.insert_resource(server).add_plugin(ReplicationServerPlugin);
+    }
+
+    app.run();
+}
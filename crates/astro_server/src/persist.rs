@@ -0,0 +1,63 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use std::path::Path;
+use bevy::prelude::*;
+
+use astro::factory::{FactorySnapshot, FactoryTick};
+
+use crate::config::ServerConfig;
+
+pub struct PersistPlugin;
+
+impl Plugin for PersistPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_startup_system(load_world.exclusive_system())
+            .add_system_to_stage(CoreStage::Last, autosave.exclusive_system());
+    }
+}
+
+pub fn load_world(world: &mut World) {
+    let path = world.get_resource::<ServerConfig>().unwrap().world_path.clone();
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            println!("{} not found, starting a new world", path.display());
+            return;
+        },
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    match text.parse::<FactorySnapshot>().and_then(|v| v.restore(world)) {
+        Ok(entities) => println!("Loaded {} machines and {} connections from {}", entities.machines.len(), entities.connections.len(), path.display()),
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+pub fn autosave(world: &mut World) {
+    let config = world.get_resource::<ServerConfig>().unwrap();
+    let (interval, path) = (config.autosave_ticks, config.world_path.clone());
+    let tick = world.get_resource::<FactoryTick>().unwrap().0;
+    if interval == 0 || tick == 0 || tick % interval != 0 { return; }
+
+    match save_world(world, &path) {
+        Ok(()) => println!("Autosaved tick {} to {}", tick, path.display()),
+        Err(e) => eprintln!("Autosave failed: {}", e),
+    }
+}
+
+/// Writes to a temporary file first so a crash mid-save can't corrupt the world.
+pub fn save_world(world: &mut World, path: &Path) -> Result<(), String> {
+    let snapshot = FactorySnapshot::capture(world).map_err(|e| e.to_string())?;
+    let path_tmp = path.with_extension("tmp");
+    std::fs::write(&path_tmp, snapshot.to_string()).map_err(|e| e.to_string())?;
+    std::fs::rename(&path_tmp, path).map_err(|e| e.to_string())
+}
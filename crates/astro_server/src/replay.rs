@@ -0,0 +1,19 @@
+/*=====================================================================*\
+** NotVeryMoe Astro | Copyright 2021 NotVeryMoe (projects@notvery.moe) **
+\*=====================================================================*/
+
+use std::path::Path;
+
+use astro::factory::{Replay, verify_replay};
+
+/// Replays a recording headlessly and checks it against its checksums.
+pub fn verify(path: &Path) -> Result<(), String> {
+    let text   = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let replay = text.parse::<Replay>().map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+    let ticks  = replay.end_tick() - replay.snapshot.tick;
+
+    match verify_replay(replay).map_err(|e| e.to_string())? {
+        None             => { println!("replay matches over {} ticks", ticks); Ok(()) },
+        Some(divergence) => Err(format!("replay diverged: {}", divergence)),
+    }
+}